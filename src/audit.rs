@@ -0,0 +1,307 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use crate::platform::{host_name, user_name};
+
+/// Hash used as the "previous" hash of the first record in a new log
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An append-only, hash-chained log of every executed command.
+///
+/// Each line is a tab-separated record:
+/// `prev_hash start end user host status program args... hash`, where `hash` is the
+/// SHA-256 of everything before it on the line. Because every record includes
+/// the hash of the one before it, editing or removing a record breaks the
+/// chain for all records after it (see [`verify`]).
+#[derive(Debug)]
+pub struct AuditLog {
+    file: File,
+    prev_hash: String,
+    user: String,
+    host: String,
+}
+
+impl AuditLog {
+    /// Opens (or creates) the log at `path`, continuing the hash chain from its
+    /// last record.
+    ///
+    /// # Errors
+    /// Will return an error if the file cannot be read or opened for appending,
+    /// or if the existing records fail verification
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let prev_hash = match File::open(path) {
+            Ok(existing) => verify(BufReader::new(existing))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => GENESIS_HASH.to_string(),
+            Err(e) => return Err(e).context("opening audit log"),
+        };
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("opening audit log")?;
+        Ok(Self {
+            file,
+            prev_hash,
+            user: user_name(),
+            host: host_name(),
+        })
+    }
+
+    /// Appends a record for a finished command. Failures to start a command
+    /// are recorded by passing `None` as the status.
+    ///
+    /// # Errors
+    /// Will return an error if the record cannot be written
+    pub fn record(
+        &mut self,
+        command: &[OsString],
+        start: SystemTime,
+        end: SystemTime,
+        status: Option<ExitStatus>,
+    ) -> anyhow::Result<()> {
+        let status = status.map_or_else(|| "spawn-failed".to_string(), |s| s.to_string());
+        let mut record = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.prev_hash,
+            timestamp(start),
+            timestamp(end),
+            escape(&self.user),
+            escape(&self.host),
+            escape(&status),
+        );
+        for arg in command {
+            record.push('\t');
+            record.push_str(&escape_bytes(arg));
+        }
+        let hash = hash(&record);
+        writeln!(self.file, "{record}\t{hash}").context("writing audit log")?;
+        self.file.flush()?;
+        self.prev_hash = hash;
+        Ok(())
+    }
+}
+
+/// A single command read back from an audit log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub start: f64,
+    pub end: f64,
+    pub user: String,
+    pub host: String,
+    pub status: String,
+    pub command: Vec<String>,
+}
+
+impl AuditRecord {
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.status == "exit status: 0"
+    }
+}
+
+/// Reads every record from an audit log, after verifying its hash chain.
+///
+/// # Errors
+/// Will return an error if the log cannot be read, fails verification, or
+/// contains malformed records
+pub fn read_records(path: &Path) -> anyhow::Result<Vec<AuditRecord>> {
+    let contents = std::fs::read_to_string(path)?;
+    verify(contents.as_bytes())?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(number, line)| {
+            parse_record(line)
+                .with_context(|| format!("malformed audit record on line {}", number + 1))
+        })
+        .collect()
+}
+
+fn parse_record(line: &str) -> Option<AuditRecord> {
+    let mut fields = line.split('\t').skip(1); // previous hash
+    let start = fields.next()?.parse().ok()?;
+    let end = fields.next()?.parse().ok()?;
+    let user = unescape(fields.next()?);
+    let host = unescape(fields.next()?);
+    let status = unescape(fields.next()?);
+    let mut command: Vec<String> = fields.map(unescape).collect();
+    command.pop()?; // the record's own hash
+    Some(AuditRecord {
+        start,
+        end,
+        user,
+        host,
+        status,
+        command,
+    })
+}
+
+/// Checks the hash chain of an audit log, returning the hash of its last
+/// record.
+///
+/// # Errors
+/// Will return an error naming the first line whose hash or chain link does
+/// not match
+fn verify<R: BufRead>(log: R) -> anyhow::Result<String> {
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (number, line) in log.lines().enumerate() {
+        let line = line?;
+        let (record, recorded_hash) = line
+            .rsplit_once('\t')
+            .with_context(|| format!("malformed audit record on line {}", number + 1))?;
+        if !record.starts_with(&prev_hash) || hash(record) != recorded_hash {
+            anyhow::bail!("audit log tampered with at line {}", number + 1);
+        }
+        prev_hash = recorded_hash.to_string();
+    }
+    Ok(prev_hash)
+}
+
+fn hash(record: &str) -> String {
+    Sha256::digest(record.as_bytes())
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Seconds since the Unix epoch, with millisecond precision
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{:.3}", since_epoch.as_secs_f64())
+}
+
+/// Keeps every record on one line with tab-separated fields
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+/// Like [`escape`], but also records bytes that aren't valid UTF-8 as `\xNN`
+fn escape_bytes(field: &OsStr) -> String {
+    field
+        .as_encoded_bytes()
+        .utf8_chunks()
+        .fold(String::new(), |mut escaped, chunk| {
+            escaped.push_str(&escape(chunk.valid()));
+            for byte in chunk.invalid() {
+                let _ = write!(escaped, "\\x{byte:02x}");
+            }
+            escaped
+        })
+}
+
+/// Reverses [`escape_bytes`]. Bytes that aren't valid UTF-8 are replaced with
+/// `U+FFFD`.
+fn unescape(field: &str) -> String {
+    let mut output = Vec::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('t') => output.push(b'\t'),
+            Some('n') => output.push(b'\n'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                output.push(u8::from_str_radix(&hex, 16).unwrap_or(b'?'));
+            }
+            Some(other) => output.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes()),
+            None => output.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::exit_status;
+
+    fn temp_log(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("arrgs-audit-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn write_records(path: &Path, count: usize) {
+        let mut log = AuditLog::open(path).unwrap();
+        for i in 0..count {
+            let now = SystemTime::now();
+            log.record(
+                &["echo".into(), i.to_string().into()],
+                now,
+                now,
+                Some(exit_status(0)),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn chain_continues_across_opens() {
+        let path = temp_log("continues");
+        write_records(&path, 2);
+        write_records(&path, 1);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 3);
+        verify(contents.as_bytes()).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let path = temp_log("round-trip");
+        let mut log = AuditLog::open(&path).unwrap();
+        let start = SystemTime::now();
+        let end = start + std::time::Duration::from_millis(1500);
+        let command = ["printf".into(), "a\tb\\c\n".into()];
+        log.record(&command, start, end, Some(exit_status(1)))
+            .unwrap();
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].command, ["printf", "a\tb\\c\n"]);
+        assert_eq!(records[0].status, "exit status: 1");
+        assert!(!records[0].succeeded());
+        assert!((records[0].duration() - 1.5).abs() < 0.01);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn invalid_utf8_is_escaped() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let arg = OsString::from_vec(b"foo\xFFbar".to_vec());
+        assert_eq!(escape_bytes(&arg), "foo\\xffbar");
+        assert_eq!(unescape(&escape_bytes(&arg)), "foo\u{FFFD}bar");
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let path = temp_log("tampered");
+        write_records(&path, 3);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let tampered = contents.replacen("\techo\t1\t", "\techo\t9\t", 1);
+        assert_ne!(contents, tampered);
+        let error = verify(tampered.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{error}");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,436 @@
+//! The config file, `~/.config/arrgs/config.toml` or `--config`: default
+//! options, and the TUI's key bindings and colors
+//!
+//! ```toml
+//! [defaults]
+//! mode = "parallel"
+//! jobs = 4
+//!
+//! [keys]
+//! kill = "K"
+//! next = ["j", "down"]
+//!
+//! [theme]
+//! succeeded = "light-green"
+//! highlight = "#ffaf00"
+//!
+//! [safety]
+//! allow = ["rm -rf build"]
+//! deny = ["git push --force"]
+//! ```
+//!
+//! Defaults are named by their long option, and apply unless the option is
+//! given on the command line. The `[safety]` commands always (`deny`) or
+//! never (`allow`) need confirmation with `--confirm-destructive`. In a
+//! shell script, each command is checked on its own, and allowing one doesn't
+//! cover its output redirection.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{Command, CommandFactory, FromArgMatches};
+use crossterm::event::KeyCode;
+use ratatui::style::Color;
+use serde::{Deserialize, Deserializer};
+
+use crate::safety::Rules;
+use crate::{shell, Options};
+
+/// Something a key does in the TUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Quit,
+    /// Show only the selected process, over the whole screen
+    Expand,
+    Search,
+    /// The next search match, or failed process if there's no search
+    NextMatch,
+    PreviousMatch,
+    /// Only show output lines that match the search
+    FilterLines,
+    /// Cycle through which processes are listed
+    FilterProcesses,
+    Wrap,
+    Kill,
+    Restart,
+    RestartFailed,
+    Save,
+    SaveAll,
+    HideStderr,
+    /// Select the previous process
+    Previous,
+    Next,
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+    ScrollToTop,
+    ScrollToBottom,
+}
+
+/// The default key bindings
+const DEFAULT_KEYS: &[(KeyCode, Action)] = &[
+    (KeyCode::Char('q'), Action::Quit),
+    (KeyCode::Esc, Action::Quit),
+    (KeyCode::Enter, Action::Expand),
+    (KeyCode::Char('/'), Action::Search),
+    (KeyCode::Char('n'), Action::NextMatch),
+    (KeyCode::Char('N'), Action::PreviousMatch),
+    (KeyCode::Char('f'), Action::FilterLines),
+    (KeyCode::Char('F'), Action::FilterProcesses),
+    (KeyCode::Char('w'), Action::Wrap),
+    (KeyCode::Char('x'), Action::Kill),
+    (KeyCode::Char('r'), Action::Restart),
+    (KeyCode::Char('R'), Action::RestartFailed),
+    (KeyCode::Char('s'), Action::Save),
+    (KeyCode::Char('S'), Action::SaveAll),
+    (KeyCode::Char('e'), Action::HideStderr),
+    (KeyCode::Char('k'), Action::Previous),
+    (KeyCode::PageUp, Action::Previous),
+    (KeyCode::Char('j'), Action::Next),
+    (KeyCode::PageDown, Action::Next),
+    (KeyCode::Up, Action::ScrollUp),
+    (KeyCode::Down, Action::ScrollDown),
+    (KeyCode::Left, Action::ScrollLeft),
+    (KeyCode::Right, Action::ScrollRight),
+    (KeyCode::Home, Action::ScrollToTop),
+    (KeyCode::End, Action::ScrollToBottom),
+];
+
+/// Which key does what in the TUI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keys(HashMap<KeyCode, Action>);
+
+impl Default for Keys {
+    fn default() -> Self {
+        Self(DEFAULT_KEYS.iter().copied().collect())
+    }
+}
+
+impl Keys {
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.0.get(&key).copied()
+    }
+
+    /// Binds `keys` to `action`, instead of its default keys
+    fn rebind(&mut self, action: Action, keys: Vec<KeyCode>) {
+        self.0.retain(|_, bound| *bound != action);
+        self.0.extend(keys.into_iter().map(|key| (key, action)));
+    }
+}
+
+/// A key, as a single character or the name of a special key, e.g. `enter`
+/// or `pagedown`
+fn parse_key(name: &str) -> Result<KeyCode, String> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyCode::Char(c));
+    }
+    let key = match name.to_ascii_lowercase().as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        lower => lower
+            .strip_prefix('f')
+            .and_then(|n| n.parse().ok())
+            .filter(|n| (1..=12).contains(n))
+            .map(KeyCode::F)
+            .ok_or_else(|| format!("unknown key `{name}`"))?,
+    };
+    Ok(key)
+}
+
+/// The TUI's colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Running processes, and the borders of those that aren't selected
+    #[serde(deserialize_with = "color")]
+    pub running: Color,
+    #[serde(deserialize_with = "color")]
+    pub succeeded: Color,
+    #[serde(deserialize_with = "color")]
+    pub failed: Color,
+    /// Processes that timed out or couldn't be started
+    #[serde(deserialize_with = "color")]
+    pub error: Color,
+    /// The selected process's border, search matches, and the running count
+    #[serde(deserialize_with = "color")]
+    pub highlight: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            running: Color::Gray,
+            succeeded: Color::Green,
+            failed: Color::Red,
+            error: Color::Magenta,
+            highlight: Color::Yellow,
+        }
+    }
+}
+
+/// A color by name (e.g. `light-red`), as `#rrggbb`, or as an index into the
+/// terminal's 256 colors
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse()
+        .map_err(|_| serde::de::Error::custom(format!("unknown color `{name}`")))
+}
+
+/// The TUI's settings from the config file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tui {
+    pub keys: Keys,
+    pub theme: Theme,
+}
+
+/// One key, or several that do the same thing
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum KeyNames {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Commands as they'd be typed at a shell, e.g. `rm -rf build`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Safety {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    defaults: toml::Table,
+    keys: HashMap<Action, KeyNames>,
+    theme: Theme,
+    safety: Safety,
+}
+
+impl Config {
+    /// Reads `path`, or else the default config file if there is one
+    fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let (path, contents) = match path {
+            Some(path) => (path.to_owned(), std::fs::read_to_string(path)),
+            None => {
+                let Some(path) = default_path() else {
+                    return Ok(Self::default());
+                };
+                match std::fs::read_to_string(&path) {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok(Self::default())
+                    }
+                    contents => (path, contents),
+                }
+            }
+        };
+        let contents = contents.with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("reading {}", path.display()))
+    }
+
+    fn tui(&self) -> anyhow::Result<Tui> {
+        let mut keys = Keys::default();
+        for (&action, names) in &self.keys {
+            let names = match names {
+                KeyNames::One(name) => std::slice::from_ref(name),
+                KeyNames::Many(names) => names.as_slice(),
+            };
+            let codes = names
+                .iter()
+                .map(|name| parse_key(name))
+                .collect::<Result<_, _>>()
+                .map_err(|e| anyhow::anyhow!("{e} in [keys]"))?;
+            keys.rebind(action, codes);
+        }
+        Ok(Tui {
+            keys,
+            theme: self.theme,
+        })
+    }
+
+    fn safety(&self) -> anyhow::Result<Rules> {
+        let split = |commands: &[String]| {
+            commands
+                .iter()
+                .map(|command| {
+                    shell::split(command)
+                        .filter(|words| !words.is_empty())
+                        .ok_or_else(|| anyhow::anyhow!("invalid command `{command}` in [safety]"))
+                })
+                .collect::<anyhow::Result<_>>()
+        };
+        Ok(Rules {
+            allow: split(&self.safety.allow)?,
+            deny: split(&self.safety.deny)?,
+        })
+    }
+
+    /// Makes the `[defaults]` the default values of their options
+    fn apply_defaults(&self, mut command: Command) -> anyhow::Result<Command> {
+        for (name, value) in &self.defaults {
+            let id = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name.as_str()))
+                .map(|arg| arg.get_id().clone())
+                .ok_or_else(|| anyhow::anyhow!("unknown option `{name}` in [defaults]"))?;
+            let values = match value {
+                toml::Value::Array(values) => values.iter().map(default_value).collect(),
+                value => vec![default_value(value)],
+            };
+            command = command.mut_arg(id, |arg| arg.default_values(values));
+        }
+        Ok(command)
+    }
+}
+
+fn default_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/arrgs/config.toml`, or `~/.config/arrgs/config.toml`
+fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("arrgs").join("config.toml"))
+}
+
+/// Parses the command line, with defaults from the config file, exiting with
+/// a usage message if it's invalid
+///
+/// # Errors
+/// Will return an error if the config file can't be read, or is invalid
+pub fn parse_options<I, T>(args: I) -> anyhow::Result<Options>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    // Parsed first without the config file, to find out which one to read
+    let matches = Options::command().get_matches_from(&args);
+    let config = Config::load(matches.get_one::<PathBuf>("config").map(PathBuf::as_path))?;
+    let matches = config
+        .apply_defaults(Options::command())?
+        .get_matches_from(args);
+    let mut options = Options::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    options.tui = config.tui()?;
+    options.safety = config.safety()?;
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mode;
+
+    fn parse_config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn keys_and_theme() {
+        let tui = parse_config(
+            r##"
+            [keys]
+            kill = "K"
+            next = ["J", "down"]
+            quit = "f10"
+
+            [theme]
+            failed = "#ff0000"
+            "##,
+        )
+        .tui()
+        .unwrap();
+        let keys = &tui.keys;
+        assert_eq!(keys.action(KeyCode::Char('K')), Some(Action::Kill));
+        assert_eq!(keys.action(KeyCode::Char('x')), None);
+        assert_eq!(keys.action(KeyCode::Char('J')), Some(Action::Next));
+        assert_eq!(keys.action(KeyCode::Down), Some(Action::Next));
+        assert_eq!(keys.action(KeyCode::PageDown), None);
+        assert_eq!(keys.action(KeyCode::F(10)), Some(Action::Quit));
+        assert_eq!(keys.action(KeyCode::Esc), None);
+        assert_eq!(keys.action(KeyCode::Char('k')), Some(Action::Previous));
+        assert_eq!(tui.theme.failed, Color::Rgb(255, 0, 0));
+        assert_eq!(tui.theme.succeeded, Color::Green);
+
+        assert!(parse_config("[keys]\nkill = \"ctrl-k\"").tui().is_err());
+        assert!(toml::from_str::<Config>("[keys]\nfly = \"f\"").is_err());
+        assert!(toml::from_str::<Config>("[theme]\nfailed = \"reddish\"").is_err());
+    }
+
+    #[test]
+    fn safety_rules() {
+        let rules = parse_config(
+            r#"
+            [safety]
+            allow = ["rm -rf build"]
+            deny = ["git push --force", "sh -c 'rm -r'"]
+            "#,
+        )
+        .safety()
+        .unwrap();
+        assert_eq!(rules.allow, [["rm", "-rf", "build"]]);
+        assert_eq!(
+            rules.deny,
+            [vec!["git", "push", "--force"], vec!["sh", "-c", "rm -r"]]
+        );
+        assert!(parse_config("[safety]\ndeny = [\"rm 'oops\"]")
+            .safety()
+            .is_err());
+        assert!(parse_config("[safety]\nallow = [\"  \"]").safety().is_err());
+        assert!(toml::from_str::<Config>("[safety]\nask = []").is_err());
+    }
+
+    #[test]
+    fn command_line_over_config_over_defaults() {
+        let config = parse_config(
+            r#"
+            [defaults]
+            mode = "parallel"
+            jobs = 4
+            tag = true
+            arg-file = ["a", "b"]
+            "#,
+        );
+        let parse = |args: &[&str]| {
+            let command = config.apply_defaults(Options::command()).unwrap();
+            Options::from_arg_matches(&command.get_matches_from(args)).unwrap()
+        };
+        let options = parse(&["arrgs", "echo"]);
+        assert_eq!(options.mode, Mode::Parallel);
+        assert_eq!(options.jobs, 4);
+        assert!(options.tag);
+        assert_eq!(options.arg_file, [PathBuf::from("a"), PathBuf::from("b")]);
+        assert_eq!(options.nargs, None);
+        let options = parse(&["arrgs", "-P", "2", "-m", "simple", "echo"]);
+        assert_eq!(options.mode, Mode::Simple);
+        assert_eq!(options.jobs, 2);
+
+        let unknown = parse_config("[defaults]\nspeed = 11");
+        assert!(unknown.apply_defaults(Options::command()).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Shell completions and the man page, generated from the command line's
+//! definition for packagers to ship: `arrgs completions <SHELL>` and
+//! `arrgs --help-man`
+
+use std::io::{self, Write};
+
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+
+use crate::Options;
+
+/// Write a shell completion script to stdout
+#[derive(Parser, Debug)]
+#[command(name = "arrgs completions", bin_name = "arrgs completions")]
+pub struct CompletionsOptions {
+    /// The shell to complete arrgs's options in
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+/// Writes the completion script for `options.shell` to stdout
+///
+/// # Errors
+/// Will return an error if stdout can't be written to
+pub fn completions(options: CompletionsOptions) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    write_completions(options.shell, &mut stdout)?;
+    stdout.flush()
+}
+
+fn write_completions(shell: Shell, out: &mut impl Write) -> io::Result<()> {
+    let mut command = Options::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+    Ok(())
+}
+
+/// Writes the man page to stdout, in roff
+///
+/// # Errors
+/// Will return an error if stdout can't be written to
+pub fn man_page() -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    clap_mangen::Man::new(Options::command()).render(&mut stdout)?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_scripts() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = vec![];
+            write_completions(shell, &mut script).unwrap();
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("arrgs"), "{shell}: {script}");
+            assert!(script.contains("max-chars"), "{shell}: {script}");
+        }
+    }
+
+    #[test]
+    fn man_page_sections() {
+        let mut page = vec![];
+        clap_mangen::Man::new(Options::command())
+            .render(&mut page)
+            .unwrap();
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.starts_with(".ie"), "{page}");
+        assert!(page.contains(".TH arrgs 1"), "{page}");
+        assert!(page.contains("like xargs"), "{page}");
+        assert!(page.contains("\\-\\-jobs"), "{page}");
+        assert!(page.contains("arrgs report"), "{page}");
+    }
+}
@@ -1,132 +1,1296 @@
-use std::time::Duration;
-use std::{process, thread};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::io::{Read, Write};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+use std::{io, process};
 
-use crate::split_input::Splitter;
-use crate::Options;
+use anyhow::Context;
+
+use crate::halt::HaltWhen;
+use crate::job::{retry_delay, Job, Logs, Output};
+use crate::joblog::Resume;
+use crate::platform::{arg_max, exit_status, wait_readable, wake_pipe, PipeReader};
+use crate::remote;
+use crate::safety::Prompt;
+use crate::signals::{self, ChildExits};
+use crate::split_input::chunks;
+use crate::template::Values;
+use crate::throttle::Throttle;
+use crate::{shell, Options};
 
 /// A trait for anything that takes our `Options` struct as an argument
-/// and returns a list of exit statuses of spawned child processes
-pub trait Executor {
+/// and returns the [`JobResult`] of each job it ran, including those that
+/// couldn't be started.
+/// Inputs are consumed as they become available, so child processes may be
+/// started before all inputs have been read. They may be read on a thread of
+/// their own, so that waiting for the next one doesn't hold up the child
+/// processes that are running.
+pub trait Executor: Sized {
     fn execute(
         self,
         options: &Options,
-        inputs: Splitter,
-    ) -> anyhow::Result<Vec<process::ExitStatus>>;
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+    ) -> anyhow::Result<Vec<JobResult>> {
+        self.execute_with(options, inputs, &mut |_| {})
+    }
+
+    /// Like [`Executor::execute`], reporting each [`Event`] as it happens
+    fn execute_with(
+        self,
+        options: &Options,
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+        on_event: &mut dyn FnMut(Event),
+    ) -> anyhow::Result<Vec<JobResult>>;
+}
+
+/// Something that happened during a run, reported as it happens to follow its
+/// progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Job number `seq` is about to start
+    Started { seq: usize },
+    /// Job number `seq` has finished, including any retries, or failed to
+    /// start
+    Finished { seq: usize, result: JobResult },
+    /// No more jobs will be started, because the inputs have run out, the
+    /// `--halt` policy was triggered, or a termination signal was caught.
+    /// Reported once, possibly before the last jobs have started.
+    NoMoreJobs,
+}
+
+/// The arguments for one child process: the fixed program arguments with
+/// their placeholders replaced by the inputs (see [`crate::template`]), or
+/// followed by the inputs if there aren't any placeholders. With `-I`, the
+/// inputs are never appended. With `--quote`, each input is shell-quoted.
+fn child_args<S: AsRef<OsStr>>(options: &Options, seq: usize, inputs: &[S]) -> Vec<OsString> {
+    let values = values(options, seq, inputs, options.quote);
+    let expanded: Vec<Option<OsString>> = options
+        .program_args
+        .iter()
+        .map(|arg| values.expand(arg))
+        .collect();
+    let appended = if options.replace.is_none() && expanded.iter().all(Option::is_none) {
+        inputs
+    } else {
+        &[]
+    };
+    options
+        .program_args
+        .iter()
+        .zip(expanded)
+        .map(|(arg, expanded)| expanded.unwrap_or_else(|| arg.into()))
+        .chain(appended.iter().map(|input| {
+            if options.quote {
+                shell::quote_os(input.as_ref())
+            } else {
+                input.as_ref().to_owned()
+            }
+        }))
+        .collect()
+}
+
+fn values<'a, S>(options: &'a Options, seq: usize, inputs: &'a [S], quote: bool) -> Values<'a, S> {
+    Values {
+        token: options.replace.as_deref().unwrap_or("{}"),
+        inputs,
+        fields: &options.fields,
+        seq,
+        quote,
+    }
+}
+
+/// The full command line for job number `seq`: the program followed by its
+/// `child_args`, or with `--shell`, a shell running the `shell_script`. With
+/// `--pipe`, the inputs are written to stdin instead, so aren't included.
+pub fn command_line<S: AsRef<OsStr>>(options: &Options, seq: usize, inputs: &[S]) -> Vec<OsString> {
+    let inputs = if options.pipe { &[] } else { inputs };
+    if options.shell {
+        let [shell, flag] = shell_command();
+        return vec![shell, flag, shell_script(options, seq, inputs)];
+    }
+    std::iter::once(OsString::from(&options.program))
+        .chain(child_args(options, seq, inputs))
+        .collect()
+}
+
+/// The program and its arguments joined into a `--shell` script, before the
+/// inputs are substituted
+pub fn shell_template(options: &Options) -> String {
+    std::iter::once(&options.program)
+        .chain(&options.program_args)
+        .map(String::as_str)
+        .intersperse(" ")
+        .collect()
+}
+
+/// The shell that runs `--shell` scripts, and the flag that passes it a script
+#[cfg(unix)]
+fn shell_command() -> [OsString; 2] {
+    ["sh".into(), "-c".into()]
+}
+
+#[cfg(windows)]
+fn shell_command() -> [OsString; 2] {
+    let comspec = std::env::var_os("COMSPEC").unwrap_or_else(|| "cmd.exe".into());
+    [comspec, "/C".into()]
+}
+
+/// The script for one child process with `--shell`: the program and its
+/// arguments joined with spaces, with the placeholders replaced by the
+/// inputs. The inputs are shell-quoted, so they can't inject commands into
+/// the script. When there's nothing to replace, the inputs are appended.
+fn shell_script<S: AsRef<OsStr>>(options: &Options, seq: usize, inputs: &[S]) -> OsString {
+    let values = values(options, seq, inputs, true);
+    let template = shell_template(options);
+    values.expand(&template).unwrap_or_else(|| {
+        let mut script = OsString::from(template);
+        if !inputs.is_empty() {
+            script.push(" ");
+            script.push(values.all());
+        }
+        script
+    })
+}
+
+/// Tells a child which job it's running for, through its environment:
+/// `ARRGS_JOB` (the job number), `ARRGS_SLOT` (which of the `--jobs` running
+/// at once it is, counting from 1), `ARRGS_INPUT` (all of the inputs,
+/// separated by spaces) and `ARRGS_INPUT_1`, `ARRGS_INPUT_2`, ... (each
+/// input). Each `--env` is set as well. With `--pipe`, the inputs are only
+/// written to stdin.
+pub fn job_env<'a>(
+    command: &'a mut process::Command,
+    options: &Options,
+    seq: usize,
+    slot: usize,
+    inputs: &[OsString],
+) -> &'a mut process::Command {
+    command.envs(job_vars(options, seq, slot, inputs))
+}
+
+/// The variables [`job_env`] sets, in order
+pub fn job_vars(
+    options: &Options,
+    seq: usize,
+    slot: usize,
+    inputs: &[OsString],
+) -> Vec<(OsString, OsString)> {
+    let mut vars: Vec<(OsString, OsString)> = vec![
+        ("ARRGS_JOB".into(), seq.to_string().into()),
+        ("ARRGS_SLOT".into(), slot.to_string().into()),
+    ];
+    if !options.pipe {
+        vars.push(("ARRGS_INPUT".into(), inputs.join(OsStr::new(" "))));
+        for (i, input) in inputs.iter().enumerate() {
+            vars.push((format!("ARRGS_INPUT_{}", i + 1).into(), input.clone()));
+        }
+    }
+    vars.extend(
+        options
+            .env
+            .iter()
+            .map(|(key, value)| (key.into(), value.into())),
+    );
+    vars
+}
+
+/// Groups the inputs into the inputs for each invocation: `--nargs` (or
+/// `--max-lines`) at a time, and no more than fit in `--max-chars`
+pub fn chunk_inputs(
+    options: &Options,
+    inputs: impl Iterator<Item = OsString>,
+) -> impl Iterator<Item = Vec<OsString>> {
+    let max_args = match (options.nargs.or(options.max_lines), options.max_chars) {
+        (Some(nargs), _) => nargs,
+        (None, Some(_)) => usize::MAX,
+        (None, None) => 1,
+    };
+    let fixed = command_line_size(&command_line::<OsString>(options, 0, &[]));
+    let budget = options
+        .max_chars
+        .unwrap_or_else(default_max_chars)
+        .saturating_sub(fixed);
+    let options = options.clone();
+    chunks(inputs, max_args).within(budget, move |input| {
+        // How much longer the command line gets for each extra input like
+        // this one, whether it's appended, replaced into several arguments or
+        // quoted into a script
+        let one = command_line_size(&command_line(&options, 0, &[input]));
+        let two = command_line_size(&command_line(&options, 0, &[input, input]));
+        two.saturating_sub(one)
+    })
+}
+
+/// The number of bytes a command line takes up, as `--max-chars` counts it
+fn command_line_size(command: &[OsString]) -> usize {
+    command.iter().map(|arg| arg.len() + 1).sum()
+}
+
+/// The default `--max-chars`: `ARG_MAX` less the environment (and some
+/// headroom, like xargs), up to 128KiB since Linux won't accept longer single
+/// arguments, as `-I` and `--shell` produce
+fn default_max_chars() -> usize {
+    const MAX: usize = 128 * 1024;
+    const HEADROOM: usize = 2048;
+    let arg_max = arg_max().unwrap_or(MAX);
+    let environment: usize = std::env::vars_os()
+        .map(|(key, value)| key.len() + value.len() + 2)
+        .sum();
+    arg_max
+        .saturating_sub(environment + HEADROOM)
+        .clamp(HEADROOM, MAX)
+}
+
+/// The jobs to run: chunks of inputs from [`chunk_inputs`], numbered from 1 in input order,
+/// without any that `--resume` says have already run, or that were declined
+/// when asked with `-p`. Since jobs are taken as they're started, `-p` asks
+/// just before starting each one.
+///
+/// # Errors
+/// Will return an error if resuming and the job log cannot be read, or `-p`
+/// was given and the controlling terminal cannot be opened
+pub fn jobs(
+    options: &Options,
+    inputs: impl Iterator<Item = OsString>,
+) -> anyhow::Result<impl Iterator<Item = (usize, Vec<OsString>)>> {
+    let jobs = unprompted_jobs(options, inputs)?;
+    let mut prompt = JobPrompt::open(options)?;
+    Ok(jobs
+        // Otherwise a declined job would go on to ask about the next
+        .take_while(|_| signals::received().is_none())
+        .filter(move |(seq, chunk)| prompt.allows(*seq, chunk)))
+}
+
+/// The [`jobs`] before `-p` has asked about them
+///
+/// # Errors
+/// Will return an error if resuming and the job log cannot be read
+fn unprompted_jobs(
+    options: &Options,
+    inputs: impl Iterator<Item = OsString>,
+) -> anyhow::Result<impl Iterator<Item = (usize, Vec<OsString>)>> {
+    let resume = Resume::load(options)?;
+    Ok(chunk_inputs(options, inputs)
+        .enumerate()
+        .map(|(index, chunk)| (index + 1, chunk))
+        .filter(move |(_, chunk)| !resume.as_ref().is_some_and(|r| r.skips(chunk))))
+}
+
+/// Asks whether to run each job just before it starts, with `-p`
+struct JobPrompt {
+    prompt: Option<Prompt>,
+    options: Options,
+}
+
+impl JobPrompt {
+    /// # Errors
+    /// Will return an error if `-p` was given and the controlling terminal
+    /// cannot be opened
+    fn open(options: &Options) -> anyhow::Result<Self> {
+        Ok(Self {
+            prompt: options.prompt.then(Prompt::open).transpose()?,
+            options: options.clone(),
+        })
+    }
+
+    fn allows(&mut self, seq: usize, chunk: &[OsString]) -> bool {
+        self.prompt
+            .as_mut()
+            .is_none_or(|prompt| prompt.ask(&command_line(&self.options, seq, chunk)))
+    }
+}
+
+/// Reads the [`unprompted_jobs`] on a thread of its own, so that waiting for
+/// the next input doesn't hold up the jobs that are running: their timeouts,
+/// output and exits. Only one job is read ahead of those taken, so with
+/// `--pipe` stdin is still read about as fast as blocks are taken.
+struct JobReader {
+    jobs: Receiver<(usize, Vec<OsString>)>,
+    /// Written to after each job is read, and closed once they run out
+    wake: PipeReader,
+    closed: bool,
+}
+
+impl JobReader {
+    /// # Errors
+    /// Will return an error if resuming and the job log cannot be read, or
+    /// the pipe to wake up the caller can't be created
+    fn spawn(
+        options: &Options,
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let jobs = unprompted_jobs(options, inputs)?;
+        let (wake, mut wake_tx) = wake_pipe().context("could not create a pipe")?;
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        // Not joined, since it may be waiting for input that never comes. It
+        // ends once the receiver has gone.
+        std::thread::spawn(move || {
+            for job in jobs {
+                if tx.send(job).is_err() {
+                    break;
+                }
+                // If the pipe is full, a wakeup is already pending
+                let _ = wake_tx.write(&[0]);
+            }
+        });
+        Ok(Self {
+            jobs: rx,
+            wake,
+            closed: false,
+        })
+    }
+
+    /// The next job, if it has been read. [`TryRecvError::Disconnected`]
+    /// means there are no more.
+    fn try_next(&self) -> Result<(usize, Vec<OsString>), TryRecvError> {
+        self.jobs.try_recv()
+    }
+
+    /// Blocks until a child process exits, another job has been read, or the
+    /// deadline passes
+    fn wait(&mut self, exits: &ChildExits, deadline: Option<Instant>) {
+        if self.closed {
+            exits.wait(deadline);
+            return;
+        }
+        wait_readable(exits, &[&self.wake], deadline);
+        // Empty the pipe, so that the next wait blocks until another job is
+        // read. Once it's closed, it would never block again.
+        let mut buffer = [0; 64];
+        loop {
+            match self.wake.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => {}
+            }
+            break;
+        }
+    }
+}
+
+/// Exit code for when the program could not be run, following xargs
+pub const EXIT_CANNOT_RUN: u8 = 126;
+/// Exit code for when the program could not be found, following xargs
+pub const EXIT_NOT_FOUND: u8 = 127;
+
+/// A child process couldn't be started, or couldn't be waited on
+#[derive(Debug)]
+pub struct SpawnError(pub io::Error);
+
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for SpawnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// The exit code to use when the program couldn't be started
+pub fn spawn_failure_code(error: io::ErrorKind) -> u8 {
+    match error {
+        io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+        _ => EXIT_CANNOT_RUN,
+    }
+}
+
+/// How a job ended, once any retries are over, or how one attempt at it did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobResult {
+    Success,
+    /// The program exited with a non-zero status, or was killed by a signal
+    ExitCode(process::ExitStatus),
+    /// The program couldn't be started, or waited on
+    SpawnError(io::ErrorKind),
+    /// The program was terminated for running past `--timeout`, and exited
+    /// with this status
+    TimedOut(process::ExitStatus),
+}
+
+impl JobResult {
+    /// The result of a run that exited with `status`, having been terminated
+    /// first if it `timed_out`
+    pub fn exited(status: process::ExitStatus, timed_out: bool) -> Self {
+        match status {
+            _ if timed_out => Self::TimedOut(status),
+            status if status.success() => Self::Success,
+            status => Self::ExitCode(status),
+        }
+    }
+
+    pub fn spawn_error(error: &io::Error) -> Self {
+        Self::SpawnError(error.kind())
+    }
+
+    pub fn success(&self) -> bool {
+        *self == Self::Success
+    }
+
+    /// The status the program exited with, if it was started
+    pub fn exit_status(&self) -> Option<process::ExitStatus> {
+        match *self {
+            Self::Success => Some(exit_status(0)),
+            Self::ExitCode(status) | Self::TimedOut(status) => Some(status),
+            Self::SpawnError(_) => None,
+        }
+    }
+
+    /// The status to record for the job: the one it exited with, or if it
+    /// couldn't be started, as if a shell had exited with the
+    /// [`spawn_failure_code`]
+    pub fn status(&self) -> process::ExitStatus {
+        match *self {
+            Self::SpawnError(error) => exit_status(spawn_failure_code(error).into()),
+            _ => self.exit_status().expect("the program was started"),
+        }
+    }
+
+    /// What went wrong, besides the exit status: that the program timed out,
+    /// or why it couldn't be started
+    pub fn error(&self) -> Option<String> {
+        match self {
+            Self::Success | Self::ExitCode(_) => None,
+            Self::SpawnError(error) => Some(format!("could not start: {error}")),
+            Self::TimedOut(_) => Some("timed out".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for JobResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success => write!(f, "succeeded"),
+            Self::ExitCode(status) => write!(f, "failed, {status}"),
+            Self::SpawnError(error) => write!(f, "could not start: {error}"),
+            Self::TimedOut(_) => write!(f, "timed out"),
+        }
+    }
 }
 
+/// Combines the results of all jobs into a single exit code, with the same
+/// meanings as GNU xargs:
+/// - 0 if every job succeeded
+/// - 123 if any process exited with a status of 1-125, or exited by itself
+///   after timing out
+/// - 124 if any process exited with a status of 255
+/// - 125 if any process was killed by a signal
+/// - 126 if the program could not be run
+/// - 127 if the program could not be found
+///
+/// When several apply, the highest code wins.
+pub fn exit_code(results: &[JobResult]) -> u8 {
+    results
+        .iter()
+        .map(|result| match *result {
+            JobResult::Success => 0,
+            JobResult::SpawnError(error) => spawn_failure_code(error),
+            JobResult::ExitCode(status) | JobResult::TimedOut(status) => match status.code() {
+                Some(code @ (126 | 127)) => code as u8,
+                Some(255) => 124,
+                Some(_) => 123,
+                None => 125,
+            },
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// How long a timed out child process has to exit after being sent `SIGTERM`,
+/// before it's sent `SIGKILL`
+pub const KILL_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
 /// Runs the child processes in sequence, waiting for each to finish before
 /// starting the next
 pub struct Sequential;
 impl Executor for Sequential {
     /// # Errors
-    /// Will return an error if either:
-    /// - The input buffer cannot be read from stdin
-    /// - One of the child processes fails to start (at which point the function
-    ///   will return early)
-    fn execute(
+    /// Will only return an error if the audit log or job log cannot be
+    /// opened or written to. Failures to start child processes are reported
+    /// on stderr, and included in the returned results.
+    ///
+    /// Stops early, without an error, when the `--halt` policy is triggered or
+    /// a termination signal is caught.
+    fn execute_with(
         self,
         options: &Options,
-        inputs: Splitter,
-    ) -> anyhow::Result<Vec<process::ExitStatus>> {
-        inputs
-            .chunks(options.nargs)
-            .map(|child_args| {
-                process::Command::new(&options.program)
-                    .args(&options.program_args)
-                    .args(child_args)
-                    .stdin(process::Stdio::null()) // Make sure the child doesn't read from *our* stdin
-                    .status()
-                    .map_err(Into::into)
-            })
-            .collect()
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+        on_event: &mut dyn FnMut(Event),
+    ) -> anyhow::Result<Vec<JobResult>> {
+        let mut logs = Logs::open(options)?;
+        let mut results = vec![];
+        let mut failures = 0;
+        let mut throttle = Throttle::new(options);
+        // The next input isn't read until the job before has finished, since
+        // waiting for it would hold up the job's timeout and output
+        for (seq, inputs) in jobs(options, inputs)? {
+            throttle.wait()?;
+            if signals::received().is_some() {
+                break;
+            }
+            on_event(Event::Started { seq });
+            throttle.started();
+            let command = command_line(options, seq, &inputs);
+            let job = Job::start(options, seq, 1, inputs, &mut logs);
+            let result = match job {
+                Ok(mut job) => match job.wait(options, &mut logs) {
+                    Ok(result) => {
+                        job.take_output().print()?;
+                        result
+                    }
+                    Err(e) => spawn_failure("run", &command, e)?,
+                },
+                Err(e) => spawn_failure("start", &command, e)?,
+            };
+            on_event(Event::Finished { seq, result });
+            results.push(result);
+            if signals::received().is_some() {
+                break;
+            }
+            if !result.success() {
+                failures += 1;
+                if options.halt.is_triggered(failures) {
+                    eprintln!("Halting after {failures} failed jobs");
+                    break;
+                }
+            }
+        }
+        on_event(Event::NoMoreJobs);
+        logs.finish()?;
+        Ok(results)
+    }
+}
+
+/// Reports on stderr that a child process failed to `start` or `run`, for a
+/// [`SpawnError`], returning its result. Any other error is passed on.
+///
+/// # Errors
+/// Will return `error` if it isn't a [`SpawnError`]
+fn spawn_failure(
+    what: &str,
+    command: &[OsString],
+    error: anyhow::Error,
+) -> anyhow::Result<JobResult> {
+    let SpawnError(e) = error.downcast()?;
+    eprintln!(
+        "Failed to {what} process ({}): {e}",
+        command.join(OsStr::new(" ")).to_string_lossy()
+    );
+    Ok(JobResult::spawn_error(&e))
+}
+
+/// The most child processes to run at once in parallel: `--jobs`, where 0
+/// means no limit. With `--pipe` it means one per CPU instead, since a block
+/// of stdin is held in memory for each running process. With `--sshlogin`,
+/// it's the slots of every login put together.
+fn max_jobs(options: &Options) -> usize {
+    if !options.sshlogin.is_empty() {
+        return remote::total_slots(options);
+    }
+    match options.jobs {
+        0 if options.pipe => std::thread::available_parallelism().map_or(1, usize::from),
+        jobs => jobs,
     }
 }
 
-/// Runs the child processes in parallel, waiting for all to finish before
-/// returning
+/// Runs the child processes in parallel, keeping at most `jobs` of them
+/// running at once, and waiting for all to finish before returning
 pub struct Parallel;
 impl Executor for Parallel {
     /// # Errors
-    /// Will only return an error if the input buffer cannot be read from stdin.
-    /// Failures to start child processes are (currently) only handled by
-    /// printing an error message to stderr.
-    fn execute(
+    /// Will only return an error if the audit log or job log cannot be
+    /// opened or written to.
+    /// Failures to start child processes are reported on stderr, and included
+    /// in the returned results.
+    ///
+    /// When the `--halt` policy is triggered, no more child processes are
+    /// started, and running ones are terminated if the policy is `now`. When a
+    /// termination signal is caught, it's passed on to every running child.
+    fn execute_with(
         self,
         options: &Options,
-        inputs: Splitter,
-    ) -> anyhow::Result<Vec<process::ExitStatus>> {
-        let mut running = vec![];
-        for child_args in inputs.chunks(options.nargs) {
-            let child = process::Command::new(&options.program)
-                .args(&options.program_args)
-                .args(&child_args)
-                .stdin(process::Stdio::null()) // Make sure the child doesn't read from *our* stdin
-                .spawn();
-            match child {
-                Ok(child) => running.push(child),
-                Err(e) => eprintln!(
-                    "Failed to start process ({} {}): {e}",
-                    options.program,
-                    child_args.join(" ")
-                ),
-            }
-        }
-
-        let mut exited = Vec::with_capacity(running.len());
-        let mut checked = Vec::with_capacity(running.len());
-        while !running.is_empty() {
-            // Wait for all child processes to finish
-            while let Some(mut child) = running.pop() {
-                // `Child.try_wait` is non-blocking, so is essentially a poll
-                match child.try_wait() {
-                    Ok(Some(status)) => exited.push(status), // Child process has exited
-                    Ok(None) => checked.push(child),         // Child process is still running
-                    Err(e) => eprintln!("Error checking child status ({child:?}): {e}"),
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+        on_event: &mut dyn FnMut(Event),
+    ) -> anyhow::Result<Vec<JobResult>> {
+        let mut logs = Logs::open(options)?;
+        // Set up before starting any children, so that no exits are missed
+        let exits = ChildExits::new()?;
+        let mut reader = JobReader::spawn(options, inputs)?;
+        let mut prompt = JobPrompt::open(options)?;
+        // Jobs are numbered in the order they're started for the sequencer,
+        // since `--resume` and `-p` can leave gaps in their sequence numbers
+        let mut started = 0;
+        let mut finished = Finished {
+            sequencer: OutputSequencer::new(options.keep_order),
+            results: vec![],
+            failures: 0,
+            on_event,
+        };
+        let mut running: Vec<(usize, Job)> = vec![];
+        let mut halted = false;
+        let mut inputs_done = false;
+        let mut no_more_jobs = false;
+        let mut throttle = Throttle::new(options);
+        let max_jobs = max_jobs(options);
+        loop {
+            // Start new child processes until we reach the jobs limit (0 means
+            // no limit), run out of inputs, or have to wait for `--delay` or
+            // `--rate`, or for the next input to be read
+            while !halted
+                && signals::received().is_none()
+                && (max_jobs == 0 || running.len() < max_jobs)
+                && throttle.ready_at().is_none()
+            {
+                let (seq, chunk) = match reader.try_next() {
+                    Ok(job) => job,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        inputs_done = true;
+                        break;
+                    }
+                };
+                if !prompt.allows(seq, &chunk) {
+                    continue;
                 }
+                let order = started;
+                started += 1;
+                (finished.on_event)(Event::Started { seq });
+                throttle.started();
+                let command = command_line(options, seq, &chunk);
+                // The lowest slot that no running job has
+                let slot = (1..)
+                    .find(|&slot| running.iter().all(|(_, job)| job.slot() != slot))
+                    .unwrap_or_default();
+                match Job::start(options, seq, slot, chunk, &mut logs) {
+                    Ok(job) => running.push((order, job)),
+                    Err(e) => {
+                        finished.sequencer.finished(order, Output::default())?;
+                        let result = spawn_failure("start", &command, e)?;
+                        (finished.on_event)(Event::Finished { seq, result });
+                        finished.results.push(result);
+                        finished.failures += 1;
+                    }
+                }
+            }
+            if !no_more_jobs && (inputs_done || halted || signals::received().is_some()) {
+                (finished.on_event)(Event::NoMoreJobs);
+                no_more_jobs = true;
+            }
+            if running.is_empty() && no_more_jobs {
+                break;
+            }
+
+            // Wait for a child to exit, for a job's timeout, grace period or
+            // retry delay to end, or until the next job may start or has
+            // been read
+            let deadline = running
+                .iter()
+                .filter_map(|(_, job)| job.deadline(options))
+                .chain(throttle.ready_at().filter(|_| !no_more_jobs))
+                .min();
+            if no_more_jobs {
+                exits.wait(deadline);
+            } else {
+                reader.wait(&exits, deadline);
+            }
+
+            // Poll the jobs whose children have exited, one at a time since
+            // polling reaps the child. Every job is polled once we've been
+            // signalled, so the signal gets passed on.
+            let mut poll_all = !signals::REPORTS_EXITS || signals::received().is_some();
+            while let Some(pid) = exits.next_exited() {
+                let Some(index) = running.iter().position(|(_, job)| job.pid() == Some(pid)) else {
+                    // Not one of ours, so it won't be reaped, and would be
+                    // reported again. Check on every job instead.
+                    poll_all = true;
+                    break;
+                };
+                let (order, job) = running.swap_remove(index);
+                running.extend(finished.poll(options, &mut logs, order, job)?);
             }
-            // Sleep for a bit to avoid busy-waiting
-            // 10ms is an arbitrary value, however ~16ms is enough for a 60fps refresh rate
-            thread::sleep(Duration::from_millis(10));
+            // Then any that are due
+            let now = Instant::now();
+            for (order, job) in std::mem::take(&mut running) {
+                if poll_all
+                    || job
+                        .deadline(options)
+                        .is_some_and(|deadline| deadline <= now)
+                {
+                    running.extend(finished.poll(options, &mut logs, order, job)?);
+                } else {
+                    running.push((order, job));
+                }
+            }
+
+            if !halted && options.halt.is_triggered(finished.failures) {
+                eprintln!("Halting after {} failed jobs", finished.failures);
+                halted = true;
+                if options.halt.when == HaltWhen::Now {
+                    running.iter_mut().for_each(|(_, job)| job.cancel());
+                }
+            }
+        }
+        logs.finish()?;
+        Ok(finished.results)
+    }
+}
+
+/// The results of the jobs that have finished in parallel
+struct Finished<'a> {
+    sequencer: OutputSequencer,
+    results: Vec<JobResult>,
+    failures: usize,
+    on_event: &'a mut dyn FnMut(Event),
+}
+
+impl Finished<'_> {
+    /// Polls a job (without blocking), recording its result if it has
+    /// finished, or handing it back if it's still running or waiting to retry
+    ///
+    /// # Errors
+    /// Will return an error if a log or the grouped output cannot be written
+    fn poll(
+        &mut self,
+        options: &Options,
+        logs: &mut Logs,
+        order: usize,
+        mut job: Job,
+    ) -> anyhow::Result<Option<(usize, Job)>> {
+        let result = match job.poll(options, logs) {
+            // Child process has exited, with no retries left
+            Ok(Some(result)) => result,
+            Ok(None) => return Ok(Some((order, job))),
+            Err(e) => spawn_failure("run", &command_line(options, job.seq(), job.inputs()), e)?,
+        };
+        self.sequencer.finished(order, job.take_output())?;
+        (self.on_event)(Event::Finished {
+            seq: job.seq(),
+            result,
+        });
+        self.results.push(result);
+        self.failures += usize::from(!result.success());
+        Ok(None)
+    }
+}
+
+/// Prints the grouped output of finished jobs. With `--keep-order`, output is
+/// held back until the output of every job started before it has been
+/// printed.
+struct OutputSequencer {
+    keep_order: bool,
+    /// Index of the next job whose output can be printed
+    next: usize,
+    pending: BTreeMap<usize, Output>,
+}
 
-            // Put the checked processes back into the running list, to check again
-            running.append(&mut checked);
+impl OutputSequencer {
+    fn new(keep_order: bool) -> Self {
+        Self {
+            keep_order,
+            next: 0,
+            pending: BTreeMap::new(),
         }
-        Ok(exited)
+    }
+
+    /// Records the output of the job with the given index (counting from 0
+    /// in the order jobs were started), printing whatever output is now
+    /// ready
+    fn finished(&mut self, index: usize, output: Output) -> io::Result<()> {
+        for output in self.ready(index, output) {
+            output.print()?;
+        }
+        Ok(())
+    }
+
+    fn ready(&mut self, index: usize, output: Output) -> Vec<Output> {
+        if !self.keep_order {
+            return vec![output];
+        }
+        self.pending.insert(index, output);
+        let mut ready = vec![];
+        while let Some(output) = self.pending.remove(&self.next) {
+            ready.push(output);
+            self.next += 1;
+        }
+        ready
     }
 }
 
+/// Runs the child processes in parallel across the `--sshlogin` machines,
+/// keeping as many running on each as it has slots
+pub struct Remote;
+impl Executor for Remote {
+    /// # Errors
+    /// See [`Parallel`]. Failing to connect to a machine is reported by `ssh`,
+    /// and counts as a failed job.
+    fn execute_with(
+        self,
+        options: &Options,
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+        on_event: &mut dyn FnMut(Event),
+    ) -> anyhow::Result<Vec<JobResult>> {
+        anyhow::ensure!(!options.sshlogin.is_empty(), "no --sshlogin to run jobs on");
+        // The slots are shared out between the machines in `max_jobs`, and
+        // each job is sent to the machine its slot belongs to when it starts
+        Parallel.execute_with(options, inputs, on_event)
+    }
+}
+
+/// Prints each command line (shell-quoted) to stdout instead of running it
+pub struct DryRun;
+impl Executor for DryRun {
+    /// # Errors
+    /// Never returns an error
+    fn execute_with(
+        self,
+        options: &Options,
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+        on_event: &mut dyn FnMut(Event),
+    ) -> anyhow::Result<Vec<JobResult>> {
+        for (index, chunk) in chunk_inputs(options, inputs).enumerate() {
+            println!("{}", shell::join(command_line(options, index + 1, &chunk)));
+        }
+        on_event(Event::NoMoreJobs);
+        Ok(vec![])
+    }
+}
+
+/// Calls a Rust function for each set of inputs instead of running a program,
+/// on up to `--jobs` threads at once (0 means one per CPU). Each thread has
+/// its own clone of the function. An error it returns is reported on stderr,
+/// and counts as the job exiting with status 1, and a panic as it exiting
+/// with status 101, like a Rust program that panicked.
+///
+/// Jobs are taken as with [`Sequential`], so `--resume`, `-p`, `--delay` and
+/// `--rate` apply, each call is recorded in the logs, and failed calls are
+/// retried. A call can't be interrupted, so one that runs past `--timeout`
+/// is left to finish, then counted as timed out.
+///
+/// ```
+/// use arrgs::{Closure, Executor, Options, Splitter};
+///
+/// let options = Options::builder("").nargs(2).jobs(2).build();
+/// let inputs = Splitter::whitespace(&b"1 2 3 x"[..]);
+/// let results = Closure(|inputs: &[std::ffi::OsString]| {
+///     for input in inputs {
+///         input.to_str().unwrap().parse::<u32>()?;
+///     }
+///     Ok::<(), std::num::ParseIntError>(())
+/// })
+/// .execute(&options, inputs)?;
+/// assert_eq!(results.iter().filter(|result| result.success()).count(), 1);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Closure<F>(pub F);
+impl<F, E> Executor for Closure<F>
+where
+    F: FnMut(&[OsString]) -> Result<(), E> + Clone + Send,
+    E: std::fmt::Display,
+{
+    /// # Errors
+    /// Will only return an error if the audit log or job log cannot be
+    /// opened or written to
+    ///
+    /// When the `--halt` policy is triggered or a termination signal is
+    /// caught, no more jobs are started, but running ones are left to finish.
+    fn execute_with(
+        self,
+        options: &Options,
+        inputs: impl Iterator<Item = OsString> + Send + 'static,
+        on_event: &mut dyn FnMut(Event),
+    ) -> anyhow::Result<Vec<JobResult>> {
+        let threads = match options.jobs {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            jobs => jobs,
+        };
+        let mut logs = Logs::open(options)?;
+        let mut jobs = jobs(options, inputs)?;
+        let (jobs_tx, jobs_rx) = std::sync::mpsc::channel::<(usize, Vec<OsString>)>();
+        let jobs_rx = std::sync::Mutex::new(jobs_rx);
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                let mut function = self.0.clone();
+                let (jobs_rx, done_tx) = (&jobs_rx, done_tx.clone());
+                scope.spawn(move || loop {
+                    // The lock is only held while waiting for the next job
+                    let next = jobs_rx.lock().unwrap().recv();
+                    let Ok((seq, inputs)) = next else { break };
+                    for attempt in 1.. {
+                        let start = SystemTime::now();
+                        let result = call(&mut function, seq, &inputs, options.timeout);
+                        let last = result.success()
+                            || attempt > options.retries
+                            || signals::received().is_some();
+                        let _ = done_tx.send(Attempt {
+                            seq,
+                            start,
+                            result,
+                            last,
+                        });
+                        if last {
+                            break;
+                        }
+                        std::thread::sleep(retry_delay(options.retry_delay, attempt));
+                    }
+                });
+            }
+            drop(done_tx);
+
+            // Run to the end, or until a log can't be written to, then let the
+            // threads finish
+            let mut dispatch = || -> anyhow::Result<Vec<JobResult>> {
+                let mut running = HashMap::new();
+                let mut results = vec![];
+                let mut failures = 0;
+                let mut no_more_jobs = false;
+                let mut throttle = Throttle::new(options);
+                loop {
+                    while !no_more_jobs && running.len() < threads {
+                        throttle.wait()?;
+                        let Some((seq, chunk)) = jobs.next() else {
+                            on_event(Event::NoMoreJobs);
+                            no_more_jobs = true;
+                            break;
+                        };
+                        on_event(Event::Started { seq });
+                        throttle.started();
+                        let _ = jobs_tx.send((seq, chunk.clone()));
+                        running.insert(seq, chunk);
+                    }
+                    if running.is_empty() {
+                        break;
+                    }
+                    let attempt = done_rx.recv().expect("a thread is running the job");
+                    let inputs = &running[&attempt.seq];
+                    let command = command_line(options, attempt.seq, inputs);
+                    logs.record(
+                        attempt.seq,
+                        inputs,
+                        &command,
+                        attempt.start,
+                        attempt.result,
+                        None,
+                    )?;
+                    if !attempt.last {
+                        continue;
+                    }
+                    running.remove(&attempt.seq);
+                    let (seq, result) = (attempt.seq, attempt.result);
+                    on_event(Event::Finished { seq, result });
+                    results.push(result);
+                    failures += usize::from(!result.success());
+                    if !no_more_jobs && options.halt.is_triggered(failures) {
+                        eprintln!("Halting after {failures} failed jobs");
+                        on_event(Event::NoMoreJobs);
+                        no_more_jobs = true;
+                    }
+                }
+                logs.finish()?;
+                Ok(results)
+            };
+            let results = dispatch();
+            drop(jobs_tx);
+            results
+        })
+    }
+}
+
+/// One call of a [`Closure`]'s function, for a try at job number `seq`
+struct Attempt {
+    seq: usize,
+    start: SystemTime,
+    result: JobResult,
+    /// Whether the job is over, rather than being retried
+    last: bool,
+}
+
+/// Calls `function` for job number `seq`, reporting an error or panic on
+/// stderr
+fn call<F, E>(
+    function: &mut F,
+    seq: usize,
+    inputs: &[OsString],
+    timeout: Option<Duration>,
+) -> JobResult
+where
+    F: FnMut(&[OsString]) -> Result<(), E>,
+    E: std::fmt::Display,
+{
+    let started = Instant::now();
+    // The panic itself is reported by the panic hook
+    let status = match std::panic::catch_unwind(AssertUnwindSafe(|| function(inputs))) {
+        Ok(Ok(())) => exit_status(0),
+        Ok(Err(e)) => {
+            eprintln!("Job {seq} failed: {e}");
+            exit_status(1)
+        }
+        Err(_) => {
+            eprintln!("Job {seq} panicked");
+            exit_status(101)
+        }
+    };
+    let timed_out = timeout.is_some_and(|timeout| started.elapsed() > timeout);
+    JobResult::exited(status, timed_out)
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::{Duration, Instant};
 
     use super::*;
+    use crate::split_input::Splitter;
     use crate::Mode;
     const MOCK_STDIN: &[u8] = b"0.1 0.2 0.3";
     const TOTAL_SLEEP: f64 = 0.6;
 
     fn test_options(mode: Mode) -> Options {
         Options {
-            nul: false,
-            nargs: 1,
+            nargs: Some(1),
             mode,
             program: "sleep".to_string(),
-            program_args: vec![],
             simulate: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_child_args_appended() {
+        let options = Options {
+            program_args: vec!["-v".to_string()],
+            ..test_options(Mode::Simple)
+        };
+        assert_eq!(child_args(&options, 1, &["a", "b"]), vec!["-v", "a", "b"]);
+    }
+
+    #[test]
+    fn test_child_args_replaced() {
+        let options = Options {
+            program_args: vec!["{}".to_string(), "/backup/{}.bak".to_string()],
+            replace: Some("{}".to_string()),
+            ..test_options(Mode::Simple)
+        };
+        assert_eq!(
+            child_args(&options, 1, &["a b"]),
+            vec!["a b", "/backup/a b.bak"]
+        );
+        assert_eq!(
+            command_line(&options, 1, &["x", "y"]),
+            vec!["sleep", "x y", "/backup/x y.bak"]
+        );
+    }
+
+    #[test]
+    fn test_child_args_placeholders() {
+        let options = Options {
+            nargs: Some(2),
+            program_args: vec!["{1}".to_string(), "{//}/{#}-{/.}".to_string()],
+            ..test_options(Mode::Simple)
+        };
+        assert_eq!(
+            command_line(&options, 3, &["a/b.c", "d"]),
+            vec!["sleep", "a/b.c", "a ./3-{/.}"]
+        );
+        // With `-I`, the inputs are only where the token is
+        let options = Options {
+            program_args: vec!["-v".to_string()],
+            replace: Some("%".to_string()),
+            ..options
+        };
+        assert_eq!(command_line(&options, 1, &["a"]), vec!["sleep", "-v"]);
+    }
+
+    #[test]
+    fn test_job_env() {
+        let options = Options {
+            env: vec![("COLOR".to_string(), "blue".to_string())],
+            ..test_options(Mode::Simple)
+        };
+        let mut command = process::Command::new("env");
+        job_env(&mut command, &options, 4, 2, &["a".into(), "b c".into()]);
+        let envs: BTreeMap<_, _> = command
+            .get_envs()
+            .map(|(key, value)| (key.to_str().unwrap(), value.unwrap().to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            envs,
+            BTreeMap::from([
+                ("ARRGS_INPUT", "a b c"),
+                ("ARRGS_INPUT_1", "a"),
+                ("ARRGS_INPUT_2", "b c"),
+                ("ARRGS_JOB", "4"),
+                ("ARRGS_SLOT", "2"),
+                ("COLOR", "blue"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_chunk_inputs_max_chars() {
+        let options = Options {
+            nargs: None,
+            max_chars: Some(32),
+            program_args: vec!["{}".to_string(), "/backup/{}.bak".to_string()],
+            replace: Some("{}".to_string()),
+            ..test_options(Mode::Simple)
+        };
+        let inputs = ["ab", "cd", "ef", "gh", "ij"].map(OsString::from);
+        let chunks: Vec<_> = chunk_inputs(&options, inputs.iter().cloned()).collect();
+        assert_eq!(chunks, vec![vec!["ab", "cd"], vec!["ef", "gh"], vec!["ij"]]);
+        assert!(command_line_size(&command_line(&options, 1, &chunks[0])) <= 32);
+
+        let options = Options {
+            nargs: Some(1),
+            ..options
+        };
+        assert_eq!(chunk_inputs(&options, inputs.iter().cloned()).count(), 5);
+    }
+
+    #[test]
+    fn test_chunk_inputs_max_lines() {
+        let options = Options {
+            nargs: None,
+            max_lines: Some(2),
+            ..test_options(Mode::Simple)
+        };
+        let inputs = ["a b", "c", "d e f"].map(OsString::from);
+        let chunks: Vec<_> = chunk_inputs(&options, inputs.into_iter()).collect();
+        assert_eq!(chunks, vec![vec!["a b", "c"], vec!["d e f"]]);
+    }
+
+    #[test]
+    fn test_shell_script() {
+        let options = Options {
+            program: "convert {} {.}.png".to_string(),
+            shell: true,
+            ..test_options(Mode::Simple)
+        };
+        assert_eq!(
+            command_line(&options, 1, &["my photo.jpg"]),
+            vec!["sh", "-c", "convert 'my photo.jpg' 'my photo'.png"]
+        );
+        assert_eq!(
+            command_line(&options, 1, &["$(reboot).jpg"]),
+            vec!["sh", "-c", "convert '$(reboot).jpg' '$(reboot)'.png"]
+        );
+        let appended = Options {
+            program: "wc".to_string(),
+            program_args: vec!["-l".to_string(), "|".to_string(), "sort".to_string()],
+            ..options
+        };
+        assert_eq!(
+            command_line(&appended, 1, &["a", "b c"]),
+            vec!["sh", "-c", "wc -l | sort a 'b c'"]
+        );
+    }
+
+    #[test]
+    fn test_child_args_quoted() {
+        let options = Options {
+            program: "ssh".to_string(),
+            program_args: vec!["host".to_string(), "cat {} > {/}.copy".to_string()],
+            quote: true,
+            ..test_options(Mode::Simple)
+        };
+        assert_eq!(
+            command_line(&options, 1, &["dir/it's $5"]),
+            vec!["ssh", "host", r"cat 'dir/it'\''s $5' > 'it'\''s $5'.copy"]
+        );
+        let appended = Options {
+            program_args: vec!["host".to_string(), "ls".to_string()],
+            ..options
+        };
+        assert_eq!(
+            command_line(&appended, 1, &["a b", "c"]),
+            vec!["ssh", "host", "ls", "'a b'", "c"]
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let status = |code| JobResult::exited(exit_status(code), false);
+        assert_eq!(exit_code(&[]), 0);
+        assert_eq!(exit_code(&[status(0), status(0)]), 0);
+        assert_eq!(exit_code(&[status(0), status(1)]), 123);
+        assert_eq!(exit_code(&[status(125), status(255)]), 124);
+        assert_eq!(exit_code(&[status(127), status(1)]), 127);
+        let timed_out = JobResult::exited(exit_status(0), true);
+        assert_eq!(exit_code(&[status(0), timed_out]), 123);
+        let not_found = JobResult::SpawnError(io::ErrorKind::NotFound);
+        assert_eq!(exit_code(&[not_found, status(255)]), 127);
+        let denied = JobResult::SpawnError(io::ErrorKind::PermissionDenied);
+        assert_eq!(exit_code(&[status(1), denied]), 126);
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            let signaled = JobResult::exited(process::ExitStatus::from_raw(libc::SIGKILL), false);
+            assert_eq!(exit_code(&[status(255), signaled]), 125);
+            assert_eq!(exit_code(&[signaled, status(126)]), 126);
         }
     }
 
+    #[test]
+    fn test_job_result() {
+        assert_eq!(JobResult::exited(exit_status(0), false), JobResult::Success);
+        let failed = JobResult::exited(exit_status(2), false);
+        assert_eq!(failed.status().code(), Some(2));
+        assert_eq!(failed.error(), None);
+        let timed_out = JobResult::exited(exit_status(0), true);
+        assert!(!timed_out.success());
+        assert_eq!(timed_out.error().as_deref(), Some("timed out"));
+        let not_found = JobResult::SpawnError(io::ErrorKind::NotFound);
+        assert_eq!(not_found.exit_status(), None);
+        assert_eq!(not_found.status().code(), Some(127));
+        assert_eq!(not_found.to_string(), "could not start: entity not found");
+    }
+
+    #[test]
+    fn test_parallel_not_found() {
+        let options = Options {
+            program: "/nonexistent/program".to_string(),
+            ..test_options(Mode::Parallel)
+        };
+        let results = Parallel
+            .execute(&options, Splitter::whitespace(MOCK_STDIN))
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results
+            .iter()
+            .all(|result| *result == JobResult::SpawnError(io::ErrorKind::NotFound)));
+        assert_eq!(exit_code(&results), 127);
+    }
+
+    #[test]
+    fn test_sequential_not_found() {
+        let options = Options {
+            program: "/nonexistent/program".to_string(),
+            ..test_options(Mode::Simple)
+        };
+        let results = Sequential
+            .execute(&options, Splitter::whitespace(MOCK_STDIN))
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(exit_code(&results), 127);
+    }
+
     #[test]
     fn test_sequential() {
         let start_time = Instant::now();
-        let statuses = Sequential
+        let results = Sequential
             .execute(
                 &test_options(Mode::Simple),
                 Splitter::whitespace(MOCK_STDIN),
             )
             .unwrap();
         let total_time = Instant::now() - start_time;
-        assert_eq!(statuses.len(), 3);
-        assert!(statuses.iter().all(|status| status.success()));
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.success()));
         // The total time should be *at least* the *sum* of all sleeps
         assert!(
             total_time >= Duration::from_secs_f64(TOTAL_SLEEP),
@@ -134,18 +1298,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_output_sequencer() {
+        let output = |text: &str| Output {
+            stdout: text.as_bytes().to_vec(),
+            stderr: vec![],
+        };
+        let stdout = |ready: Vec<Output>| -> Vec<Vec<u8>> {
+            ready.into_iter().map(|output| output.stdout).collect()
+        };
+        let mut sequencer = OutputSequencer::new(true);
+        assert!(sequencer.ready(1, output("b")).is_empty());
+        assert!(sequencer.ready(2, output("c")).is_empty());
+        assert_eq!(stdout(sequencer.ready(0, output("a"))), [b"a", b"b", b"c"]);
+        assert_eq!(stdout(sequencer.ready(3, output("d"))), [b"d"]);
+
+        let mut unordered = OutputSequencer::new(false);
+        assert_eq!(stdout(unordered.ready(1, output("b"))), [b"b"]);
+    }
+
     #[test]
     fn test_parallel() {
         let start_time = Instant::now();
-        let statuses = Parallel
+        let results = Parallel
             .execute(
                 &test_options(Mode::Parallel),
                 Splitter::whitespace(MOCK_STDIN),
             )
             .unwrap();
         let total_time = Instant::now() - start_time;
-        assert_eq!(statuses.len(), 3);
-        assert!(statuses.iter().all(|status| status.success()));
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.success()));
         // The total time should only be as long as the longest sleep
         // Testing for *less* than the *sum* of all sleeps to account for variable
         // system load
@@ -154,4 +1337,278 @@ mod tests {
             "{total_time:?}"
         );
     }
+
+    #[test]
+    fn test_sequential_timeout() {
+        let options = Options {
+            timeout: Some(Duration::from_secs_f64(0.1)),
+            ..test_options(Mode::Simple)
+        };
+        let start_time = Instant::now();
+        let results = Sequential
+            .execute(&options, Splitter::whitespace(&b"0 5"[..]))
+            .unwrap();
+        let total_time = Instant::now() - start_time;
+        assert_eq!(results.len(), 2);
+        assert!(results[0].success());
+        assert!(matches!(results[1], JobResult::TimedOut(_)));
+        assert!(total_time < Duration::from_secs(5), "{total_time:?}");
+    }
+
+    /// `first`, then no more inputs once `pause` has passed, like a slow stdin
+    fn slow_inputs(first: &str, pause: Duration) -> impl Iterator<Item = OsString> {
+        std::iter::once(first.into()).chain(std::iter::from_fn(move || {
+            std::thread::sleep(pause);
+            None
+        }))
+    }
+
+    /// How long after starting the first job finished
+    fn first_finished(executor: impl Executor, options: &Options) -> Duration {
+        let start_time = Instant::now();
+        let mut finished = None;
+        executor
+            .execute_with(
+                options,
+                slow_inputs("5", Duration::from_secs(3)),
+                &mut |event| {
+                    if matches!(event, Event::Finished { .. }) {
+                        finished.get_or_insert_with(|| start_time.elapsed());
+                    }
+                },
+            )
+            .unwrap();
+        finished.unwrap()
+    }
+
+    #[test]
+    fn test_sequential_timeout_slow_input() {
+        let options = Options {
+            timeout: Some(Duration::from_secs_f64(0.1)),
+            ..test_options(Mode::Simple)
+        };
+        let finished = first_finished(Sequential, &options);
+        assert!(finished < Duration::from_secs(2), "{finished:?}");
+    }
+
+    #[test]
+    fn test_parallel_timeout_slow_input() {
+        let options = Options {
+            timeout: Some(Duration::from_secs_f64(0.1)),
+            ..test_options(Mode::Parallel)
+        };
+        let finished = first_finished(Parallel, &options);
+        assert!(finished < Duration::from_secs(2), "{finished:?}");
+    }
+
+    #[test]
+    fn test_parallel_timeout() {
+        let options = Options {
+            timeout: Some(Duration::from_secs_f64(0.1)),
+            ..test_options(Mode::Parallel)
+        };
+        let start_time = Instant::now();
+        let results = Parallel
+            .execute(&options, Splitter::whitespace(&b"5 5"[..]))
+            .unwrap();
+        let total_time = Instant::now() - start_time;
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, JobResult::TimedOut(_))));
+        assert!(total_time < Duration::from_secs(5), "{total_time:?}");
+    }
+
+    #[test]
+    fn test_sequential_halt() {
+        let options = Options {
+            halt: "soon".parse().unwrap(),
+            ..test_options(Mode::Simple)
+        };
+        let results = Sequential
+            .execute(&options, Splitter::whitespace(&b"0 x 0"[..]))
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(!results[1].success());
+    }
+
+    #[test]
+    fn test_parallel_halt_now() {
+        let options = Options {
+            halt: "now".parse().unwrap(),
+            ..test_options(Mode::Parallel)
+        };
+        let start_time = Instant::now();
+        let results = Parallel
+            .execute(&options, Splitter::whitespace(&b"5 x"[..]))
+            .unwrap();
+        let total_time = Instant::now() - start_time;
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| !result.success()));
+        assert!(total_time < Duration::from_secs(5), "{total_time:?}");
+    }
+
+    #[test]
+    fn test_parallel_jobs_limit() {
+        let options = Options {
+            jobs: 1,
+            ..test_options(Mode::Parallel)
+        };
+        let start_time = Instant::now();
+        let results = Parallel
+            .execute(&options, Splitter::whitespace(MOCK_STDIN))
+            .unwrap();
+        let total_time = Instant::now() - start_time;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.success()));
+        // With only one job at a time, this is no faster than running sequentially
+        assert!(
+            total_time >= Duration::from_secs_f64(TOTAL_SLEEP),
+            "{total_time:?}"
+        );
+    }
+
+    /// Options for a job that fails until it has been run `succeed_on` times,
+    /// counting runs in the file given as its input
+    fn flaky_options(mode: Mode, retries: usize, succeed_on: usize) -> Options {
+        Options {
+            program: "sh".to_string(),
+            program_args: vec![
+                "-c".to_string(),
+                format!("echo >> \"$0\"; test $(wc -l < \"$0\") -ge {succeed_on}"),
+            ],
+            retries,
+            retry_delay: Duration::from_millis(10),
+            ..test_options(mode)
+        }
+    }
+
+    fn run_count(
+        name: &str,
+        execute: impl FnOnce(OsString) -> Vec<JobResult>,
+    ) -> (Vec<JobResult>, usize) {
+        let path = std::env::temp_dir().join(format!("arrgs-retry-{}-{name}", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let results = execute(path.clone().into());
+        let runs = std::fs::read_to_string(&path).unwrap().lines().count();
+        std::fs::remove_file(path).unwrap();
+        (results, runs)
+    }
+
+    #[test]
+    fn test_sequential_retries() {
+        let options = flaky_options(Mode::Simple, 2, 3);
+        let (results, runs) = run_count("sequential", |input| {
+            Sequential
+                .execute(&options, std::iter::once(input))
+                .unwrap()
+        });
+        assert_eq!(runs, 3);
+        assert!(results.iter().all(|result| result.success()));
+    }
+
+    #[test]
+    fn test_parallel_retries_exhausted() {
+        let options = flaky_options(Mode::Parallel, 1, 3);
+        let (results, runs) = run_count("parallel", |input| {
+            Parallel.execute(&options, std::iter::once(input)).unwrap()
+        });
+        assert_eq!(runs, 2);
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].success());
+    }
+
+    #[test]
+    fn test_closure() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+
+        let options = Options {
+            nargs: Some(2),
+            jobs: 2,
+            ..Default::default()
+        };
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let chunks = Mutex::new(vec![]);
+        let results = Closure(|inputs: &[OsString]| {
+            most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+            chunks.lock().unwrap().push(inputs.to_vec());
+            if inputs.iter().any(|input| input == "x") {
+                Err("not a number")
+            } else {
+                Ok(())
+            }
+        })
+        .execute(&options, Splitter::whitespace(&b"1 2 3 4 5 6 x"[..]))
+        .unwrap();
+
+        let mut chunks = chunks.into_inner().unwrap();
+        chunks.sort();
+        assert_eq!(
+            chunks,
+            [vec!["1", "2"], vec!["3", "4"], vec!["5", "6"], vec!["x"]]
+        );
+        assert_eq!(most.into_inner(), 2);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results.iter().filter(|result| !result.success()).count(), 1);
+        assert_eq!(exit_code(&results), 123);
+    }
+
+    #[test]
+    fn test_closure_retries_panics_and_logs() {
+        use std::sync::Mutex;
+
+        let joblog = std::env::temp_dir().join(format!("arrgs-closure-{}.log", process::id()));
+        let _ = std::fs::remove_file(&joblog);
+        let options = Options {
+            nargs: Some(1),
+            retries: 1,
+            joblog: Some(joblog.clone()),
+            ..Default::default()
+        };
+        let calls = Mutex::new(vec![]);
+        let function = |inputs: &[OsString]| {
+            let tries = {
+                let mut calls = calls.lock().unwrap();
+                calls.push(inputs[0].clone());
+                calls.iter().filter(|&call| *call == inputs[0]).count()
+            };
+            match inputs[0].to_str().unwrap() {
+                "f" if tries == 1 => Err("first try"),
+                "p" => panic!("can't handle {inputs:?}"),
+                _ => Ok(()),
+            }
+        };
+        let mut results = Closure(function)
+            .execute(&options, Splitter::whitespace(&b"ok f p"[..]))
+            .unwrap();
+        results.sort_by_key(|result| result.exit_status().and_then(|status| status.code()));
+        assert_eq!(
+            results,
+            [
+                JobResult::Success,
+                JobResult::Success,
+                JobResult::ExitCode(exit_status(101))
+            ]
+        );
+        assert_eq!(calls.lock().unwrap().len(), 5);
+        let log = std::fs::read_to_string(&joblog).unwrap();
+        // A header, then every call
+        assert_eq!(log.lines().count(), 6, "{log}");
+
+        // Only the new input is run when resuming
+        calls.lock().unwrap().clear();
+        let options = Options {
+            resume: true,
+            ..options
+        };
+        let results = Closure(function)
+            .execute(&options, Splitter::whitespace(&b"ok f p q"[..]))
+            .unwrap();
+        assert_eq!(results, [JobResult::Success]);
+        assert_eq!(*calls.lock().unwrap(), ["q"]);
+        std::fs::remove_file(joblog).unwrap();
+    }
 }
@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+/// What to do with the remaining jobs once too many have failed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HaltWhen {
+    /// Keep running every job
+    #[default]
+    Never,
+    /// Stop starting new jobs, but let running jobs finish
+    Soon,
+    /// Stop starting new jobs, and terminate running jobs
+    Now,
+}
+
+/// When to stop running jobs because of failures, parsed from `--halt`.
+///
+/// The policy is a comma-separated list of `never`, `soon` or `now`, and
+/// `fail=N` to tolerate up to `N` failures before halting. A bare `fail=N`
+/// implies `soon`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HaltPolicy {
+    pub when: HaltWhen,
+    pub tolerated_failures: usize,
+}
+
+impl HaltPolicy {
+    /// Whether `failures` failed jobs is enough to halt
+    pub fn is_triggered(&self, failures: usize) -> bool {
+        self.when != HaltWhen::Never && failures > self.tolerated_failures
+    }
+}
+
+impl FromStr for HaltPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut when = None;
+        let mut tolerated_failures = 0;
+        for part in value.split(',') {
+            match part {
+                "never" => when = Some(HaltWhen::Never),
+                "soon" => when = Some(HaltWhen::Soon),
+                "now" => when = Some(HaltWhen::Now),
+                _ => {
+                    tolerated_failures = part
+                        .strip_prefix("fail=")
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| format!("unknown halt policy `{part}`"))?;
+                    when.get_or_insert(HaltWhen::Soon);
+                }
+            }
+        }
+        Ok(Self {
+            when: when.unwrap_or_default(),
+            tolerated_failures,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("never".parse(), Ok(HaltPolicy::default()));
+        assert_eq!(
+            "now".parse(),
+            Ok(HaltPolicy {
+                when: HaltWhen::Now,
+                tolerated_failures: 0
+            })
+        );
+        assert_eq!(
+            "fail=3".parse(),
+            Ok(HaltPolicy {
+                when: HaltWhen::Soon,
+                tolerated_failures: 3
+            })
+        );
+        assert_eq!(
+            "now,fail=2".parse(),
+            Ok(HaltPolicy {
+                when: HaltWhen::Now,
+                tolerated_failures: 2
+            })
+        );
+        assert!("sometime".parse::<HaltPolicy>().is_err());
+        assert!("fail=x".parse::<HaltPolicy>().is_err());
+    }
+
+    #[test]
+    fn triggered() {
+        let never = HaltPolicy::default();
+        assert!(!never.is_triggered(100));
+        let soon: HaltPolicy = "soon".parse().unwrap();
+        assert!(!soon.is_triggered(0));
+        assert!(soon.is_triggered(1));
+        let tolerant: HaltPolicy = "fail=2".parse().unwrap();
+        assert!(!tolerant.is_triggered(2));
+        assert!(tolerant.is_triggered(3));
+    }
+}
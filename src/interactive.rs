@@ -4,21 +4,47 @@
 // - X [red text args] -----
 // lines of output
 
-use std::collections::VecDeque;
-use std::io::{BufRead, BufReader};
+use std::collections::{HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{PipeWriter, Read, Write};
 use std::ops::Deref;
-use std::process::{Command, Stdio};
-use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Context;
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
 use ratatui::prelude::*;
 use ratatui::widgets::{Block, Borders, Paragraph};
 use ratatui::DefaultTerminal;
 
-use crate::split_input::Splitter;
+use crate::config::{Action, Keys, Theme};
+use crate::exec::{
+    chunk_inputs, command_line, job_env, spawn_failure_code, JobResult, KILL_GRACE_PERIOD,
+};
+use crate::job::{retry_delay, Logs};
+use crate::naming::{job_file_name, log_directory, unused_path};
+#[cfg(unix)]
+use crate::platform::{exit_signal, signal_status};
+use crate::platform::{
+    kill, limit_resources, own_process_group, send_signal, term_is_limited, terminate, try_wait,
+    wait_readable, wake_pipe, CpuTime, PipeReader, TERMINATION_SIGNAL,
+};
+use crate::progress::format_duration;
+use crate::recording::{self, Recorder};
+use crate::signals::{self, ChildExits};
+use crate::{read_inputs, split_inputs, Inputs};
+
+/// How many lines each turn of the mouse wheel scrolls output by
+const MOUSE_SCROLL_LINES: i16 = 3;
 
 #[derive(Debug, Default)]
 struct App {
@@ -28,40 +54,315 @@ struct App {
     scroll_position: (u16, u16),
     wrap: bool,
     expanded: bool,
+    hide_stderr: bool,
+    /// Text searched for in the selected process's output
+    search: String,
+    /// Whether keys are being typed into the search
+    searching: bool,
+    /// Only show output lines that match the search
+    filter_lines: bool,
+    process_filter: ProcessFilter,
     max_lines: u16,
-    keys: VecDeque<KeyCode>,
+    /// How many of the listed processes are scrolled off the top
+    list_offset: usize,
+    /// Where each process was last drawn, for finding the one clicked on
+    process_areas: Vec<(usize, Rect)>,
+    logs: Arc<Mutex<Logs>>,
+    input_done: bool,
+    /// When the run started, for the elapsed time in the status bar
+    started: Option<Instant>,
+    /// What happened after the last key press, e.g. where output was saved
+    message: Option<String>,
+    /// The processes waiting for a free job slot, in the order they'll start
+    queue: VecDeque<usize>,
+    runner: Option<Runner>,
+    /// Whether the events come from a `--replay` recording rather than from
+    /// processes being run
+    replaying: bool,
+    /// Where the events are written with `--record`
+    recorder: Option<Recorder>,
+    /// `--jobs`, where 0 means no limit
+    jobs: usize,
+    keys: Keys,
+    theme: Theme,
 }
 
 enum AppEvent {
     KeyEvent(crossterm::event::KeyEvent),
-    Input(Vec<String>),
-    Output { pid: usize, lines: Vec<String> },
-    Exit { pid: usize, status: ProcessStatus },
+    MouseEvent(crossterm::event::MouseEvent),
+    Input(Vec<OsString>),
+    Started {
+        pid: usize,
+    },
+    Output {
+        pid: usize,
+        stream: Stream,
+        lines: Vec<String>,
+    },
+    Truncated {
+        pid: usize,
+    },
+    Retry {
+        pid: usize,
+        attempt: usize,
+    },
+    Kill {
+        pid: usize,
+    },
+    Restart {
+        pid: usize,
+    },
+    Exit {
+        pid: usize,
+        status: ProcessStatus,
+    },
+    InputDone,
+    /// Something went wrong on another thread, which has stopped
+    Error(anyhow::Error),
+}
+
+impl AppEvent {
+    /// The event as written to a `--record` file. Key presses and mouse
+    /// events aren't recorded, nor are kills and restarts, which show up as
+    /// the exits and starts that follow.
+    fn recorded(&self) -> Option<recording::Event> {
+        Some(match self {
+            AppEvent::Input(inputs) => recording::Event::Input {
+                inputs: inputs
+                    .iter()
+                    .map(|input| input.to_string_lossy().into_owned())
+                    .collect(),
+            },
+            AppEvent::InputDone => recording::Event::InputDone,
+            &AppEvent::Started { pid } => recording::Event::Started { pid },
+            AppEvent::Output { pid, stream, lines } => recording::Event::Output {
+                pid: *pid,
+                stream: match stream {
+                    Stream::Stdout => recording::Stream::Stdout,
+                    Stream::Stderr => recording::Stream::Stderr,
+                },
+                lines: lines.clone(),
+            },
+            &AppEvent::Truncated { pid } => recording::Event::Truncated { pid },
+            &AppEvent::Retry { pid, attempt } => recording::Event::Retry { pid, attempt },
+            AppEvent::Exit { pid, status } => recording::Event::Exit {
+                pid: *pid,
+                status: match status {
+                    ProcessStatus::Success => recording::Status::Success,
+                    ProcessStatus::Failure(code) => recording::Status::Failure(*code),
+                    #[cfg(unix)]
+                    ProcessStatus::Signal(status) => {
+                        recording::Status::Signal(exit_signal(*status).unwrap_or_default())
+                    }
+                    ProcessStatus::TimedOut => recording::Status::TimedOut,
+                    ProcessStatus::Killed => recording::Status::Killed,
+                    ProcessStatus::NotStarted(code) => recording::Status::NotStarted(*code),
+                },
+            },
+            AppEvent::KeyEvent(_)
+            | AppEvent::MouseEvent(_)
+            | AppEvent::Kill { .. }
+            | AppEvent::Restart { .. }
+            | AppEvent::Error(_) => return None,
+        })
+    }
+}
+
+/// A recorded event, as it's replayed
+impl From<recording::Event> for AppEvent {
+    fn from(event: recording::Event) -> Self {
+        match event {
+            recording::Event::Input { inputs } => {
+                AppEvent::Input(inputs.into_iter().map(OsString::from).collect())
+            }
+            recording::Event::InputDone => AppEvent::InputDone,
+            recording::Event::Started { pid } => AppEvent::Started { pid },
+            recording::Event::Output { pid, stream, lines } => AppEvent::Output {
+                pid,
+                stream: match stream {
+                    recording::Stream::Stdout => Stream::Stdout,
+                    recording::Stream::Stderr => Stream::Stderr,
+                },
+                lines,
+            },
+            recording::Event::Truncated { pid } => AppEvent::Truncated { pid },
+            recording::Event::Retry { pid, attempt } => AppEvent::Retry { pid, attempt },
+            recording::Event::Exit { pid, status } => AppEvent::Exit {
+                pid,
+                status: match status {
+                    recording::Status::Success => ProcessStatus::Success,
+                    recording::Status::Failure(code) => ProcessStatus::Failure(code),
+                    #[cfg(unix)]
+                    recording::Status::Signal(signal) => {
+                        ProcessStatus::Signal(signal_status(signal))
+                    }
+                    // Recorded on Unix, so shown as a shell reports it
+                    #[cfg(not(unix))]
+                    recording::Status::Signal(signal) => ProcessStatus::Failure(128 + signal),
+                    recording::Status::TimedOut => ProcessStatus::TimedOut,
+                    recording::Status::Killed => ProcessStatus::Killed,
+                    recording::Status::NotStarted(code) => ProcessStatus::NotStarted(code),
+                },
+            },
+        }
+    }
 }
 
 impl App {
-    fn run<R: std::io::Read + Send + 'static>(
+    fn run(
         &mut self,
         options: crate::Options,
         terminal: &mut DefaultTerminal,
-        input: &Arc<Mutex<R>>,
+        source: Source,
     ) -> anyhow::Result<()> {
         let (sender, mut receiver) = std::sync::mpsc::channel::<AppEvent>();
+        self.logs = Arc::new(Mutex::new(Logs::open(&options)?));
+        self.started = Some(Instant::now());
+        self.jobs = options.jobs;
+        self.keys = options.tui.keys.clone();
+        self.theme = options.tui.theme;
 
-        let _keyboard_thread = spawn_keyboard_events_thread(&sender);
-        let _input_thread = spawn_input_process(&sender, input, &options);
+        let _terminal_thread = spawn_terminal_events_thread(&sender);
+        let _source_thread = self.start(source, &sender, &options)?;
 
         while !self.exit {
             terminal.draw(|frame| {
                 self.max_lines = frame.area().height.saturating_sub(2);
+                self.scroll_list(frame.area().height.saturating_sub(1));
+                self.process_areas = self.process_areas(frame.area());
                 self.draw(frame)
             })?;
             self.handle_events(&mut receiver, &sender, &options)?;
+            // Quit gracefully on `SIGINT` or `SIGTERM`
+            self.exit |= signals::received().is_some();
+        }
+
+        Ok(())
+    }
+
+    /// Runs without a TUI, printing every event as a line of plain text. Used
+    /// for screen readers and terminals that can't display the TUI.
+    fn run_accessible(&mut self, options: crate::Options, source: Source) -> anyhow::Result<()> {
+        let (sender, receiver) = std::sync::mpsc::channel::<AppEvent>();
+        self.logs = Arc::new(Mutex::new(Logs::open(&options)?));
+        self.jobs = options.jobs;
+
+        let _source_thread = self.start(source, &sender, &options)?;
+
+        let mut stdout = std::io::stdout().lock();
+        while !(self.input_done && self.processes.iter().all(|p| p.status.is_some())) {
+            if signals::received().is_some() {
+                break;
+            }
+            // Blocking here (rather than polling) means we only ever print in
+            // response to something happening. The timeout is only so that
+            // termination signals are noticed.
+            let event = match receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            match &event {
+                AppEvent::Started { pid } => writeln!(
+                    stdout,
+                    "[started] #{pid}: {}",
+                    self.processes[*pid].display_args()
+                )?,
+                AppEvent::Output { pid, stream, lines } => {
+                    let separator = match stream {
+                        Stream::Stdout => '|',
+                        Stream::Stderr => '!',
+                    };
+                    for line in lines {
+                        writeln!(stdout, "#{pid}{separator} {}", line.trim_end_matches('\n'))?;
+                    }
+                }
+                AppEvent::Truncated { pid } => {
+                    if let Some(log_path) = &self.processes[*pid].log_path {
+                        writeln!(
+                            stdout,
+                            "#{pid}: … truncated, full log at {}",
+                            log_path.display()
+                        )?;
+                    }
+                }
+                AppEvent::Retry { pid, attempt } => {
+                    writeln!(stdout, "[retrying] #{pid}: attempt {attempt}")?;
+                }
+                AppEvent::Exit { pid, status } => writeln!(
+                    stdout,
+                    "[{}] #{pid} after {}: {}",
+                    status.label(),
+                    format_duration(self.processes[*pid].elapsed()),
+                    self.processes[*pid].display_args()
+                )?,
+                AppEvent::KeyEvent(_)
+                | AppEvent::MouseEvent(_)
+                | AppEvent::Input(_)
+                | AppEvent::InputDone
+                | AppEvent::Kill { .. }
+                | AppEvent::Restart { .. }
+                | AppEvent::Error(_) => {}
+            }
+            self.handle_event(event, &sender, &options)?;
         }
 
         Ok(())
     }
 
+    /// Starts the thread that sends the events for the processes: either
+    /// reading the inputs, for the runner thread to run the program for, or
+    /// playing back a recording. Opens the `--record` file too.
+    fn start(
+        &mut self,
+        source: Source,
+        tx: &Sender<AppEvent>,
+        options: &crate::Options,
+    ) -> anyhow::Result<JoinHandle<()>> {
+        self.recorder = options
+            .record
+            .as_deref()
+            .map(Recorder::create)
+            .transpose()?;
+        match source {
+            Source::Inputs(inputs) => {
+                self.runner = Some(spawn_runner_thread(tx, options, &self.logs)?);
+                Ok(spawn_input_process(tx, inputs, options))
+            }
+            Source::Replay(events) => {
+                self.replaying = true;
+                // The recording doesn't say how many processes could run at
+                // once
+                self.jobs = 0;
+                Ok(spawn_replay_thread(tx, events))
+            }
+        }
+    }
+
+    /// Stops the processes that are still running when quitting, so they
+    /// aren't left behind: passes on the termination signal that was caught
+    /// (or sends `SIGTERM`), then kills any still running after the grace
+    /// period
+    fn stop_processes(&self) {
+        let signal = signals::received().unwrap_or(TERMINATION_SIGNAL);
+        let running: Vec<_> = self
+            .processes
+            .iter()
+            .filter(|process| process.running())
+            .map(|process| &process.child)
+            .collect();
+        for child in &running {
+            child.signal(signal);
+        }
+        let deadline = Instant::now() + KILL_GRACE_PERIOD;
+        while running.iter().any(|child| child.is_running()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        for child in running {
+            child.kill();
+        }
+    }
+
     fn draw(&self, frame: &mut Frame) {
         frame.render_widget(self, frame.area());
     }
@@ -71,15 +372,10 @@ impl App {
         rx: &mut Receiver<AppEvent>,
         tx: &Sender<AppEvent>,
         options: &crate::Options,
-    ) -> std::io::Result<()> {
+    ) -> anyhow::Result<()> {
         loop {
             match rx.try_recv() {
-                Ok(event) => match event {
-                    AppEvent::KeyEvent(key_event) => self.handle_key_event(key_event),
-                    AppEvent::Input(inputs) => self.spawn_sub_process(inputs, tx, options),
-                    AppEvent::Output { pid, lines } => self.handle_output_event(pid, lines),
-                    AppEvent::Exit { pid, status } => self.handle_exit_event(pid, status),
-                },
+                Ok(event) => self.handle_event(event, tx, options)?,
                 Err(TryRecvError::Empty) => break,
                 Err(TryRecvError::Disconnected) => panic!("all event senders disconnected"),
             }
@@ -87,221 +383,1152 @@ impl App {
         Ok(())
     }
 
-    fn handle_key_event(&mut self, key_event: KeyEvent) {
+    /// Handles one event, first writing it to the `--record` file
+    ///
+    /// # Errors
+    /// Will return an error if the event cannot be recorded
+    fn handle_event(
+        &mut self,
+        event: AppEvent,
+        tx: &Sender<AppEvent>,
+        options: &crate::Options,
+    ) -> anyhow::Result<()> {
+        if let (Some(recorder), Some(recorded)) = (self.recorder.as_mut(), event.recorded()) {
+            recorder.record(&recorded)?;
+        }
+        match event {
+            AppEvent::KeyEvent(key_event) => self.handle_key_event(key_event, tx),
+            AppEvent::MouseEvent(mouse_event) => self.handle_mouse_event(mouse_event),
+            AppEvent::Input(inputs) => self.spawn_sub_process(inputs, tx, options),
+            AppEvent::Started { pid } if self.replaying => self.handle_replayed_start(pid),
+            AppEvent::Started { .. } => {}
+            AppEvent::Output { pid, stream, lines } => self.handle_output_event(pid, stream, lines),
+            AppEvent::Truncated { pid } => self.processes[pid].truncated = true,
+            AppEvent::Retry { pid, attempt } => self.processes[pid].attempt = attempt,
+            AppEvent::Exit { pid, status } => self.handle_exit_event(pid, status, tx, options),
+            AppEvent::Kill { .. } | AppEvent::Restart { .. } if self.replaying => {
+                self.message = Some("Processes can't be killed or restarted in a replay".into());
+            }
+            AppEvent::Kill { pid } => self.handle_kill_event(pid),
+            AppEvent::Restart { pid } => self.handle_restart_event(pid, tx, options),
+            AppEvent::InputDone => self.input_done = true,
+            // Quits, showing the error once the terminal has been restored
+            AppEvent::Error(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key_event: KeyEvent, tx: &Sender<AppEvent>) {
         if key_event.kind == KeyEventKind::Press {
-            self.keys.push_front(key_event.code);
-            self.keys.truncate(8);
-            match key_event.code {
-                KeyCode::Char('q') | KeyCode::Esc => self.exit = true,
-                KeyCode::Char('/') => {
+            // The terminal is in raw mode, so Ctrl-C is a key press rather
+            // than `SIGINT`
+            if key_event.code == KeyCode::Char('c')
+                && key_event.modifiers.contains(KeyModifiers::CONTROL)
+            {
+                self.exit = true;
+                return;
+            }
+            self.message = None;
+            if self.searching {
+                self.handle_search_key(key_event.code);
+                return;
+            }
+            let Some(action) = self.keys.action(key_event.code) else {
+                return;
+            };
+            match action {
+                Action::Quit => self.exit = true,
+                Action::Expand => {
                     self.expanded = !self.expanded;
                     self.reset_scroll_position();
                 }
-                KeyCode::Char('w') => self.wrap = !self.wrap,
-                KeyCode::PageUp => {
-                    self.selected = self
-                        .selected
-                        .saturating_sub(1)
-                        .min(self.processes.len().saturating_sub(1));
+                Action::Search => {
+                    self.searching = true;
+                    self.search.clear();
+                }
+                // Through the search matches if there's a search, otherwise
+                // through the failed processes
+                Action::NextMatch if !self.search.is_empty() => self.jump_to_match(true, false),
+                Action::PreviousMatch if !self.search.is_empty() => self.jump_to_match(true, true),
+                Action::NextMatch => self.select_failed(true),
+                Action::PreviousMatch => self.select_failed(false),
+                Action::FilterLines => {
+                    self.filter_lines = !self.filter_lines;
                     self.reset_scroll_position();
                 }
-                KeyCode::PageDown => {
-                    self.selected = self
-                        .selected
-                        .saturating_add(1)
-                        .min(self.processes.len().saturating_sub(1));
+                Action::FilterProcesses => {
+                    self.process_filter = self.process_filter.next();
+                    let filter = self.process_filter;
+                    if self
+                        .processes
+                        .get(self.selected)
+                        .is_some_and(|process| !filter.shows(process))
+                    {
+                        if let Some(&last) = self.visible_processes().last() {
+                            self.selected = last;
+                        }
+                    }
                     self.reset_scroll_position();
                 }
-                KeyCode::Up => {
-                    self.scroll_position.0 = self.scroll_position.0.saturating_sub(1).min(
-                        self.processes[self.selected]
-                            .output_lines
-                            .len()
-                            .saturating_sub(1) as u16,
-                    );
+                Action::Wrap => self.wrap = !self.wrap,
+                Action::Kill if !self.processes.is_empty() => {
+                    let _ = tx.send(AppEvent::Kill { pid: self.selected });
+                }
+                Action::Restart if !self.processes.is_empty() => {
+                    let _ = tx.send(AppEvent::Restart { pid: self.selected });
                 }
-                KeyCode::Down => {
-                    self.scroll_position.0 = self.scroll_position.0.saturating_add(1).min(
-                        self.processes[self.selected]
-                            .output_lines
-                            .len()
-                            .saturating_sub(1) as u16,
+                // Only once all of the inputs have been read
+                Action::RestartFailed if self.input_done => {
+                    for pid in (0..self.processes.len()).filter(|&i| self.processes[i].failed()) {
+                        let _ = tx.send(AppEvent::Restart { pid });
+                    }
+                }
+                Action::RestartFailed => {
+                    self.message = Some(
+                        "Still reading input; failed processes can be restarted once all jobs \
+                         have started"
+                            .into(),
                     );
                 }
-                KeyCode::Left => {
+                Action::Save if !self.processes.is_empty() => self.save_selected(),
+                Action::SaveAll if !self.processes.is_empty() => self.save_all(),
+                Action::HideStderr => {
+                    self.hide_stderr = !self.hide_stderr;
+                    self.reset_scroll_position();
+                }
+                Action::Previous => self.select_adjacent(false),
+                Action::Next => self.select_adjacent(true),
+                Action::ScrollUp => self.scroll_output(-1),
+                Action::ScrollDown => self.scroll_output(1),
+                Action::ScrollLeft => {
                     self.scroll_position.1 = if self.wrap {
                         0
                     } else {
                         self.scroll_position.1.saturating_sub(4)
                     };
                 }
-                KeyCode::Right => {
+                Action::ScrollRight => {
                     self.scroll_position.1 = if self.wrap {
                         0
                     } else {
                         self.scroll_position.1.saturating_add(4)
                     };
                 }
-                KeyCode::Home => {
+                Action::ScrollToTop => {
                     self.scroll_position.0 = 0;
                 }
-                KeyCode::End => {
-                    self.scroll_position.0 = self.processes[self.selected]
-                        .output_lines
-                        .len()
-                        .saturating_sub(1) as u16;
+                Action::ScrollToBottom => {
+                    self.scroll_position.0 = self.selected_line_count().saturating_sub(1) as u16;
                 }
                 _ => {}
             }
         }
     }
 
+    /// Clicking a process selects it, and clicking its title also toggles the
+    /// expanded view. The scroll wheel scrolls the selected process's output.
+    fn handle_mouse_event(&mut self, mouse_event: MouseEvent) {
+        match mouse_event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let position = Position::new(mouse_event.column, mouse_event.row);
+                let Some(&(pid, area)) = self
+                    .process_areas
+                    .iter()
+                    .find(|(_, area)| area.contains(position))
+                else {
+                    return;
+                };
+                self.message = None;
+                if position.y == area.y {
+                    self.expanded = !self.expanded;
+                } else if pid == self.selected {
+                    return;
+                }
+                self.selected = pid;
+                self.reset_scroll_position();
+            }
+            MouseEventKind::ScrollUp => self.scroll_output(-MOUSE_SCROLL_LINES),
+            MouseEventKind::ScrollDown => self.scroll_output(MOUSE_SCROLL_LINES),
+            _ => {}
+        }
+    }
+
+    /// Scrolls the selected process's output down by `lines`, or up if it's
+    /// negative
+    fn scroll_output(&mut self, lines: i16) {
+        if self.processes.is_empty() {
+            return;
+        }
+        self.scroll_position.0 = self
+            .scroll_position
+            .0
+            .saturating_add_signed(lines)
+            .min(self.selected_line_count().saturating_sub(1) as u16);
+    }
+
+    /// Edits the search as it's typed, jumping to the first match as it
+    /// changes. Enter keeps the search, Esc clears it.
+    fn handle_search_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.searching = false;
+                self.search.clear();
+            }
+            KeyCode::Backspace => {
+                self.search.pop();
+            }
+            KeyCode::Char(c) => self.search.push(c),
+            _ => return,
+        }
+        if self.filter_lines {
+            self.reset_scroll_position();
+        } else {
+            self.jump_to_match(false, false);
+        }
+    }
+
+    /// Scrolls the selected process to the nearest output line matching the
+    /// search, wrapping around at either end. `skip_current` starts looking
+    /// from the line after (or before) the current one.
+    fn jump_to_match(&mut self, skip_current: bool, backward: bool) {
+        if self.search.is_empty() || self.processes.is_empty() {
+            return;
+        }
+        let matches: Vec<bool> = self.processes[self.selected]
+            .visible_lines(self.line_filter())
+            .map(|line| line.text.contains(&self.search))
+            .collect();
+        let count = matches.len();
+        if count == 0 {
+            return;
+        }
+        let current = usize::from(self.scroll_position.0) % count;
+        let found = (0..count)
+            .map(|i| match (backward, skip_current) {
+                (true, true) => (current + 2 * count - 1 - i) % count,
+                (true, false) => (current + count - i) % count,
+                (false, true) => (current + 1 + i) % count,
+                (false, false) => (current + i) % count,
+            })
+            .find(|&i| matches[i]);
+        if let Some(line) = found {
+            self.scroll_position.0 = line as u16;
+        }
+    }
+
+    /// Indexes of the processes shown by the current process filter
+    fn visible_processes(&self) -> Vec<usize> {
+        (0..self.processes.len())
+            .filter(|&i| self.process_filter.shows(&self.processes[i]))
+            .collect()
+    }
+
+    /// Selects the next (or previous) process shown by the process filter
+    fn select_adjacent(&mut self, forward: bool) {
+        let visible = self.visible_processes();
+        let next = if forward {
+            visible.iter().find(|&&i| i > self.selected)
+        } else {
+            visible.iter().rev().find(|&&i| i < self.selected)
+        };
+        if let Some(&next) = next {
+            self.selected = next;
+        }
+        self.reset_scroll_position();
+    }
+
+    /// Selects the next (or previous) failed process shown by the process
+    /// filter, wrapping around at either end
+    fn select_failed(&mut self, forward: bool) {
+        let visible = self.visible_processes();
+        let failed = |&&i: &&usize| self.processes[i].failed();
+        let next = if forward {
+            let after = visible.iter().filter(|&&i| i > self.selected);
+            after.chain(&visible).find(failed)
+        } else {
+            let before = visible.iter().rev().filter(|&&i| i < self.selected);
+            before.chain(visible.iter().rev()).find(failed)
+        };
+        if let Some(&next) = next {
+            self.selected = next;
+            self.reset_scroll_position();
+        }
+    }
+
+    /// Scrolls the process list, `height` lines tall, just enough that the
+    /// selected process is in view
+    fn scroll_list(&mut self, height: u16) {
+        let visible = self.visible_processes();
+        let Some(position) = visible.iter().position(|&i| i == self.selected) else {
+            return;
+        };
+        let mut offset = self.list_offset.min(position);
+        let mut heights: VecDeque<usize> = visible[offset..=position]
+            .iter()
+            .map(|&i| usize::from(self.process_widget(i).height()))
+            .collect();
+        while offset < position && heights.iter().sum::<usize>() > usize::from(height) {
+            heights.pop_front();
+            offset += 1;
+        }
+        self.list_offset = offset;
+    }
+
+    /// Where each process shown is drawn: only the selected one when
+    /// expanded, otherwise those that fit from the first one scrolled into
+    /// view
+    fn process_areas(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let [_, mut list_area] = split_status_area(area);
+        if self.expanded {
+            return if self.processes.is_empty() {
+                vec![]
+            } else {
+                vec![(self.selected, list_area)]
+            };
+        }
+        let mut areas = vec![];
+        for &i in self.visible_processes().iter().skip(self.list_offset) {
+            if list_area.is_empty() {
+                break;
+            }
+            let height = self.process_widget(i).height();
+            let [process_area, rest] =
+                Layout::vertical([Constraint::Max(height), Constraint::Fill(1)]).areas(list_area);
+            areas.push((i, process_area));
+            list_area = rest;
+        }
+        areas
+    }
+
+    /// Saves the selected process's output to a file in the current
+    /// directory
+    fn save_selected(&mut self) {
+        let process = &self.processes[self.selected];
+        let path = unused_path(Path::new(&job_file_name(self.selected + 1, &process.args)));
+        self.message = Some(match process.save(&path) {
+            Ok(()) => format!("Saved output to {}", path.display()),
+            Err(e) => format!("Couldn't save output to {}: {e}", path.display()),
+        });
+    }
+
+    /// Saves every process's output to a file of its own, in a new
+    /// directory in the current one
+    fn save_all(&mut self) {
+        let dir = unused_path(Path::new("arrgs-output"));
+        let saved = std::fs::create_dir(&dir).and_then(|()| {
+            self.processes
+                .iter()
+                .enumerate()
+                .try_for_each(|(pid, process)| {
+                    process.save(&dir.join(job_file_name(pid + 1, &process.args)))
+                })
+        });
+        self.message = Some(match saved {
+            Ok(()) => format!("Saved the output of every process to {}", dir.display()),
+            Err(e) => format!("Couldn't save output to {}: {e}", dir.display()),
+        });
+    }
+
+    fn process_widget(&self, i: usize) -> ProcessWidget<'_> {
+        let selected = i == self.selected;
+        ProcessWidget {
+            process: &self.processes[i],
+            scroll_position: selected.then_some(self.scroll_position),
+            wrap: self.wrap,
+            filter: self.line_filter(),
+            highlight: Some(self.search.as_str()).filter(|search| selected && !search.is_empty()),
+            queue_position: self.queue.iter().position(|&queued| queued == i),
+            theme: self.theme,
+        }
+    }
+
+    fn line_filter(&self) -> LineFilter<'_> {
+        LineFilter {
+            hide_stderr: self.hide_stderr,
+            matching: (self.filter_lines && !self.search.is_empty())
+                .then_some(self.search.as_str()),
+        }
+    }
+
+    /// The status bar above the process list: the search being typed, or
+    /// how many processes are running, have succeeded and have failed, how
+    /// long the run has taken and which process is selected, along with any
+    /// active search and filters
+    fn status_line(&self) -> Line<'_> {
+        if self.searching {
+            return Line::from(format!("/{}", self.search));
+        }
+        let count = |matches: fn(&ProcessStatus) -> bool| {
+            self.processes
+                .iter()
+                .filter(|process| process.status.as_ref().is_some_and(matches))
+                .count()
+        };
+        let queued = self.queue.len();
+        let running = self.processes.len() - count(|_| true) - queued;
+        let succeeded = count(|status| *status == ProcessStatus::Success);
+        let failed = count(|status| *status != ProcessStatus::Success);
+        let elapsed = self
+            .started
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        let more = if self.input_done { "" } else { "+" };
+        let limit = match self.jobs {
+            0 => String::new(),
+            jobs => format!("/{jobs}"),
+        };
+        let mut spans = vec![
+            Span::styled(format!("{running}{limit} running"), self.theme.highlight),
+            Span::raw("  "),
+            Span::styled(format!("{queued} queued"), Color::DarkGray),
+            Span::raw("  "),
+            Span::styled(format!("{succeeded} succeeded"), self.theme.succeeded),
+            Span::raw("  "),
+            Span::styled(format!("{failed} failed"), self.theme.failed),
+            Span::raw(format!(
+                "  of {}{more}  {}",
+                self.processes.len(),
+                format_duration(elapsed)
+            )),
+        ];
+        if !self.processes.is_empty() {
+            spans.push(Span::raw(format!("  #{} selected", self.selected)));
+        }
+        if !self.search.is_empty() {
+            spans.push(Span::raw(format!("  Search: {}", self.search)));
+            if self.filter_lines {
+                spans.push(Span::raw(" (filtered)"));
+            }
+        }
+        if self.process_filter != ProcessFilter::All {
+            spans.push(Span::raw(format!("  Showing: {}", self.process_filter)));
+        }
+        if let Some(message) = &self.message {
+            spans.push(Span::styled(format!("  {message}"), Color::Cyan));
+        }
+        Line::from(spans)
+    }
+
     fn spawn_sub_process(
         &mut self,
-        inputs: Vec<String>,
+        inputs: Vec<OsString>,
         tx: &Sender<AppEvent>,
         options: &crate::Options,
     ) {
-        let pid = self.processes.len();
+        // Follow the newest process, unless another one has been selected
+        let follow = self.selected + 1 >= self.processes.len();
+        self.queue.push_back(self.processes.len());
+        self.processes.push(Process::queued(inputs));
+        if follow {
+            self.selected = self.processes.len() - 1;
+        }
+        self.start_queued(tx, options);
+    }
+
+    /// Starts queued processes, in order, while fewer than `--jobs` are
+    /// running. In a replay, they're started by the recording instead.
+    fn start_queued(&mut self, tx: &Sender<AppEvent>, options: &crate::Options) {
+        if self.replaying {
+            return;
+        }
+        let mut running = self.processes.iter().filter(|p| p.running()).count();
+        while self.jobs == 0 || running < self.jobs {
+            let Some(pid) = self.queue.pop_front() else {
+                break;
+            };
+            // Sent first, so that it comes before any of the process's output
+            let _ = tx.send(AppEvent::Started { pid });
+            let inputs = self.processes[pid].args.clone();
+            self.processes[pid] = self.start_process(pid, inputs, tx, options);
+            running += 1;
+        }
+    }
+
+    /// Shows a process as started, when the recording being replayed says it
+    /// did. Restarting a process that has finished clears its output.
+    fn handle_replayed_start(&mut self, pid: usize) {
+        self.queue.retain(|&queued| queued != pid);
+        let process = &mut self.processes[pid];
+        *process = Process {
+            queued: false,
+            ..Process::queued(std::mem::take(&mut process.args))
+        };
+        if self.selected == pid {
+            self.reset_scroll_position();
+        }
+    }
+
+    /// Kills a running process, or takes a queued one off the queue
+    fn handle_kill_event(&mut self, pid: usize) {
+        let process = &mut self.processes[pid];
+        if process.queued {
+            self.queue.retain(|&queued| queued != pid);
+            process.queued = false;
+            process.status = Some(ProcessStatus::Killed);
+            process.finished = Some(process.started);
+        } else {
+            self.kill_child(pid);
+        }
+    }
+
+    /// Kills a process's child, or if it's waiting to be retried, cancels the
+    /// retry
+    fn kill_child(&self, pid: usize) {
+        self.processes[pid].child.kill();
+        if let Some(runner) = &self.runner {
+            runner.wake();
+        }
+    }
+
+    /// Has the runner thread run the program for `inputs` (retrying as
+    /// configured), reporting its output and exit as events for `pid`
+    fn start_process(
+        &self,
+        pid: usize,
+        inputs: Vec<OsString>,
+        tx: &Sender<AppEvent>,
+        options: &crate::Options,
+    ) -> Process {
         let args = inputs.clone();
-        let process_tx = tx.clone();
-        let options = options.clone();
-        let handle = std::thread::spawn(move || {
-            let mut child = Command::new(&options.program)
-                .args(&options.program_args)
-                .args(inputs)
-                .stdout(Stdio::piped())
-               // .stderr(Stdio::piped())
-                .spawn()
-                .expect("could not spawn output process");
-            let mut stdout = child.stdout.take().map(BufReader::new).unwrap();
-            loop {
-                match child.try_wait() {
-                    Ok(Some(status)) => {
-                        // Read the rest of stdout
-                        let mut buffer = String::new();
-                        while let Ok(amount) = stdout.read_line(&mut buffer) {
-                            if amount == 0 {
-                                break;
-                            }
-                            let _ = process_tx.send(AppEvent::Output {
-                                pid,
-                                lines: vec![buffer.clone()],
-                            });
-                            buffer.clear();
-                        }
-                        // Capture the exit status
-                        let process_status = if status.success() {
-                            ProcessStatus::Success
-                        } else {
-                            status
-                                .code()
-                                .map(ProcessStatus::Failure)
-                                .unwrap_or_else(|| ProcessStatus::Signal(status))
-                        };
-                        let _ = process_tx.send(AppEvent::Exit {
-                            pid,
-                            status: process_status,
-                        });
-                        break;
-                    }
-                    Ok(None) => {
-                        // TODO: handle stderr
-                        // Read stdout for output
-                        let mut buffer = String::new();
-                        if let Ok(amount) = stdout.read_line(&mut buffer) {
-                            if amount == 0 {
-                                continue;
-                            }
-                            let _ = process_tx.send(AppEvent::Output {
-                                pid,
-                                lines: vec![buffer],
-                            });
-                        }
-                    }
-                    Err(e) => {
-                        panic!("could not wait on subprocess {e}")
-                    }
-                }
-            }
-        });
-        self.processes.push(Process {
+        // The lowest slot that no running process has
+        let used: HashSet<usize> = self
+            .processes
+            .iter()
+            .filter(|process| process.running())
+            .map(|process| process.slot)
+            .collect();
+        let slot = (1..).find(|slot| !used.contains(slot)).unwrap_or_default();
+        let log_path = options
+            .max_capture_bytes
+            .map(|_| log_directory().join(format!("{pid}.log")));
+        let capture = OutputCapture::new(pid, tx.clone(), log_path.as_deref(), options);
+        let child = ChildHandle::default();
+        self.runner
+            .as_ref()
+            .expect("the runner thread has started")
+            .start(Run::new(inputs, slot, capture, child.clone()));
+        Process {
             args,
             output_lines: Default::default(),
             status: None,
-            handle: Some(handle),
-        });
-        self.selected = self.processes.len() - 1;
+            started: Instant::now(),
+            finished: None,
+            child,
+            restart: false,
+            log_path,
+            truncated: false,
+            attempt: 1,
+            slot,
+            queued: false,
+        }
+    }
+
+    /// Re-runs a process with the same inputs, replacing its output, by
+    /// queueing it again. A process that's still running is killed first,
+    /// and queued once it has exited.
+    fn handle_restart_event(
+        &mut self,
+        pid: usize,
+        tx: &Sender<AppEvent>,
+        options: &crate::Options,
+    ) {
+        if self.processes[pid].queued {
+            return;
+        }
+        if self.processes[pid].running() {
+            self.processes[pid].restart = true;
+            self.kill_child(pid);
+            return;
+        }
+        let inputs = self.processes[pid].args.clone();
+        self.processes[pid] = Process::queued(inputs);
+        self.queue.push_back(pid);
+        self.start_queued(tx, options);
+        if self.selected == pid {
+            self.reset_scroll_position();
+        }
     }
 
-    fn handle_output_event(&mut self, pid: usize, lines: Vec<String>) {
-        self.processes[pid].output_lines.extend(lines);
+    fn handle_output_event(&mut self, pid: usize, stream: Stream, lines: Vec<String>) {
+        self.processes[pid]
+            .output_lines
+            .extend(lines.into_iter().map(|text| OutputLine { stream, text }));
         if self.selected == pid {
             self.reset_scroll_position();
         }
     }
 
-    fn handle_exit_event(&mut self, pid: usize, status: ProcessStatus) {
+    fn handle_exit_event(
+        &mut self,
+        pid: usize,
+        status: ProcessStatus,
+        tx: &Sender<AppEvent>,
+        options: &crate::Options,
+    ) {
         self.processes[pid].status = Some(status);
-        // TODO: maybe handle when a child thread panics?
-        let _ = self.processes[pid].handle.take().unwrap().join();
+        self.processes[pid].finished = Some(Instant::now());
+        if self.processes[pid].restart {
+            self.handle_restart_event(pid, tx, options);
+        } else {
+            self.start_queued(tx, options);
+        }
+    }
+
+    /// Number of output lines the selected process is showing
+    fn selected_line_count(&self) -> usize {
+        self.processes[self.selected]
+            .visible_lines(self.line_filter())
+            .count()
     }
 
     fn reset_scroll_position(&mut self) {
+        if self.processes.is_empty() {
+            return;
+        }
         self.scroll_position = (
             if self.expanded {
                 // We want to display the last N lines of the output
                 // (where N is the height of the pane that we're rendering into)
-                (self.processes[self.selected].output_lines.len() as u16)
-                    .saturating_sub(self.max_lines)
+                (self.selected_line_count() as u16).saturating_sub(self.max_lines)
             } else {
-                self.processes[self.selected]
-                    .output_lines
-                    .len()
-                    .saturating_sub(5) as u16
+                self.selected_line_count().saturating_sub(5) as u16
             },
             0,
         );
     }
 }
 
-fn spawn_keyboard_events_thread(sender: &Sender<AppEvent>) -> JoinHandle<()> {
+/// The main thread's end of the runner thread, see [`spawn_runner_thread`]
+#[derive(Debug)]
+struct Runner {
+    runs: Sender<Run>,
+    /// Written to after sending a run, to wake the runner thread
+    waker: PipeWriter,
+}
+
+impl Runner {
+    fn start(&self, run: Run) {
+        let _ = self.runs.send(run);
+        self.wake();
+    }
+
+    /// Has the runner thread check on its processes, e.g. one that was
+    /// killed while waiting to be retried
+    fn wake(&self) {
+        // If the pipe is full, there's already a wakeup pending
+        let _ = (&self.waker).write(&[0]);
+    }
+}
+
+/// Starts the thread that runs every process: a single thread however many
+/// run at once, which waits (see [`wait_readable`]) for their output, their
+/// exits, new processes to start, and the deadlines for `--timeout` and
+/// retries
+fn spawn_runner_thread(
+    tx: &Sender<AppEvent>,
+    options: &crate::Options,
+    logs: &Arc<Mutex<Logs>>,
+) -> std::io::Result<Runner> {
+    let (runs_tx, runs) = std::sync::mpsc::channel();
+    let (mut wake, waker) = wake_pipe()?;
+    // Set up before any child starts, so that no exits are missed
+    let exits = ChildExits::new()?;
+    let options = options.clone();
+    let logs = Arc::clone(logs);
+    let tx = tx.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = run_processes(&runs, &mut wake, &exits, &options, &logs) {
+            // Otherwise the app would go on waiting for events that never come
+            let _ = tx.send(AppEvent::Error(e));
+        }
+    });
+    Ok(Runner {
+        runs: runs_tx,
+        waker,
+    })
+}
+
+/// The runner thread's loop, until the app has quit
+///
+/// # Errors
+/// Will return an error if a log cannot be written to, or a child cannot be
+/// waited on
+fn run_processes(
+    runs: &Receiver<Run>,
+    wake: &mut PipeReader,
+    exits: &ChildExits,
+    options: &crate::Options,
+    logs: &Mutex<Logs>,
+) -> anyhow::Result<()> {
+    let mut running: Vec<Run> = vec![];
+    loop {
+        loop {
+            match runs.try_recv() {
+                Ok(run) => running.push(run),
+                Err(TryRecvError::Empty) => break,
+                // The app has quit
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        // Start the attempts that are due: new processes, and retries
+        // once their delay is over. A process killed during the delay
+        // finishes without waiting it out.
+        let now = Instant::now();
+        for run in &mut running {
+            let RunState::Waiting { until } = run.state else {
+                continue;
+            };
+            if until <= now || run.child.is_killed() {
+                run.start_attempt(options, logs)?;
+            }
+        }
+        running.retain(|run| !matches!(run.state, RunState::Finished));
+
+        // Wait for output, a child to exit, a process to start, or a
+        // timeout or retry delay to end
+        let mut readers = vec![&*wake];
+        let mut pipes = vec![];
+        for (i, run) in running.iter().enumerate() {
+            if let RunState::Running(attempt) = &run.state {
+                for pipe in [&attempt.stdout, &attempt.stderr] {
+                    if let Some(reader) = &pipe.reader {
+                        readers.push(reader);
+                        pipes.push((i, pipe.stream));
+                    }
+                }
+            }
+        }
+        let deadline = running.iter().filter_map(|run| run.deadline(options)).min();
+        let ready = wait_readable(exits, &readers, deadline);
+        while wake.read(&mut [0; 64]).is_ok_and(|n| n > 0) {}
+        for (&(i, stream), _) in pipes.iter().zip(&ready[1..]).filter(|(_, &ready)| ready) {
+            running[i].read_output(stream, false);
+        }
+
+        // Check on the children that have exited, one at a time since
+        // polling reaps the child. Every child is checked once we've been
+        // signalled, as are those that are due.
+        let mut poll_all = !signals::REPORTS_EXITS || signals::received().is_some();
+        while let Some(pid) = exits.next_exited() {
+            let Some(run) = running.iter_mut().find(|run| run.child.id() == Some(pid)) else {
+                // Not one of ours, so it won't be reaped, and would be
+                // reported again. Check on every child instead.
+                poll_all = true;
+                break;
+            };
+            run.poll(options, logs)?;
+        }
+        let now = Instant::now();
+        for run in &mut running {
+            if poll_all
+                || run
+                    .deadline(options)
+                    .is_some_and(|deadline| deadline <= now)
+            {
+                run.poll(options, logs)?;
+            }
+        }
+    }
+}
+
+/// A process on the runner thread, through each of its attempts
+struct Run {
+    inputs: Vec<OsString>,
+    slot: usize,
+    capture: OutputCapture,
+    child: ChildHandle,
+    /// The attempt that's running, or the number of the last one
+    attempt: usize,
+    state: RunState,
+}
+
+enum RunState {
+    /// Ready to start the next attempt once the retry delay is over, or
+    /// straight away for the first
+    Waiting {
+        until: Instant,
+    },
+    Running(Attempt),
+    Finished,
+}
+
+/// One run of the program: its output, and what's needed to record it and
+/// enforce `--timeout`
+struct Attempt {
+    command: Vec<OsString>,
+    start: SystemTime,
+    started: Instant,
+    /// When the child was sent `SIGTERM` for running past the timeout
+    terminated: Option<Instant>,
+    stdout: OutputPipe,
+    stderr: OutputPipe,
+}
+
+impl Run {
+    fn new(inputs: Vec<OsString>, slot: usize, capture: OutputCapture, child: ChildHandle) -> Self {
+        Self {
+            inputs,
+            slot,
+            capture,
+            child,
+            attempt: 0,
+            state: RunState::Waiting {
+                until: Instant::now(),
+            },
+        }
+    }
+
+    /// Starts the program for the next attempt, unless the process has been
+    /// killed in the meantime
+    ///
+    /// # Errors
+    /// Will return an error if the program can't be started and that can't
+    /// be logged
+    fn start_attempt(
+        &mut self,
+        options: &crate::Options,
+        logs: &Mutex<Logs>,
+    ) -> anyhow::Result<()> {
+        if self.child.is_killed() {
+            self.finish(ProcessStatus::Killed);
+            return Ok(());
+        }
+        self.attempt += 1;
+        if self.attempt > 1 {
+            self.capture.retry(self.attempt);
+        }
+        let start = SystemTime::now();
+        let seq = self.capture.pid + 1;
+        let command = command_line(options, seq, &self.inputs);
+        let mut command_builder = Command::new(&command[0]);
+        own_process_group(&mut command_builder).args(&command[1..]);
+        limit_resources(&mut command_builder, options);
+        let spawned = job_env(&mut command_builder, options, seq, self.slot, &self.inputs)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                let stdout = PipeReader::new(child.stdout.take().unwrap());
+                let stderr = PipeReader::new(child.stderr.take().unwrap());
+                let stdout = OutputPipe::new(Stream::Stdout, stdout);
+                let stderr = OutputPipe::new(Stream::Stderr, stderr);
+                self.child.set(child);
+                self.state = RunState::Running(Attempt {
+                    command,
+                    start,
+                    started: Instant::now(),
+                    terminated: None,
+                    stdout,
+                    stderr,
+                });
+            }
+            Err(e) => {
+                logs.lock().unwrap().record(
+                    seq,
+                    &self.inputs,
+                    &command,
+                    start,
+                    JobResult::spawn_error(&e),
+                    None,
+                )?;
+                let message = format!("could not start {}: {e}\n", command[0].to_string_lossy());
+                self.capture.lines(Stream::Stderr, vec![message]);
+                self.finish(ProcessStatus::NotStarted(
+                    spawn_failure_code(e.kind()).into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// When the process next needs to be polled, even if its child hasn't
+    /// exited: when it times out, its grace period after being terminated
+    /// ends, or its retry delay is over
+    fn deadline(&self, options: &crate::Options) -> Option<Instant> {
+        match &self.state {
+            RunState::Waiting { until } => Some(*until),
+            RunState::Running(attempt) => match attempt.terminated {
+                Some(terminated) => terminated.checked_add(KILL_GRACE_PERIOD),
+                None => attempt.started.checked_add(options.timeout?),
+            },
+            RunState::Finished => None,
+        }
+    }
+
+    /// Forwards what the child has written to `stream`, or everything that's
+    /// left once it has exited
+    fn read_output(&mut self, stream: Stream, exited: bool) {
+        let RunState::Running(attempt) = &mut self.state else {
+            return;
+        };
+        let pipe = match stream {
+            Stream::Stdout => &mut attempt.stdout,
+            Stream::Stderr => &mut attempt.stderr,
+        };
+        self.capture.lines(stream, pipe.read_lines(exited));
+    }
+
+    /// Checks whether the child has exited, without blocking, and if so
+    /// finishes the process or waits to retry it. Children that run past the
+    /// timeout are sent `SIGTERM`, then `SIGKILL` if they still haven't
+    /// exited after the [`KILL_GRACE_PERIOD`].
+    ///
+    /// # Errors
+    /// Will return an error if the child can't be waited on, or the logs
+    /// can't be written to
+    fn poll(&mut self, options: &crate::Options, logs: &Mutex<Logs>) -> anyhow::Result<()> {
+        let RunState::Running(attempt) = &mut self.state else {
+            return Ok(());
+        };
+        let (status, cpu_time) = match self.child.try_wait() {
+            Ok(Some(exited)) => exited,
+            Ok(None) => {
+                let pid = self.child.id().unwrap_or_default();
+                match (options.timeout, attempt.terminated) {
+                    (Some(timeout), None) if attempt.started.elapsed() >= timeout => {
+                        terminate(pid);
+                        attempt.terminated = Some(Instant::now());
+                    }
+                    (_, Some(terminated)) if terminated.elapsed() >= KILL_GRACE_PERIOD => {
+                        kill(pid);
+                    }
+                    _ => {}
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                let command = crate::shell::join(&attempt.command);
+                return Err(anyhow::Error::new(e).context(format!("waiting for {command}")));
+            }
+        };
+        let timed_out = attempt.terminated.is_some();
+        logs.lock().unwrap().record(
+            self.capture.pid + 1,
+            &self.inputs,
+            &attempt.command,
+            attempt.start,
+            JobResult::exited(status, timed_out),
+            cpu_time,
+        )?;
+        self.read_output(Stream::Stdout, true);
+        self.read_output(Stream::Stderr, true);
+        let process_status = if self.child.is_killed() {
+            ProcessStatus::Killed
+        } else if timed_out {
+            ProcessStatus::TimedOut
+        } else if status.success() {
+            ProcessStatus::Success
+        } else {
+            match status.code() {
+                Some(code) => ProcessStatus::Failure(code),
+                #[cfg(unix)]
+                None => ProcessStatus::Signal(status),
+                // Every exit status has a code on Windows
+                #[cfg(not(unix))]
+                None => unreachable!("exit status without a code"),
+            }
+        };
+        if process_status == ProcessStatus::Success
+            || process_status == ProcessStatus::Killed
+            || self.attempt > options.retries
+        {
+            self.finish(process_status);
+        } else {
+            self.state = RunState::Waiting {
+                until: Instant::now() + retry_delay(options.retry_delay, self.attempt),
+            };
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, status: ProcessStatus) {
+        self.capture.exit(status);
+        self.state = RunState::Finished;
+    }
+}
+
+/// The most to read from a pipe each time it's ready, so that one child
+/// writing a lot can't hold up reading the others' output
+const MAX_READ: usize = 256 * 1024;
+
+/// One of a child's output streams, read without blocking
+struct OutputPipe {
+    stream: Stream,
+    /// `None` once the child has closed it
+    reader: Option<PipeReader>,
+    /// The start of a line that hasn't been finished yet
+    partial: Vec<u8>,
+}
+
+impl OutputPipe {
+    fn new(stream: Stream, reader: PipeReader) -> Self {
+        Self {
+            stream,
+            reader: Some(reader),
+            partial: vec![],
+        }
+    }
+
+    /// Reads what's been written, returning the lines that are complete.
+    /// The rest is returned too once the pipe has been closed, or `last` is
+    /// set because the child has exited.
+    fn read_lines(&mut self, last: bool) -> Vec<String> {
+        if let Some(reader) = &mut self.reader {
+            let mut buffer = [0; 16 * 1024];
+            let mut read = 0;
+            let mut closed = false;
+            while read < MAX_READ {
+                match reader.read(&mut buffer) {
+                    Ok(0) => closed = true,
+                    Ok(n) => {
+                        self.partial.extend_from_slice(&buffer[..n]);
+                        read += n;
+                        continue;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => closed = e.kind() != std::io::ErrorKind::WouldBlock,
+                }
+                break;
+            }
+            if closed {
+                self.reader = None;
+            }
+        }
+        let end = if last || self.reader.is_none() {
+            self.partial.len()
+        } else {
+            self.partial
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(0, |newline| newline + 1)
+        };
+        self.partial
+            .drain(..end)
+            .collect::<Vec<u8>>()
+            .split_inclusive(|&byte| byte == b'\n')
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect()
+    }
+}
+
+/// Forwards a child's output lines to the main thread, keeping at most
+/// `max_capture_bytes` in memory. When a log file is configured, every line is
+/// also written to disk, regardless of the in-memory limit. The log is only
+/// kept if some of the output didn't fit.
+struct OutputCapture {
+    pid: usize,
+    tx: Sender<AppEvent>,
+    log: Option<(File, PathBuf)>,
+    remaining: Option<usize>,
+    truncated: bool,
+}
+
+impl OutputCapture {
+    fn new(
+        pid: usize,
+        tx: Sender<AppEvent>,
+        log_path: Option<&Path>,
+        options: &crate::Options,
+    ) -> Self {
+        let log = log_path.and_then(|path| {
+            std::fs::create_dir_all(log_directory()).ok()?;
+            Some((File::create(path).ok()?, path.to_owned()))
+        });
+        Self {
+            pid,
+            tx,
+            log,
+            remaining: options.max_capture_bytes,
+            truncated: false,
+        }
+    }
+
+    fn lines(&mut self, stream: Stream, lines: Vec<String>) {
+        let mut kept = vec![];
+        for line in lines {
+            if let Some((log, _)) = self.log.as_mut() {
+                let _ = log.write_all(line.as_bytes());
+            }
+            match self.remaining {
+                Some(remaining) if line.len() > remaining => {
+                    self.remaining = Some(0);
+                    if !self.truncated {
+                        self.truncated = true;
+                        self.send(stream, std::mem::take(&mut kept));
+                        let _ = self.tx.send(AppEvent::Truncated { pid: self.pid });
+                    }
+                }
+                _ => {
+                    self.remaining = self.remaining.map(|r| r - line.len());
+                    kept.push(line);
+                }
+            }
+        }
+        self.send(stream, kept);
+    }
+
+    fn send(&self, stream: Stream, lines: Vec<String>) {
+        if !lines.is_empty() {
+            let _ = self.tx.send(AppEvent::Output {
+                pid: self.pid,
+                stream,
+                lines,
+            });
+        }
+    }
+
+    fn retry(&mut self, attempt: usize) {
+        let _ = self.tx.send(AppEvent::Retry {
+            pid: self.pid,
+            attempt,
+        });
+    }
+
+    fn exit(&mut self, status: ProcessStatus) {
+        if let Some((log, path)) = self.log.take() {
+            // Closed first, since an open file can't be removed on Windows
+            drop(log);
+            if !self.truncated {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        let _ = self.tx.send(AppEvent::Exit {
+            pid: self.pid,
+            status,
+        });
+    }
+}
+
+fn spawn_terminal_events_thread(sender: &Sender<AppEvent>) -> JoinHandle<()> {
     let events_tx = sender.clone();
     std::thread::spawn(move || {
         while let Ok(event) = event::read() {
-            if let Event::Key(key_event) = event {
-                events_tx
-                    .send(AppEvent::KeyEvent(key_event))
-                    .expect("could not send to main thread");
+            let event = match event {
+                Event::Key(key_event) => AppEvent::KeyEvent(key_event),
+                Event::Mouse(mouse_event) => AppEvent::MouseEvent(mouse_event),
+                _ => continue,
+            };
+            if events_tx.send(event).is_err() {
+                // The app has quit
+                return;
             }
         }
     })
 }
 
-fn spawn_input_process<R: std::io::Read + Send + 'static>(
+fn spawn_input_process(
     sender: &Sender<AppEvent>,
-    input: &Arc<Mutex<R>>,
+    inputs: Inputs,
     options: &crate::Options,
 ) -> JoinHandle<()> {
     let inputs_tx = sender.clone();
     let options = options.clone();
-    let input = Arc::clone(input);
     std::thread::spawn(move || {
-        let mut input_buffer = vec![];
-        input
-            .lock()
-            .unwrap()
-            .read_to_end(&mut input_buffer)
-            .expect("could not read stdin");
-        let inputs = if options.nul {
-            Splitter::null(&input_buffer)
-        } else {
-            Splitter::whitespace(&input_buffer)
-        };
-        for chunk in inputs.chunks(options.nargs) {
-            let chunk_inputs = chunk.into_iter().map(|s| s.to_string()).collect();
-            let _ = inputs_tx.send(AppEvent::Input(chunk_inputs));
+        for chunk in chunk_inputs(&options, inputs) {
+            let _ = inputs_tx.send(AppEvent::Input(chunk));
         }
+        let _ = inputs_tx.send(AppEvent::InputDone);
     })
 }
 
@@ -310,74 +1537,275 @@ impl Widget for &App {
     where
         Self: Sized,
     {
-        if self.expanded {
-            let layout = Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]);
-            let rects = layout.split(area);
-            Paragraph::new(Text::from(format!(
-                "Selected: {} Keys: {}",
-                self.selected,
-                self.keys
-                    .iter()
-                    .rev()
-                    .map(|k| format!("{k:?}"))
-                    .intersperse(String::from(" "))
-                    .collect::<String>()
-            )))
-            .render(rects[0], buf);
-            let process_widget = ProcessWidget {
-                process: &self.processes[self.selected],
-                scroll_position: Some(self.scroll_position),
-                wrap: self.wrap,
-            };
-            process_widget.render(rects[1], buf);
-        } else {
-            let process_widgets: Vec<ProcessWidget<'_>> = self
-                .processes
-                .iter()
-                .enumerate()
-                .map(|(i, p)| ProcessWidget {
-                    process: p,
-                    scroll_position: (i == self.selected).then_some(self.scroll_position),
-                    wrap: self.wrap,
-                })
-                .collect();
-
-            let widget_constraints = process_widgets.iter().map(ProcessWidget::layout_constraint);
-            let layout =
-                Layout::vertical(std::iter::once(Constraint::Length(1)).chain(widget_constraints));
-            let rects = layout.split(area);
-            let mut areas = rects.iter();
-            let first = areas.next().unwrap();
-            Paragraph::new(Text::from(format!(
-                "Selected: {} Keys: {}",
-                self.selected,
-                self.keys
-                    .iter()
-                    .rev()
-                    .map(|k| format!("{k:?}"))
-                    .intersperse(String::from(" "))
-                    .collect::<String>()
-            )))
-            .render(*first, buf);
-            for (rect, process) in areas.zip(process_widgets.iter()) {
-                process.render(*rect, buf);
-            }
+        let [status_area, _] = split_status_area(area);
+        Paragraph::new(self.status_line()).render(status_area, buf);
+        for (i, process_area) in self.process_areas(area) {
+            self.process_widget(i).render(process_area, buf);
         }
     }
 }
 
+/// The status bar at the top, and the list of processes below it
+fn split_status_area(area: Rect) -> [Rect; 2] {
+    Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(area)
+}
+
 #[derive(Debug)]
 struct Process {
-    args: Vec<String>,
-    output_lines: Vec<String>,
+    args: Vec<OsString>,
+    output_lines: Vec<OutputLine>,
     status: Option<ProcessStatus>,
-    handle: Option<JoinHandle<()>>,
+    /// When the first attempt started, and the last one exited
+    started: Instant,
+    finished: Option<Instant>,
+    log_path: Option<PathBuf>,
+    truncated: bool,
+    child: ChildHandle,
+    /// Whether to run the process again once it has been killed
+    restart: bool,
+    /// Which run this is, counting from 1, when failures are retried
+    attempt: usize,
+    /// Which of the processes running at once this is, for `ARRGS_SLOT`
+    slot: usize,
+    /// Whether the process is waiting for a free job slot
+    queued: bool,
+}
+
+impl Process {
+    /// A process waiting to start, see [`App::start_queued`]
+    fn queued(args: Vec<OsString>) -> Self {
+        Self {
+            args,
+            output_lines: Vec::new(),
+            status: None,
+            started: Instant::now(),
+            finished: None,
+            log_path: None,
+            truncated: false,
+            child: ChildHandle::default(),
+            restart: false,
+            attempt: 1,
+            slot: 0,
+            queued: true,
+        }
+    }
+
+    /// Whether the process has started and not yet exited
+    fn running(&self) -> bool {
+        !self.queued && self.status.is_none()
+    }
+
+    /// The captured output that passes the filter
+    fn visible_lines<'a>(&'a self, filter: LineFilter<'a>) -> impl Iterator<Item = &'a OutputLine> {
+        self.output_lines
+            .iter()
+            .filter(move |line| filter.shows(line))
+    }
+
+    /// Whether the process has finished, other than successfully
+    fn failed(&self) -> bool {
+        self.status
+            .as_ref()
+            .is_some_and(|status| *status != ProcessStatus::Success)
+    }
+
+    /// How long the process has been running, or ran for, including retries
+    fn elapsed(&self) -> Duration {
+        self.finished.unwrap_or_else(Instant::now) - self.started
+    }
+
+    /// Writes the captured output to `path`, after a header with the inputs
+    /// and how the process exited. Stderr lines are marked with `!`, as in
+    /// the accessible view.
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(File::create_new(path)?);
+        writeln!(file, "# inputs: {}", self.display_args())?;
+        match &self.status {
+            Some(status) => writeln!(file, "# status: {status}")?,
+            None => writeln!(file, "# status: running")?,
+        }
+        if self.attempt > 1 {
+            writeln!(file, "# attempt: {}", self.attempt)?;
+        }
+        if let (true, Some(log_path)) = (self.truncated, &self.log_path) {
+            writeln!(file, "# truncated, full log at {}", log_path.display())?;
+        }
+        writeln!(file)?;
+        for line in &self.output_lines {
+            if line.stream == Stream::Stderr {
+                write!(file, "! ")?;
+            }
+            write!(file, "{}", line.text)?;
+        }
+        file.flush()
+    }
+
+    /// The inputs for this process, with any invalid UTF-8 replaced
+    fn display_args(&self) -> String {
+        self.args
+            .join(OsStr::new(" "))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// The running child of a process, shared between the runner thread and
+/// the `App`, so that it can be killed from the TUI
+#[derive(Debug, Clone, Default)]
+struct ChildHandle {
+    child: Arc<Mutex<Option<Child>>>,
+    killed: Arc<AtomicBool>,
+}
+
+impl ChildHandle {
+    fn set(&self, child: Child) {
+        if self.is_killed() {
+            // Killed while it was being started
+            kill(child.id());
+        }
+        *self.child.lock().unwrap() = Some(child);
+    }
+
+    fn try_wait(&self) -> std::io::Result<Option<(std::process::ExitStatus, Option<CpuTime>)>> {
+        let mut child = self.child.lock().unwrap();
+        let status = child.as_mut().map_or(Ok(None), try_wait)?;
+        if status.is_some() {
+            *child = None;
+        }
+        Ok(status)
+    }
+
+    /// Sends the child `signal`, if it's running, and stops it from being
+    /// retried
+    fn signal(&self, signal: i32) {
+        self.killed.store(true, Ordering::Relaxed);
+        if let Some(child) = self.child.lock().unwrap().as_ref() {
+            send_signal(child.id(), signal);
+        }
+    }
+
+    fn id(&self) -> Option<u32> {
+        self.child.lock().unwrap().as_ref().map(Child::id)
+    }
+
+    fn is_running(&self) -> bool {
+        self.child.lock().unwrap().is_some()
+    }
+
+    /// Kills the child, if it's running, and stops it from being retried
+    fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+        if let Some(child) = self.child.lock().unwrap().as_ref() {
+            kill(child.id());
+        }
+    }
+
+    fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+    }
+}
+
+/// Which of a child's output streams a line was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug)]
+struct OutputLine {
+    stream: Stream,
+    text: String,
+}
+
+/// Which output lines of a process are shown
+#[derive(Debug, Clone, Copy)]
+struct LineFilter<'a> {
+    hide_stderr: bool,
+    /// Only show lines containing this text
+    matching: Option<&'a str>,
+}
+
+impl LineFilter<'_> {
+    fn shows(&self, line: &OutputLine) -> bool {
+        !(self.hide_stderr && line.stream == Stream::Stderr)
+            && self.matching.is_none_or(|text| line.text.contains(text))
+    }
+}
+
+/// Which processes are listed, cycled through with `F`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ProcessFilter {
+    #[default]
+    All,
+    Failed,
+    Running,
+}
+
+impl ProcessFilter {
+    fn next(self) -> Self {
+        match self {
+            ProcessFilter::All => ProcessFilter::Failed,
+            ProcessFilter::Failed => ProcessFilter::Running,
+            ProcessFilter::Running => ProcessFilter::All,
+        }
+    }
+
+    fn shows(self, process: &Process) -> bool {
+        match self {
+            ProcessFilter::All => true,
+            ProcessFilter::Failed => process.failed(),
+            ProcessFilter::Running => process.running(),
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessFilter::All => write!(f, "all"),
+            ProcessFilter::Failed => write!(f, "failed"),
+            ProcessFilter::Running => write!(f, "running"),
+        }
+    }
+}
+
+/// A line of output with each occurrence of `pattern` highlighted in
+/// `highlight`
+fn highlight_matches<'a>(
+    text: &'a str,
+    pattern: Option<&str>,
+    style: Style,
+    highlight: Color,
+) -> Line<'a> {
+    let Some(pattern) = pattern else {
+        return Line::styled(text, style);
+    };
+    let mut spans = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find(pattern) {
+        let end = start + pattern.len();
+        spans.push(Span::styled(&rest[..start], style));
+        spans.push(Span::styled(
+            &rest[start..end],
+            Style::from(Color::Black).bg(highlight),
+        ));
+        rest = &rest[end..];
+    }
+    spans.push(Span::styled(rest, style));
+    Line::from(spans)
 }
 
 struct ProcessWidget<'a> {
     process: &'a Process,
     scroll_position: Option<(u16, u16)>,
     wrap: bool,
+    filter: LineFilter<'a>,
+    /// Search text to highlight in the output
+    highlight: Option<&'a str>,
+    /// Where the process is in the queue, counting from 0, if it's queued
+    queue_position: Option<usize>,
+    theme: Theme,
 }
 
 impl Deref for ProcessWidget<'_> {
@@ -389,14 +1817,16 @@ impl Deref for ProcessWidget<'_> {
 }
 
 impl ProcessWidget<'_> {
-    fn layout_constraint(&self) -> Constraint {
+    /// How many lines the process takes up in the list
+    fn height(&self) -> u16 {
         // Succeeded: 1 line title only
         if self.status == Some(ProcessStatus::Success) && self.scroll_position.is_none() {
-            Constraint::Length(1)
+            1
         } else {
             // Failed or unfinished: up to 5 lines of text
             //   - title + min(output_lines.len(), 5)
-            Constraint::Max(1 + self.output_lines.len().min(5) as u16)
+            let lines = self.visible_lines(self.filter).take(5).count();
+            1 + lines as u16
         }
     }
 }
@@ -406,30 +1836,58 @@ impl Widget for &ProcessWidget<'_> {
     where
         Self: Sized,
     {
-        let title: String = self
-            .args
-            .iter()
-            .map(|s| s.as_str())
-            .chain(std::iter::once(
-                format!("({})", self.output_lines.len()).as_str(),
-            ))
-            .intersperse(" ")
-            .collect();
+        let mut title = match self.queue_position {
+            Some(position) => format!("{} [queued #{}]", self.display_args(), position + 1),
+            None => format!(
+                "{} ({}) {}",
+                self.display_args(),
+                self.visible_lines(self.filter).count(),
+                format_duration(self.elapsed())
+            ),
+        };
+        // Not only shown by color, for those who can't tell them apart
+        match &self.status {
+            Some(status) => title.push_str(&format!(" [{}]", status.label())),
+            None if !self.queued => title.push_str(" [running]"),
+            None => {}
+        }
+        if self.attempt > 1 {
+            title.push_str(&format!(" [attempt {}]", self.attempt));
+        }
         let title_style = match self.status {
-            None => Color::Gray,
-            Some(ProcessStatus::Success) => Color::Green,
-            Some(_) => Color::Red,
+            None if self.queued => Color::DarkGray,
+            None => self.theme.running,
+            Some(ProcessStatus::Success) => self.theme.succeeded,
+            Some(ProcessStatus::TimedOut | ProcessStatus::NotStarted(_)) => self.theme.error,
+            Some(_) => self.theme.failed,
         };
         let border_style = if self.scroll_position.is_some() {
-            Color::Yellow
+            self.theme.highlight
         } else {
-            Color::Gray
+            self.theme.running
         };
-        let contents: Text = if self.scroll_position.is_some() {
-            self.output_lines.iter().map(|s| s.as_str()).collect()
+        let mut contents: Text = if self.scroll_position.is_some() {
+            self.visible_lines(self.filter)
+                .map(|line| {
+                    let style = match line.stream {
+                        Stream::Stdout => Style::default(),
+                        Stream::Stderr => Style::from(Color::LightRed),
+                    };
+                    highlight_matches(&line.text, self.highlight, style, self.theme.highlight)
+                })
+                .collect()
         } else {
             Text::default()
         };
+        if self.truncated && self.scroll_position.is_some() {
+            let log_path = self
+                .log_path
+                .as_deref()
+                .map_or_else(|| "<unavailable>".to_string(), |p| p.display().to_string());
+            contents.push_line(
+                Line::from(format!("… truncated, full log at {log_path}")).style(Color::DarkGray),
+            );
+        }
         let block = Block::default()
             .title(title)
             .title_style(Style::from(title_style).bg(Color::Black))
@@ -450,27 +1908,321 @@ impl Widget for &ProcessWidget<'_> {
 pub enum ProcessStatus {
     Success,
     Failure(i32),
+    #[cfg(unix)]
     Signal(std::process::ExitStatus),
+    TimedOut,
+    Killed,
+    /// The program couldn't be started, with the exit code that counts for
+    NotStarted(i32),
+}
+
+impl ProcessStatus {
+    /// A short label for the status, e.g. `ok`, `failed: exit 1` or
+    /// `signal: TERM`, as shown on each process's row
+    fn label(&self) -> String {
+        match self {
+            ProcessStatus::Success => "ok".to_string(),
+            ProcessStatus::Failure(code) => format!("failed: exit {code}"),
+            #[cfg(unix)]
+            ProcessStatus::Signal(status) => match exit_signal(*status) {
+                Some(signal) => match crate::platform::signal_name(signal) {
+                    Some(name) => format!("signal: {name}"),
+                    None => format!("signal: {signal}"),
+                },
+                None => format!("failed: {status}"),
+            },
+            ProcessStatus::TimedOut => "timed out".to_string(),
+            ProcessStatus::Killed => "killed".to_string(),
+            ProcessStatus::NotStarted(_) => "not started".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessStatus::Success => write!(f, "succeeded"),
+            ProcessStatus::Failure(code) => write!(f, "failed with exit code {code}"),
+            #[cfg(unix)]
+            ProcessStatus::Signal(status) => write!(f, "failed, {status}"),
+            ProcessStatus::TimedOut => write!(f, "timed out"),
+            ProcessStatus::Killed => write!(f, "killed"),
+            ProcessStatus::NotStarted(code) => write!(f, "could not start, exit code {code}"),
+        }
+    }
+}
+
+/// Where the events for the processes come from
+enum Source {
+    /// Inputs to run the program for
+    Inputs(Inputs),
+    /// A `--replay` recording, and when each of its events happened
+    Replay(Vec<(Duration, recording::Event)>),
+}
+
+/// Sends the events of a recording, as long after the thread starts as they
+/// happened in the recorded session
+fn spawn_replay_thread(
+    sender: &Sender<AppEvent>,
+    events: Vec<(Duration, recording::Event)>,
+) -> JoinHandle<()> {
+    let events_tx = sender.clone();
+    std::thread::spawn(move || {
+        let started = Instant::now();
+        for (at, event) in events {
+            if let Some(wait) = (started + at).checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+            if events_tx.send(AppEvent::from(event)).is_err() {
+                // The app has quit
+                return;
+            }
+        }
+    })
+}
+
+/// Runs either the TUI or the accessible plain-text view, for the events
+/// from `source`
+fn run_app(options: crate::Options, source: Source) -> anyhow::Result<()> {
+    let mut app = App::default();
+    let result = if options.accessible || term_is_limited() {
+        app.run_accessible(options, source)
+    } else {
+        let mut terminal = ratatui::try_init().context("initializing TUI")?;
+        let result = crossterm::execute!(std::io::stdout(), EnableMouseCapture)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| app.run(options, &mut terminal, source));
+        let _ = crossterm::execute!(std::io::stdout(), DisableMouseCapture);
+        ratatui::restore();
+        result
+    };
+    app.stop_processes();
+    result?;
+    app.logs.lock().unwrap().finish()?;
+    Ok(())
 }
 
 pub fn run(options: crate::Options) -> anyhow::Result<()> {
-    if options.simulate {
+    if let Some(path) = &options.replay {
+        let events = recording::read_events(path)?;
+        run_app(options, Source::Replay(events))
+    } else if options.simulate {
         let mut input_program = Command::new("echo")
             .args((1..10).map(|_| "loremipsum.txt"))
             .stdout(Stdio::piped())
             // .stderr(Stdio::piped())
             .spawn()?;
-        let input = Arc::new(Mutex::new(input_program.stdout.take().unwrap()));
-        let mut terminal = ratatui::try_init().context("initializing TUI")?;
-        let result = App::default().run(options, &mut terminal, &input);
-        ratatui::restore();
+        let inputs = split_inputs(&options, input_program.stdout.take().unwrap());
+        let result = run_app(options, Source::Inputs(Box::new(inputs)));
         input_program.wait().unwrap();
         result
     } else {
-        let input = Arc::new(Mutex::new(std::io::stdin()));
-        let mut terminal = ratatui::try_init().context("initializing TUI")?;
-        let result = App::default().run(options, &mut terminal, &input);
-        ratatui::restore();
-        result
+        let mut options = options;
+        let inputs = read_inputs(&mut options)?;
+        run_app(options, Source::Inputs(inputs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(max_bytes: usize, log: &Path) -> (OutputCapture, Receiver<AppEvent>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let capture = OutputCapture {
+            pid: 0,
+            tx,
+            log: Some((File::create(log).unwrap(), log.to_owned())),
+            remaining: Some(max_bytes),
+            truncated: false,
+        };
+        (capture, rx)
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split_inclusive('\n').map(String::from).collect()
+    }
+
+    /// The events sent, as output text or `[truncated]`
+    fn events(rx: &Receiver<AppEvent>) -> Vec<String> {
+        rx.try_iter()
+            .map(|event| match event {
+                AppEvent::Output { lines, .. } => lines.concat(),
+                AppEvent::Truncated { .. } => "[truncated]".to_string(),
+                _ => panic!("unexpected event"),
+            })
+            .collect()
+    }
+
+    fn log_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("arrgs-capture-{}-{name}", std::process::id()))
+    }
+
+    fn finished(status: Option<ProcessStatus>) -> Process {
+        Process {
+            status,
+            queued: false,
+            ..Process::queued(vec![])
+        }
+    }
+
+    fn press(app: &mut App, key: char, tx: &Sender<AppEvent>) {
+        app.handle_key_event(KeyEvent::from(KeyCode::Char(key)), tx);
+    }
+
+    #[test]
+    fn runner_errors_quit() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let options = crate::Options::default();
+        let mut app = App::default();
+        let error = anyhow::anyhow!("could not write logs");
+        let result = app.handle_event(AppEvent::Error(error), &tx, &options);
+        assert_eq!(result.unwrap_err().to_string(), "could not write logs");
+    }
+
+    #[test]
+    fn restart_failed() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let options = crate::Options::default();
+        let mut app = App {
+            processes: vec![
+                finished(Some(ProcessStatus::Success)),
+                finished(Some(ProcessStatus::Failure(1))),
+                // Keeps the restarted processes queued rather than run
+                finished(None),
+                finished(Some(ProcessStatus::TimedOut)),
+                finished(Some(ProcessStatus::NotStarted(127))),
+            ],
+            jobs: 1,
+            ..Default::default()
+        };
+
+        // Not until all of the inputs have been read
+        press(&mut app, 'R', &tx);
+        assert!(rx.try_recv().is_err());
+        assert!(app
+            .message
+            .as_ref()
+            .unwrap()
+            .starts_with("Still reading input"));
+
+        app.input_done = true;
+        press(&mut app, 'R', &tx);
+        assert_eq!(app.message, None);
+        for event in rx.try_iter() {
+            app.handle_event(event, &tx, &options).unwrap();
+        }
+        assert_eq!(app.queue, [1, 3, 4]);
+        assert_eq!(app.processes[0].status, Some(ProcessStatus::Success));
+        assert!(app.processes[2].running());
+        for pid in [1, 3, 4] {
+            assert!(app.processes[pid].queued);
+            assert_eq!(app.processes[pid].status, None);
+        }
+    }
+
+    #[test]
+    fn restart_during_retry_delay() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let options = crate::Options {
+            program: "sh".to_string(),
+            program_args: vec!["-c".to_string(), "echo failed; exit 1".to_string()],
+            retries: 1,
+            retry_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let mut app = App {
+            jobs: 1,
+            ..Default::default()
+        };
+        app.runner = Some(spawn_runner_thread(&tx, &options, &app.logs).unwrap());
+        app.handle_event(AppEvent::Input(vec!["x".into()]), &tx, &options)
+            .unwrap();
+        // Handles events until one matches, failing if it doesn't come well
+        // before the retry delay is over
+        let handle_until = |app: &mut App, done: fn(&AppEvent) -> bool| loop {
+            let event = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+            let found = done(&event);
+            app.handle_event(event, &tx, &options).unwrap();
+            if found {
+                break;
+            }
+        };
+
+        // Once the first attempt has exited, it's waiting to be retried
+        handle_until(&mut app, |event| matches!(event, AppEvent::Output { .. }));
+        while app.processes[0].child.is_running() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(app.processes[0].running());
+        app.handle_event(AppEvent::Restart { pid: 0 }, &tx, &options)
+            .unwrap();
+        handle_until(&mut app, |event| matches!(event, AppEvent::Started { .. }));
+        assert!(app.processes[0].running());
+        assert_eq!(app.processes[0].attempt, 1);
+    }
+
+    #[test]
+    fn status_labels() {
+        assert_eq!(ProcessStatus::Success.label(), "ok");
+        assert_eq!(ProcessStatus::Failure(1).label(), "failed: exit 1");
+        #[cfg(unix)]
+        assert_eq!(
+            ProcessStatus::Signal(signal_status(libc::SIGTERM)).label(),
+            "signal: TERM"
+        );
+        assert_eq!(ProcessStatus::NotStarted(127).label(), "not started");
+    }
+
+    #[test]
+    fn capture_exactly_fills_budget() {
+        let path = log_path("fill");
+        let (mut capture, rx) = capture(9, &path);
+        capture.lines(Stream::Stdout, lines("abc\ndefg\n"));
+        assert_eq!(events(&rx), ["abc\ndefg\n"]);
+        assert_eq!(capture.remaining, Some(0));
+        assert!(!capture.truncated);
+
+        // Even an empty line no longer fits
+        capture.lines(Stream::Stdout, lines("\n"));
+        assert_eq!(events(&rx), ["[truncated]"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn capture_line_crosses_budget() {
+        let path = log_path("cross");
+        let (mut capture, rx) = capture(6, &path);
+        capture.lines(Stream::Stdout, lines("abc\ndefg\nh\n"));
+        // The line that would cross the budget is dropped whole, and so is
+        // everything after it, even if it would fit
+        assert_eq!(events(&rx), ["abc\n", "[truncated]"]);
+        assert_eq!(capture.remaining, Some(0));
+        assert!(capture.truncated);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn capture_truncation_marked_once() {
+        let path = log_path("marker");
+        let (mut capture, rx) = capture(2, &path);
+        capture.lines(Stream::Stdout, lines("abc\n"));
+        capture.lines(Stream::Stderr, lines("def\n"));
+        capture.lines(Stream::Stdout, lines("ghi\n"));
+        assert_eq!(events(&rx), ["[truncated]"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn capture_logs_every_line() {
+        let path = log_path("log");
+        let (mut capture, rx) = capture(4, &path);
+        capture.lines(Stream::Stdout, lines("abc\ndefg\n"));
+        capture.lines(Stream::Stderr, lines("hij\n"));
+        capture.exit(ProcessStatus::Success);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abc\ndefg\nhij\n");
+        drop(rx);
+        std::fs::remove_file(path).unwrap();
     }
 }
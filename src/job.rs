@@ -0,0 +1,902 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use std::{io, process, thread};
+
+use crate::audit::AuditLog;
+use crate::exec::{command_line, job_env, JobResult, SpawnError, KILL_GRACE_PERIOD};
+use crate::joblog::{JobLog, JobRecord, Summary};
+use crate::naming::{job_file_name, log_directory};
+use crate::platform::{
+    kill, limit_resources, own_process_group, send_signal, terminate, try_wait, CpuTime,
+};
+use crate::remote::remote_command;
+use crate::results::Results;
+use crate::signals::{self, ChildExits};
+use crate::timing::Timings;
+use crate::{shell, Options};
+
+/// A thread reading one of a child's pipes, returning the output it captured
+type Reader = JoinHandle<Vec<u8>>;
+
+/// A spawned child process, along with the bookkeeping needed to audit it and
+/// enforce `--timeout`
+struct RunningChild {
+    child: process::Child,
+    command: Vec<OsString>,
+    start: SystemTime,
+    started: Instant,
+    terminated: Option<Instant>,
+    /// Whether it was terminated for running past the timeout
+    timed_out: bool,
+    /// Threads reading stdout and stderr, when output is grouped or tagged
+    readers: Option<(Reader, Reader)>,
+    /// How much CPU time the child used, once it has exited
+    cpu_time: Option<CpuTime>,
+}
+
+impl RunningChild {
+    fn new(
+        child: process::Child,
+        command: Vec<OsString>,
+        start: SystemTime,
+        readers: Option<(Reader, Reader)>,
+    ) -> Self {
+        Self {
+            child,
+            command,
+            start,
+            started: Instant::now(),
+            terminated: None,
+            timed_out: false,
+            readers,
+            cpu_time: None,
+        }
+    }
+
+    /// Appends everything the child wrote to `output`, once it has exited
+    fn collect_output(&mut self, output: &mut Output) {
+        if let Some((stdout, stderr)) = self.readers.take() {
+            output.stdout.extend(stdout.join().unwrap_or_default());
+            output.stderr.extend(stderr.join().unwrap_or_default());
+        }
+    }
+
+    /// Checks whether the child has exited, without blocking. Children that
+    /// run past the timeout are sent `SIGTERM`, then `SIGKILL` if they still
+    /// haven't exited after the [`KILL_GRACE_PERIOD`]. Likewise, a caught
+    /// termination signal is passed on, followed by `SIGKILL`.
+    fn poll(&mut self, timeout: Option<Duration>) -> io::Result<Option<process::ExitStatus>> {
+        if let Some((status, cpu_time)) = try_wait(&mut self.child)? {
+            self.cpu_time = cpu_time;
+            return Ok(Some(status));
+        }
+        if let (Some(signal), None) = (signals::received(), self.terminated) {
+            send_signal(self.child.id(), signal);
+            self.terminated = Some(Instant::now());
+        }
+        match (timeout, self.terminated) {
+            (Some(timeout), None) if self.started.elapsed() >= timeout => {
+                eprintln!(
+                    "Timed out after {timeout:?}, terminating: {}",
+                    self.command.join(OsStr::new(" ")).to_string_lossy()
+                );
+                terminate(self.child.id());
+                self.terminated = Some(Instant::now());
+                self.timed_out = true;
+            }
+            (_, Some(terminated)) if terminated.elapsed() >= KILL_GRACE_PERIOD => {
+                kill(self.child.id());
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// When the child next needs to be polled, even if it hasn't exited: when
+    /// it times out, or its grace period after being terminated ends
+    fn deadline(&self, timeout: Option<Duration>) -> Option<Instant> {
+        match self.terminated {
+            Some(terminated) => terminated.checked_add(KILL_GRACE_PERIOD),
+            None => self.started.checked_add(timeout?),
+        }
+    }
+
+    /// Terminates the child (if it hasn't already been), sending `SIGKILL` if
+    /// it's still running when next polled after the [`KILL_GRACE_PERIOD`]
+    fn cancel(&mut self) {
+        if self.terminated.is_none() {
+            terminate(self.child.id());
+            self.terminated = Some(Instant::now());
+        }
+    }
+
+    /// Blocks until the child has exited, enforcing the timeout
+    fn wait(&mut self, timeout: Option<Duration>) -> io::Result<process::ExitStatus> {
+        let exits = ChildExits::new()?;
+        loop {
+            if let Some(status) = self.poll(timeout)? {
+                return Ok(status);
+            }
+            exits.wait(self.deadline(timeout));
+        }
+    }
+}
+
+/// Everywhere a run of the program is recorded: the `--audit-log`, the
+/// `--joblog`, the `--summary` totals, the `--time` statistics and the
+/// `--output` results
+#[derive(Debug, Default)]
+pub struct Logs {
+    audit: Option<AuditLog>,
+    joblog: Option<JobLog>,
+    summary: Option<Summary>,
+    timings: Option<Timings>,
+    results: Option<Results>,
+}
+
+impl Logs {
+    /// # Errors
+    /// Will return an error if a log was requested but cannot be opened
+    pub fn open(options: &Options) -> anyhow::Result<Self> {
+        Ok(Self {
+            audit: options
+                .audit_log
+                .as_deref()
+                .map(AuditLog::open)
+                .transpose()?,
+            joblog: options
+                .joblog
+                .as_deref()
+                .map(|path| JobLog::open(path, options.joblog_format))
+                .transpose()?,
+            summary: options.summary.then(Summary::default),
+            timings: options.time.then(Timings::default),
+            results: options
+                .output
+                .map(|_| Results::open(options.output_file.as_deref()))
+                .transpose()?,
+        })
+    }
+
+    /// Records one run of job number `seq` for `inputs`, which started at
+    /// `start` and has just finished with `result`, using `cpu_time` if
+    /// that's known
+    ///
+    /// # Errors
+    /// Will return an error if a log cannot be written to
+    pub fn record(
+        &mut self,
+        seq: usize,
+        inputs: &[OsString],
+        command: &[OsString],
+        start: SystemTime,
+        result: JobResult,
+        cpu_time: Option<CpuTime>,
+    ) -> anyhow::Result<()> {
+        let end = SystemTime::now();
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(command, start, end, result.exit_status())?;
+        }
+        if let Some(joblog) = self.joblog.as_mut() {
+            joblog.record(&JobRecord::new(seq, inputs, command, start, end, result))?;
+        }
+        let runtime = end.duration_since(start).unwrap_or_default();
+        if let Some(summary) = self.summary.as_mut() {
+            summary.add(runtime, result);
+        }
+        if let Some(timings) = self.timings.as_mut() {
+            timings.add(command, runtime, cpu_time);
+        }
+        if let Some(results) = self.results.as_mut() {
+            results.record(seq, inputs, command, start, end, result);
+        }
+        Ok(())
+    }
+
+    /// Adds output captured from job number `seq` to its `--output` results
+    pub fn capture(&mut self, seq: usize, output: &Output) {
+        if let Some(results) = self.results.as_mut() {
+            results.capture(seq, output);
+        }
+    }
+
+    /// Prints the totals to stderr, with `--summary`, and the statistics,
+    /// with `--time`, and writes the `--output` results
+    ///
+    /// # Errors
+    /// Will return an error if the results cannot be written
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(summary) = &self.summary {
+            eprintln!("{summary}");
+        }
+        if let Some(timings) = &self.timings {
+            eprint!("{timings}");
+        }
+        match self.results.as_mut() {
+            Some(results) => results.write(),
+            None => Ok(()),
+        }
+    }
+}
+
+enum JobState {
+    Running(RunningChild),
+    /// Waiting to retry after the attempt that ended with this result
+    Delayed {
+        until: Instant,
+        result: JobResult,
+    },
+}
+
+/// One invocation of the program for a chunk of inputs, re-run up to
+/// `--retries` times while it keeps failing. Used by both the sequential and
+/// parallel executors.
+pub struct Job {
+    /// Position of the job in input order, counting from 1
+    seq: usize,
+    /// Which of the jobs running at once this is, counting from 1, for
+    /// `ARRGS_SLOT`
+    slot: usize,
+    inputs: Vec<OsString>,
+    attempt: usize,
+    cancelled: bool,
+    state: JobState,
+    output: Output,
+    /// Shared with the threads reading the output, when output is captured
+    /// and `--max-capture-bytes` limits it
+    limit: Option<Arc<Mutex<CaptureLimit>>>,
+}
+
+/// The output captured from every attempt of a job with `--group`
+#[derive(Debug, Default)]
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    /// Writes the output to our own stdout and stderr, each in one go so it
+    /// isn't interleaved with output from other jobs
+    ///
+    /// # Errors
+    /// Will return an error if stdout or stderr cannot be written to
+    pub fn print(&self) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&self.stdout)?;
+        stdout.flush()?;
+        io::stderr().lock().write_all(&self.stderr)
+    }
+}
+
+/// Keeps the output captured from a job, across both streams and every
+/// attempt, to `--max-capture-bytes`. Every line is also written to a log
+/// file, which is only kept if some of the output didn't fit.
+struct CaptureLimit {
+    remaining: usize,
+    log: Option<File>,
+    path: PathBuf,
+    truncated: bool,
+}
+
+impl CaptureLimit {
+    fn new(max_bytes: usize, seq: usize, inputs: &[OsString]) -> Self {
+        let path = log_directory().join(job_file_name(seq, inputs));
+        let log = std::fs::create_dir_all(log_directory())
+            .and_then(|()| File::create(&path))
+            .ok();
+        Self {
+            remaining: max_bytes,
+            log,
+            path,
+            truncated: false,
+        }
+    }
+
+    /// Writes `line` to the log, returning whether it fits in memory too.
+    /// Once a line doesn't fit, nothing after it is kept either.
+    fn keep(&mut self, line: &[u8]) -> bool {
+        if let Some(log) = &mut self.log {
+            let _ = log.write_all(line);
+        }
+        if self.truncated || line.len() > self.remaining {
+            self.truncated = true;
+            return false;
+        }
+        self.remaining -= line.len();
+        true
+    }
+}
+
+impl Drop for CaptureLimit {
+    fn drop(&mut self) {
+        // Closed first, since an open file can't be removed on Windows
+        if self.log.take().is_some() && !self.truncated {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl Job {
+    /// Starts the first attempt
+    ///
+    /// # Errors
+    /// Will return a [`SpawnError`] if the program cannot be started, or an
+    /// error if the audit log cannot be written to
+    pub fn start(
+        options: &Options,
+        seq: usize,
+        slot: usize,
+        inputs: Vec<OsString>,
+        logs: &mut Logs,
+    ) -> anyhow::Result<Self> {
+        let limit = options
+            .max_capture_bytes
+            .filter(|_| captures_output(options))
+            .map(|max_bytes| Arc::new(Mutex::new(CaptureLimit::new(max_bytes, seq, &inputs))));
+        let child = spawn(options, seq, slot, &inputs, limit.as_ref(), logs)?;
+        Ok(Self {
+            seq,
+            slot,
+            inputs,
+            attempt: 1,
+            cancelled: false,
+            state: JobState::Running(child),
+            output: Output::default(),
+            limit,
+        })
+    }
+
+    pub fn seq(&self) -> usize {
+        self.seq
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    pub fn inputs(&self) -> &[OsString] {
+        &self.inputs
+    }
+
+    /// The process id of the running attempt, if there is one
+    pub fn pid(&self) -> Option<u32> {
+        match &self.state {
+            JobState::Running(child) => Some(child.child.id()),
+            JobState::Delayed { .. } => None,
+        }
+    }
+
+    /// When the job next needs to be polled, even if its child hasn't exited:
+    /// when the running attempt times out or should be killed, or the next
+    /// attempt is due
+    pub fn deadline(&self, options: &Options) -> Option<Instant> {
+        match &self.state {
+            JobState::Running(child) => child.deadline(options.timeout),
+            JobState::Delayed { .. } if self.stopping() => Some(Instant::now()),
+            JobState::Delayed { until, .. } => Some(*until),
+        }
+    }
+
+    /// The output captured so far, when output is grouped
+    pub fn take_output(&mut self) -> Output {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Checks whether the job has finished, without blocking, starting the
+    /// next attempt once a retry is due. Every attempt is written to the
+    /// audit log.
+    ///
+    /// # Errors
+    /// Will return a [`SpawnError`] if a retry cannot be started or the child
+    /// cannot be waited on, or an error if the audit log cannot be written to
+    pub fn poll(
+        &mut self,
+        options: &Options,
+        logs: &mut Logs,
+    ) -> anyhow::Result<Option<JobResult>> {
+        let stopping = self.stopping();
+        match &mut self.state {
+            JobState::Running(child) => match child.poll(options.timeout).map_err(SpawnError)? {
+                Some(status) => self.finish_attempt(options, logs, status),
+                None => Ok(None),
+            },
+            JobState::Delayed { result, .. } if stopping => Ok(Some(*result)),
+            JobState::Delayed { until, .. } => {
+                if Instant::now() >= *until {
+                    self.attempt += 1;
+                    let child = spawn(
+                        options,
+                        self.seq,
+                        self.slot,
+                        &self.inputs,
+                        self.limit.as_ref(),
+                        logs,
+                    )?;
+                    self.state = JobState::Running(child);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Blocks until the job has finished, including any retries
+    ///
+    /// # Errors
+    /// See [`Job::poll`]
+    pub fn wait(&mut self, options: &Options, logs: &mut Logs) -> anyhow::Result<JobResult> {
+        loop {
+            match &mut self.state {
+                JobState::Running(child) => {
+                    let status = child.wait(options.timeout).map_err(SpawnError)?;
+                    if let Some(result) = self.finish_attempt(options, logs, status)? {
+                        return Ok(result);
+                    }
+                }
+                JobState::Delayed { until, .. } => {
+                    // Sleeps until the retry is due, unless a termination
+                    // signal is caught first
+                    ChildExits::new().map_err(SpawnError)?.wait(Some(*until));
+                    if let Some(result) = self.poll(options, logs)? {
+                        return Ok(result);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether to skip any remaining retries: the job was cancelled, or a
+    /// termination signal was caught
+    fn stopping(&self) -> bool {
+        self.cancelled || signals::received().is_some()
+    }
+
+    /// Terminates the running attempt, and skips any remaining retries
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+        if let JobState::Running(child) = &mut self.state {
+            child.cancel();
+        }
+    }
+
+    /// Records the attempt that exited, then either schedules a retry or
+    /// returns the final result
+    fn finish_attempt(
+        &mut self,
+        options: &Options,
+        logs: &mut Logs,
+        status: process::ExitStatus,
+    ) -> anyhow::Result<Option<JobResult>> {
+        let JobState::Running(child) = &mut self.state else {
+            unreachable!("only a running attempt can exit");
+        };
+        let result = JobResult::exited(status, child.timed_out);
+        let printed = self.output.stdout.len();
+        child.collect_output(&mut self.output);
+        forward(
+            options,
+            &self.inputs,
+            result,
+            &mut self.output.stdout,
+            printed,
+        );
+        logs.record(
+            self.seq,
+            &self.inputs,
+            &child.command,
+            child.start,
+            result,
+            child.cpu_time,
+        )?;
+        if options.output_capture {
+            logs.capture(self.seq, &std::mem::take(&mut self.output));
+        }
+        if result.success() || self.stopping() || self.attempt > options.retries {
+            self.report_truncation();
+            return Ok(Some(result));
+        }
+        let delay = retry_delay(options.retry_delay, self.attempt);
+        eprintln!(
+            "Retrying in {delay:?} ({result}), attempt {} of {}: {}",
+            self.attempt + 1,
+            options.retries + 1,
+            command_line(options, self.seq, &self.inputs)
+                .join(OsStr::new(" "))
+                .to_string_lossy()
+        );
+        self.state = JobState::Delayed {
+            until: Instant::now() + delay,
+            result,
+        };
+        Ok(None)
+    }
+
+    /// Tells the user where to find the whole output, if some of it didn't
+    /// fit in `--max-capture-bytes`
+    fn report_truncation(&self) {
+        let Some(limit) = &self.limit else {
+            return;
+        };
+        let Ok(limit) = limit.lock() else {
+            return;
+        };
+        match (limit.truncated, &limit.log) {
+            (false, _) => {}
+            (true, Some(_)) => eprintln!(
+                "Output of job {} was truncated, full log at {}",
+                self.seq,
+                limit.path.display()
+            ),
+            (true, None) => eprintln!("Output of job {} was truncated", self.seq),
+        }
+    }
+}
+
+/// Replaces what an attempt wrote to stdout, from `printed` on, with what's
+/// passed on down the pipeline: with `--filter`, the inputs if it succeeded,
+/// and with `--print0`, its output ended with a NUL
+fn forward(
+    options: &Options,
+    inputs: &[OsString],
+    result: JobResult,
+    stdout: &mut Vec<u8>,
+    printed: usize,
+) {
+    let terminator = if options.print0 { b'\0' } else { b'\n' };
+    if options.filter {
+        stdout.truncate(printed);
+        if result.success() {
+            for input in inputs {
+                stdout.extend_from_slice(input.as_encoded_bytes());
+                if !options.pipe {
+                    stdout.push(terminator);
+                }
+            }
+        }
+    } else if options.print0 {
+        if stdout.len() > printed && stdout.ends_with(b"\n") {
+            stdout.pop();
+        }
+        stdout.push(terminator);
+    }
+}
+
+/// How long to wait before retrying after the given (1-based) attempt failed:
+/// `--retry-delay`, doubling after each failed attempt
+pub fn retry_delay(base: Duration, attempt: usize) -> Duration {
+    let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+    base.saturating_mul(2u32.saturating_pow(exponent))
+}
+
+fn spawn(
+    options: &Options,
+    seq: usize,
+    slot: usize,
+    inputs: &[OsString],
+    limit: Option<&Arc<Mutex<CaptureLimit>>>,
+    logs: &mut Logs,
+) -> anyhow::Result<RunningChild> {
+    let start = SystemTime::now();
+    let command = remote_command(
+        options,
+        seq,
+        slot,
+        inputs,
+        command_line(options, seq, inputs),
+    );
+    if options.verbose {
+        eprintln!("{}", shell::join(&command));
+    }
+    let mut child = process::Command::new(&command[0]);
+    child.args(&command[1..]);
+    job_env(&mut child, options, seq, slot, inputs);
+    // Make sure the child doesn't read from *our* stdin, unless the inputs
+    // come from somewhere else
+    if options.pipe {
+        child.stdin(process::Stdio::piped());
+    } else if options.arg_file.is_empty() {
+        child.stdin(process::Stdio::null());
+    }
+    // A child reading from the terminal has to stay in our process group, the
+    // one in the foreground, or reading would stop it with `SIGTTIN`. Ctrl-C
+    // and Ctrl-Z at the terminal then reach it directly, as with xargs.
+    let reads_terminal = !options.pipe && !options.arg_file.is_empty() && io::stdin().is_terminal();
+    if !reads_terminal {
+        own_process_group(&mut child);
+    }
+    limit_resources(&mut child, options);
+    if options.group || options.tag || options.output_capture || options.filter || options.print0 {
+        child
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped());
+    }
+    let child = child.spawn();
+    match child {
+        Ok(mut child) => {
+            write_input(&mut child, inputs);
+            let readers = read_output(&mut child, options, inputs, limit);
+            Ok(RunningChild::new(child, command, start, readers))
+        }
+        Err(e) => {
+            logs.record(
+                seq,
+                inputs,
+                &command,
+                start,
+                JobResult::spawn_error(&e),
+                None,
+            )?;
+            Err(SpawnError(e).into())
+        }
+    }
+}
+
+/// Starts a thread writing the `--pipe` block to the child's stdin, then
+/// closing it. The thread isn't joined: it finishes when the child has read
+/// the whole block, or fails with a broken pipe once the child exits without
+/// reading it all.
+fn write_input(child: &mut process::Child, inputs: &[OsString]) {
+    let Some(mut stdin) = child.stdin.take() else {
+        return;
+    };
+    let block: Vec<u8> = inputs
+        .iter()
+        .flat_map(|input| input.as_encoded_bytes())
+        .copied()
+        .collect();
+    thread::spawn(move || stdin.write_all(&block));
+}
+
+/// Where a reader thread writes output that isn't grouped
+#[derive(Clone, Copy)]
+enum Passthrough {
+    Stdout,
+    Stderr,
+    /// Nowhere, for stdout with `--filter`, which passes on the inputs instead
+    Discard,
+}
+
+/// Whether the reader threads keep any output in memory until the job is
+/// done, rather than only passing it through
+fn captures_output(options: &Options) -> bool {
+    options.group || options.output_capture || (options.print0 && !options.filter)
+}
+
+/// Starts threads reading the child's stdout and stderr, when they're piped
+/// for `--group` or `--tag`, or stdout is replaced by `--filter` or
+/// `--print0`. The pipes have to be drained while the child runs, or it could
+/// block writing to a full pipe.
+fn read_output(
+    child: &mut process::Child,
+    options: &Options,
+    inputs: &[OsString],
+    limit: Option<&Arc<Mutex<CaptureLimit>>>,
+) -> Option<(Reader, Reader)> {
+    let stdout = child.stdout.take()?;
+    let stderr = child.stderr.take()?;
+    let prefix = options.tag.then(|| {
+        let mut prefix = inputs.join(OsStr::new(" ")).into_encoded_bytes();
+        prefix.push(b'\t');
+        prefix
+    });
+    let passthrough = |to| (!options.group && !options.output_capture).then_some(to);
+    let stdout_to = if options.filter {
+        Some(Passthrough::Discard)
+    } else {
+        passthrough(Passthrough::Stdout).filter(|_| !options.print0)
+    };
+    Some((
+        read_lines(stdout, prefix.clone(), stdout_to, limit.cloned()),
+        read_lines(
+            stderr,
+            prefix,
+            passthrough(Passthrough::Stderr),
+            limit.cloned(),
+        ),
+    ))
+}
+
+/// Reads lines from `reader`, adding the `--tag` prefix, and either writes
+/// them straight through or captures them to be printed when the job is done,
+/// as much as fits in the `--max-capture-bytes` limit
+fn read_lines<R: Read + Send + 'static>(
+    reader: R,
+    prefix: Option<Vec<u8>>,
+    passthrough: Option<Passthrough>,
+    limit: Option<Arc<Mutex<CaptureLimit>>>,
+) -> Reader {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut captured = vec![];
+        let mut line = vec![];
+        while reader.read_until(b'\n', &mut line).is_ok_and(|n| n > 0) {
+            if let Some(prefix) = &prefix {
+                line.splice(0..0, prefix.iter().copied());
+                if !line.ends_with(b"\n") {
+                    line.push(b'\n');
+                }
+            }
+            // Each line is written with the lock held, so that lines from
+            // different jobs don't get mixed together
+            let _ = match passthrough {
+                Some(Passthrough::Stdout) => io::stdout().lock().write_all(&line),
+                Some(Passthrough::Stderr) => io::stderr().lock().write_all(&line),
+                Some(Passthrough::Discard) => Ok(()),
+                None => {
+                    let fits = limit
+                        .as_ref()
+                        .is_none_or(|limit| limit.lock().is_ok_and(|mut limit| limit.keep(&line)));
+                    if fits {
+                        captured.append(&mut line);
+                    }
+                    Ok(())
+                }
+            };
+            line.clear();
+        }
+        captured
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mode;
+
+    #[test]
+    fn delay_doubles() {
+        let base = Duration::from_millis(100);
+        assert_eq!(retry_delay(base, 1), base);
+        assert_eq!(retry_delay(base, 2), base * 2);
+        assert_eq!(retry_delay(base, 4), base * 8);
+        assert_eq!(retry_delay(Duration::ZERO, 10), Duration::ZERO);
+        assert_eq!(retry_delay(Duration::MAX, 2), Duration::MAX);
+    }
+
+    #[test]
+    fn grouped_output_collects_every_attempt() {
+        let options = Options {
+            mode: Mode::Simple,
+            program: "sh".to_string(),
+            program_args: vec![
+                "-c".to_string(),
+                "echo out $0; echo err $0 >&2; exit 1".to_string(),
+            ],
+            group: true,
+            retries: 1,
+            ..Default::default()
+        };
+        let mut logs = Logs::default();
+        let mut job = Job::start(&options, 1, 1, vec!["x".into()], &mut logs).unwrap();
+        let status = job.wait(&options, &mut logs).unwrap();
+        assert!(!status.success());
+        let output = job.take_output();
+        assert_eq!(output.stdout, b"out x\nout x\n");
+        assert_eq!(output.stderr, b"err x\nerr x\n");
+    }
+
+    #[test]
+    fn grouped_output_limited() {
+        let options = Options {
+            mode: Mode::Simple,
+            program: "sh".to_string(),
+            program_args: vec![
+                "-c".to_string(),
+                "echo out $0; echo more $0; sleep 0.2; echo err $0 >&2; exit 1".to_string(),
+            ],
+            group: true,
+            retries: 1,
+            max_capture_bytes: Some(12),
+            ..Default::default()
+        };
+        let mut logs = Logs::default();
+        let inputs = vec![OsString::from("limited")];
+        let mut job = Job::start(&options, 1, 1, inputs.clone(), &mut logs).unwrap();
+        job.wait(&options, &mut logs).unwrap();
+        let output = job.take_output();
+        // Once a line doesn't fit, later ones don't either
+        assert_eq!(output.stdout, b"out limited\n");
+        assert_eq!(output.stderr, b"");
+        drop(job);
+
+        // The whole output is logged, from every attempt
+        let path = log_directory().join(job_file_name(1, &inputs));
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(log.matches("more limited\n").count(), 2);
+        assert_eq!(log.matches("err limited\n").count(), 2);
+    }
+
+    #[test]
+    fn log_removed_when_output_fits() {
+        let options = Options {
+            mode: Mode::Simple,
+            program: "echo".to_string(),
+            group: true,
+            max_capture_bytes: Some(100),
+            ..Default::default()
+        };
+        let mut logs = Logs::default();
+        let inputs = vec![OsString::from("fits")];
+        let mut job = Job::start(&options, 2, 1, inputs.clone(), &mut logs).unwrap();
+        job.wait(&options, &mut logs).unwrap();
+        assert_eq!(job.take_output().stdout, b"fits\n");
+        drop(job);
+        assert!(!log_directory().join(job_file_name(2, &inputs)).exists());
+    }
+
+    #[test]
+    fn tagged_output() {
+        let options = Options {
+            mode: Mode::Simple,
+            program: "printf".to_string(),
+            program_args: vec!["one\\ntwo".to_string()],
+            group: true,
+            tag: true,
+            ..Default::default()
+        };
+        let mut logs = Logs::default();
+        let mut job = Job::start(&options, 1, 1, vec!["a".into(), "b".into()], &mut logs).unwrap();
+        job.wait(&options, &mut logs).unwrap();
+        assert_eq!(job.take_output().stdout, b"a b\tone\na b\ttwo\n");
+    }
+
+    #[test]
+    fn pipe_writes_the_block_to_every_attempt() {
+        let options = Options {
+            mode: Mode::Simple,
+            program: "sh".to_string(),
+            program_args: vec!["-c".to_string(), "tr a-z A-Z; exit 1".to_string()],
+            pipe: true,
+            group: true,
+            retries: 1,
+            ..Default::default()
+        };
+        let mut logs = Logs::default();
+        let mut job = Job::start(&options, 1, 1, vec!["one\ntwo\n".into()], &mut logs).unwrap();
+        job.wait(&options, &mut logs).unwrap();
+        assert_eq!(job.take_output().stdout, b"ONE\nTWO\nONE\nTWO\n");
+    }
+
+    fn forwarded(options: &Options, inputs: &[&str]) -> Vec<u8> {
+        let inputs = inputs.iter().map(OsString::from).collect();
+        let mut logs = Logs::default();
+        let mut job = Job::start(options, 1, 1, inputs, &mut logs).unwrap();
+        job.wait(options, &mut logs).unwrap();
+        job.take_output().stdout
+    }
+
+    #[test]
+    fn filter_forwards_inputs_that_succeed() {
+        let mut options = Options {
+            mode: Mode::Simple,
+            program: "sh".to_string(),
+            program_args: vec!["-c".to_string(), "echo $0; [ $0 = yes ]".to_string()],
+            filter: true,
+            ..Default::default()
+        };
+        assert_eq!(forwarded(&options, &["yes"]), b"yes\n");
+        assert_eq!(forwarded(&options, &["no"]), b"");
+        options.print0 = true;
+        options.retries = 1;
+        assert_eq!(forwarded(&options, &["yes"]), b"yes\0");
+        assert_eq!(forwarded(&options, &["no"]), b"");
+    }
+
+    #[test]
+    fn print0_ends_each_output_with_nul() {
+        let options = Options {
+            mode: Mode::Simple,
+            program: "printf".to_string(),
+            program_args: vec!["%s\\n".to_string()],
+            print0: true,
+            ..Default::default()
+        };
+        assert_eq!(forwarded(&options, &["a b"]), b"a b\0");
+        assert_eq!(forwarded(&options, &["a", "b"]), b"a\nb\0");
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::exec::JobResult;
+use crate::platform::exit_signal;
+use crate::shell;
+
+/// Column headings written at the top of a new TSV job log
+const TSV_HEADER: &str = "Seq\tStarttime\tJobRuntime\tExitval\tSignal\tInputHash\tCommand";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum JobLogFormat {
+    /// Tab-separated values with a header line, like GNU parallel's job log
+    #[default]
+    Tsv,
+    /// One JSON object per line
+    Json,
+}
+
+/// One invocation of the program, as written to the job log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobRecord {
+    /// Which job this was, counting from 1 in input order. Retries of a job
+    /// share its sequence number.
+    pub seq: usize,
+    /// Seconds since the Unix epoch
+    pub start: f64,
+    /// Seconds the invocation took
+    pub runtime: f64,
+    /// The exit code, or -1 if the process was killed by a signal
+    pub exit_code: i32,
+    /// The signal that killed the process, or 0
+    pub signal: i32,
+    /// Identifies the job by its inputs, see [`input_hash`]
+    pub input_hash: String,
+    /// The command line. TSV logs have it shell-quoted, with tabs and
+    /// newlines escaped, and it's split back into words when read.
+    pub command: Vec<String>,
+    /// What went wrong besides the exit status: that the process timed out,
+    /// or why it couldn't be started. Only written to JSON logs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobRecord {
+    pub fn new(
+        seq: usize,
+        inputs: &[OsString],
+        command: &[OsString],
+        start: SystemTime,
+        end: SystemTime,
+        result: JobResult,
+    ) -> Self {
+        let status = result.status();
+        Self {
+            seq,
+            start: start
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            runtime: end.duration_since(start).unwrap_or_default().as_secs_f64(),
+            exit_code: status.code().unwrap_or(-1),
+            signal: exit_signal(status).unwrap_or(0),
+            input_hash: input_hash(inputs),
+            command: command
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            error: result.error(),
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == 0 && self.signal == 0
+    }
+
+    fn to_tsv(&self) -> String {
+        let command = shell::join(&self.command)
+            .replace('\t', "\\t")
+            .replace('\n', "\\n");
+        format!(
+            "{}\t{:.3}\t{:.3}\t{}\t{}\t{}\t{command}",
+            self.seq, self.start, self.runtime, self.exit_code, self.signal, self.input_hash
+        )
+    }
+
+    fn from_tsv(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(7, '\t');
+        Some(Self {
+            seq: fields.next()?.parse().ok()?,
+            start: fields.next()?.parse().ok()?,
+            runtime: fields.next()?.parse().ok()?,
+            exit_code: fields.next()?.parse().ok()?,
+            signal: fields.next()?.parse().ok()?,
+            input_hash: fields.next()?.to_string(),
+            command: shell::split(&fields.next()?.replace("\\t", "\t").replace("\\n", "\n"))?,
+            error: None,
+        })
+    }
+}
+
+impl JobLogFormat {
+    /// Which format a job log is in, from its first line, or `None` if it
+    /// isn't a job log
+    pub fn detect(line: &str) -> Option<Self> {
+        if line == TSV_HEADER || JobRecord::from_tsv(line).is_some() {
+            Some(Self::Tsv)
+        } else if serde_json::from_str::<JobRecord>(line).is_ok() {
+            Some(Self::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// Identifies a job by its inputs, so that `--resume` can find it in the job
+/// log even if the inputs are read in a different order: the SHA-256 of the
+/// inputs, each followed by a NUL byte, truncated to 128 bits
+pub fn input_hash(inputs: &[OsString]) -> String {
+    let mut hasher = Sha256::new();
+    for input in inputs {
+        hasher.update(input.as_encoded_bytes());
+        hasher.update([0]);
+    }
+    hasher.finalize()[..16]
+        .iter()
+        .fold(String::with_capacity(32), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Reads every record from a job log
+///
+/// # Errors
+/// Will return an error if the log cannot be read, or contains malformed
+/// records
+pub fn read_records(path: &Path, format: JobLogFormat) -> anyhow::Result<Vec<JobRecord>> {
+    let mut records = vec![];
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let record = match format {
+            JobLogFormat::Tsv if line == TSV_HEADER => continue,
+            JobLogFormat::Tsv => JobRecord::from_tsv(&line),
+            JobLogFormat::Json => serde_json::from_str(&line).ok(),
+        };
+        records.push(
+            record.with_context(|| format!("malformed job log record on line {}", number + 1))?,
+        );
+    }
+    Ok(records)
+}
+
+/// The jobs to skip with `--resume` or `--resume-failed`, read from the job
+/// log of a previous run
+#[derive(Debug, Default)]
+pub struct Resume {
+    skip: HashSet<String>,
+}
+
+impl Resume {
+    /// Returns `None` unless resuming. A job log that doesn't exist yet means
+    /// nothing is skipped.
+    ///
+    /// # Errors
+    /// Will return an error if the job log exists but cannot be read
+    pub fn load(options: &crate::Options) -> anyhow::Result<Option<Self>> {
+        let Some(path) = options.joblog.as_deref() else {
+            return Ok(None);
+        };
+        if !(options.resume || options.resume_failed) {
+            return Ok(None);
+        }
+        if !path.exists() {
+            return Ok(Some(Self::default()));
+        }
+        let skip = read_records(path, options.joblog_format)?
+            .into_iter()
+            .filter(|record| options.resume || record.succeeded())
+            .map(|record| record.input_hash)
+            .collect();
+        Ok(Some(Self { skip }))
+    }
+
+    pub fn skips(&self, inputs: &[OsString]) -> bool {
+        self.skip.contains(&input_hash(inputs))
+    }
+}
+
+/// A record of every invocation, written with `--joblog`
+#[derive(Debug)]
+pub struct JobLog {
+    file: File,
+    format: JobLogFormat,
+}
+
+impl JobLog {
+    /// Opens (or creates) the log at `path` for appending
+    ///
+    /// # Errors
+    /// Will return an error if the file cannot be opened or written to
+    pub fn open(path: &Path, format: JobLogFormat) -> anyhow::Result<Self> {
+        let mut file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("opening job log")?;
+        if format == JobLogFormat::Tsv && file.metadata()?.len() == 0 {
+            writeln!(file, "{TSV_HEADER}").context("writing job log")?;
+        }
+        Ok(Self { file, format })
+    }
+
+    /// # Errors
+    /// Will return an error if the record cannot be written
+    pub fn record(&mut self, record: &JobRecord) -> anyhow::Result<()> {
+        let line = match self.format {
+            JobLogFormat::Tsv => record.to_tsv(),
+            JobLogFormat::Json => serde_json::to_string(record)?,
+        };
+        writeln!(self.file, "{line}").context("writing job log")?;
+        Ok(())
+    }
+}
+
+/// Totals printed at the end of a run with `--summary`
+#[derive(Debug, Default)]
+pub struct Summary {
+    runs: usize,
+    failed: usize,
+    /// Of the failed runs, those that timed out
+    timed_out: usize,
+    /// Of the failed runs, those that couldn't be started
+    not_started: usize,
+    total: Duration,
+    max: Duration,
+}
+
+impl Summary {
+    pub fn add(&mut self, runtime: Duration, result: JobResult) {
+        self.runs += 1;
+        self.failed += usize::from(!result.success());
+        self.timed_out += usize::from(matches!(result, JobResult::TimedOut(_)));
+        self.not_started += usize::from(matches!(result, JobResult::SpawnError(_)));
+        self.total += runtime;
+        self.max = self.max.max(runtime);
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mean = match self.runs {
+            0 => Duration::ZERO,
+            runs => self.total.div_f64(runs as f64),
+        };
+        let mut why = vec![];
+        if self.timed_out > 0 {
+            why.push(format!("{} timed out", self.timed_out));
+        }
+        if self.not_started > 0 {
+            why.push(format!("{} not started", self.not_started));
+        }
+        let why = match why.is_empty() {
+            true => String::new(),
+            false => format!(": {}", why.join(", ")),
+        };
+        write!(
+            f,
+            "Runs: {} ({} succeeded, {} failed{why}), total {:.3}s, mean {:.3}s, max {:.3}s",
+            self.runs,
+            self.runs - self.failed,
+            self.failed,
+            self.total.as_secs_f64(),
+            mean.as_secs_f64(),
+            self.max.as_secs_f64()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitStatus;
+
+    use super::*;
+    use crate::platform::exit_status;
+
+    fn record(status: ExitStatus) -> JobRecord {
+        result_record(JobResult::exited(status, false))
+    }
+
+    fn result_record(result: JobResult) -> JobRecord {
+        let start = UNIX_EPOCH + Duration::from_secs(100);
+        JobRecord::new(
+            3,
+            &["a b".into()],
+            &["echo".into(), "a b".into()],
+            start,
+            start + Duration::from_millis(1500),
+            result,
+        )
+    }
+
+    #[test]
+    fn tsv() {
+        let hash = input_hash(&["a b".into()]);
+        let failed = record(exit_status(2));
+        assert_eq!(
+            failed.to_tsv(),
+            format!("3\t100.000\t1.500\t2\t0\t{hash}\techo 'a b'")
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+
+            assert_eq!(
+                record(ExitStatus::from_raw(libc::SIGKILL)).to_tsv(),
+                format!("3\t100.000\t1.500\t-1\t9\t{hash}\techo 'a b'")
+            );
+        }
+        let parsed = JobRecord::from_tsv(&failed.to_tsv()).unwrap();
+        assert_eq!(parsed.input_hash, hash);
+        assert_eq!(parsed.command, ["echo", "a b"]);
+        assert!(!parsed.succeeded());
+        let tab = JobRecord {
+            command: vec!["printf".into(), "a\tb\n".into()],
+            ..failed
+        };
+        assert_eq!(JobRecord::from_tsv(&tab.to_tsv()).unwrap(), tab);
+    }
+
+    #[test]
+    fn detect_format() {
+        let record = record(exit_status(0));
+        assert_eq!(JobLogFormat::detect(TSV_HEADER), Some(JobLogFormat::Tsv));
+        assert_eq!(
+            JobLogFormat::detect(&record.to_tsv()),
+            Some(JobLogFormat::Tsv)
+        );
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(JobLogFormat::detect(&json), Some(JobLogFormat::Json));
+        assert_eq!(JobLogFormat::detect(r#"{"type":"summary"}"#), None);
+        assert_eq!(JobLogFormat::detect("0000	1.0	2.0	user"), None);
+    }
+
+    #[test]
+    fn input_hashes() {
+        let hash = input_hash(&["a".into(), "b".into()]);
+        assert_eq!(hash.len(), 32);
+        assert_ne!(hash, input_hash(&["ab".into()]));
+        assert_ne!(hash, input_hash(&["b".into(), "a".into()]));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let record = record(exit_status(0));
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains(r#""command":["echo","a b"]"#), "{json}");
+        assert_eq!(serde_json::from_str::<JobRecord>(&json).unwrap(), record);
+        assert!(record.succeeded());
+        assert!(!json.contains("error"), "{json}");
+    }
+
+    #[test]
+    fn errors() {
+        let not_found = result_record(JobResult::SpawnError(std::io::ErrorKind::NotFound));
+        assert_eq!((not_found.exit_code, not_found.signal), (127, 0));
+        assert_eq!(
+            not_found.error.as_deref(),
+            Some("could not start: entity not found")
+        );
+        let json = serde_json::to_string(&not_found).unwrap();
+        assert!(
+            json.ends_with(r#""error":"could not start: entity not found"}"#),
+            "{json}"
+        );
+        let timed_out = result_record(JobResult::TimedOut(exit_status(0)));
+        assert_eq!(timed_out.error.as_deref(), Some("timed out"));
+        assert!(timed_out.succeeded());
+    }
+
+    #[test]
+    fn summary() {
+        let mut summary = Summary::default();
+        summary.add(Duration::from_secs(1), JobResult::Success);
+        summary.add(Duration::from_secs(3), JobResult::ExitCode(exit_status(1)));
+        assert_eq!(
+            summary.to_string(),
+            "Runs: 2 (1 succeeded, 1 failed), total 4.000s, mean 2.000s, max 3.000s"
+        );
+        summary.add(Duration::from_secs(2), JobResult::TimedOut(exit_status(0)));
+        summary.add(
+            Duration::ZERO,
+            JobResult::SpawnError(std::io::ErrorKind::PermissionDenied),
+        );
+        assert_eq!(
+            summary.to_string(),
+            "Runs: 4 (1 succeeded, 3 failed: 1 timed out, 1 not started), total 6.000s, mean 1.500s, max 3.000s"
+        );
+    }
+}
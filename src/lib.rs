@@ -0,0 +1,1011 @@
+#![feature(iter_intersperse)]
+//! Runs a program for each set of inputs, like `xargs`, sequentially or in
+//! parallel.
+//!
+//! The command-line tool is a thin wrapper around this library, which can also
+//! be used to run chunked, parallel jobs from other programs:
+//!
+//! ```
+//! use arrgs::{Executor, Options, Parallel, Splitter};
+//!
+//! let options = Options::builder("echo").args(["hello"]).nargs(2).jobs(4).build();
+//! let inputs = Splitter::whitespace(&b"a b c d e"[..]);
+//! let statuses = Parallel.execute(&options, inputs)?;
+//! assert_eq!(statuses.len(), 3);
+//! assert!(statuses.iter().all(|status| status.success()));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, stdin, IsTerminal, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+pub use exec::{Closure, DryRun, Event, Executor, JobResult, Parallel, Remote, Sequential};
+pub use halt::{HaltPolicy, HaltWhen};
+pub use joblog::JobLogFormat;
+use progress::Progress;
+pub use remote::SshLogin;
+pub use results::OutputFormat;
+use signals::UntilSignalled;
+pub use split_input::{shuffled, unique, InputFormat, Splitter};
+use split_input::{Blocks, CsvInputs, JsonInputs};
+use throttle::Rate;
+
+mod audit;
+mod config;
+pub mod docs;
+pub mod exec;
+mod halt;
+mod interactive;
+mod job;
+mod joblog;
+mod metrics;
+mod naming;
+mod platform;
+mod progress;
+mod recording;
+mod remote;
+pub mod report;
+mod results;
+mod safety;
+pub mod shell;
+mod signals;
+mod split_input;
+mod template;
+mod throttle;
+mod timing;
+
+#[derive(Default, ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    #[default]
+    #[value(alias("sequential"))]
+    Simple,
+    Parallel,
+    Interactive,
+}
+
+/// When to show the progress line
+#[derive(Default, ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+enum ShowProgress {
+    /// When stderr is a terminal but stdout isn't, so it isn't mixed up with
+    /// the output
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Everything that controls a run, parsed from the command line or built with
+/// [`Options::builder`]
+#[derive(Parser, Debug, Clone, Default)]
+#[command(
+    name = "arrgs",
+    version,
+    about = "Run a program for each set of inputs, like xargs, sequentially or in parallel",
+    long_about = "Run a program for each set of inputs, like xargs, sequentially or in \
+                  parallel.\n\n\
+                  Inputs are read from stdin (or --arg-file), split on whitespace by \
+                  default, and passed to PROGRAM as extra arguments, or in place of {} \
+                  placeholders in its arguments.",
+    after_long_help = "Other commands:\n  \
+                       arrgs report FILES...        Summarize the jobs in job logs, results or audit logs\n  \
+                       arrgs completions SHELL      Write a shell completion script\n  \
+                       arrgs --help-man             Write this help as a man page\n\n\
+                       To run a program named `report` or `completions`, put `--` before \
+                       it: `arrgs -- report`."
+)]
+pub struct Options {
+    /// Use null-separated inputs, e.g. output from `find -0`
+    #[arg(short = '0', long)]
+    nul: bool,
+
+    /// Split inputs on this character or string instead of whitespace.
+    /// Supports the escapes `\n`, `\t`, `\0` and `\\`.
+    #[arg(short = 'd', long, value_parser = parse_delimiter, conflicts_with = "nul")]
+    delimiter: Option<String>,
+
+    /// Read inputs from this file instead of stdin, which child processes then
+    /// inherit. Can be given more than once, to read each file in turn.
+    #[arg(short = 'a', long, value_name = "PATH", conflicts_with = "pipe")]
+    arg_file: Vec<PathBuf>,
+
+    /// How to read the inputs. With `json`, if the elements are objects,
+    /// each one is a job whose inputs are its values, which `{name}`
+    /// placeholders refer to by key. With `csv` or `tsv`, each record is a
+    /// job whose inputs are its columns, which `{name}` placeholders refer to
+    /// by the header's names.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = InputFormat::Text,
+        conflicts_with_all = ["nul", "delimiter", "pipe"]
+    )]
+    input_format: InputFormat,
+
+    /// With `--input-format csv` or `tsv`, the first record is inputs rather
+    /// than the names of the columns
+    #[arg(long)]
+    no_header: bool,
+
+    /// The names of the inputs, from structured input, for `{name}`
+    /// placeholders
+    #[arg(skip)]
+    fields: Vec<String>,
+
+    /// Skip inputs that have already been seen, keeping the first of each
+    #[arg(long, conflicts_with_all = ["pipe", "input_format"])]
+    unique: bool,
+
+    /// Run the inputs in a random order. They're all read before the first
+    /// job starts.
+    #[arg(long, conflicts_with_all = ["pipe", "input_format"])]
+    shuffle: bool,
+
+    /// Split stdin into blocks of whole lines (or `-0`/`-d` records) and
+    /// write each block to a process's stdin, instead of passing inputs as
+    /// arguments
+    #[arg(long, conflicts_with_all = ["nargs", "max_chars", "replace", "tag"])]
+    pipe: bool,
+
+    /// With `--pipe`, the most bytes to put in each block, e.g. `512k` or
+    /// `4M`. A record longer than this gets a block to itself.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "1M")]
+    block: usize,
+
+    /// With `--pipe`, put this many records in each block instead of
+    /// limiting their size
+    #[arg(long, value_name = "N", requires = "pipe")]
+    block_records: Option<usize>,
+
+    /// Maximum number of inputs to pass to the sub-command at a time.
+    /// Defaults to 1, or to as many as fit in `--max-chars` if that is given.
+    #[arg(short = 'n', long)]
+    nargs: Option<usize>,
+
+    /// Use each line as one input, keeping any spaces in it, and pass up to
+    /// this many lines to each process, like `xargs -L`. Blank lines are
+    /// skipped.
+    #[arg(
+        short = 'L',
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = ["nargs", "nul", "delimiter", "input_format", "pipe"]
+    )]
+    max_lines: Option<usize>,
+
+    /// Maximum length of each command line in bytes, counting a terminating
+    /// NUL for each argument. Defaults to the system's `ARG_MAX`, less the
+    /// size of the environment, up to 128KiB.
+    #[arg(short = 's', long)]
+    max_chars: Option<usize>,
+
+    /// Use this token for all of the inputs instead of `{}`, and never append
+    /// the inputs, e.g. `-I % cp % %.bak`
+    #[arg(short = 'I', value_name = "REPLACE")]
+    replace: Option<String>,
+
+    /// Run the program and its arguments as a shell script with `sh -c`,
+    /// replacing the placeholders with the shell-quoted inputs, e.g.
+    /// `--shell 'convert {} {.}.png'`
+    #[arg(long)]
+    shell: bool,
+
+    /// Shell-quote each input that's substituted into (or appended to) the
+    /// arguments, for programs that pass them on to a shell, e.g. `ssh host`
+    /// or `sh -c`. The inputs in `--shell` scripts are always quoted.
+    #[arg(short = 'q', long)]
+    quote: bool,
+
+    /// Maximum number of processes to run at once in parallel and interactive
+    /// mode (0 means no limit, or one per CPU with `--pipe`)
+    #[arg(short = 'P', long, default_value = "0")]
+    jobs: usize,
+
+    /// Terminate any process that runs for longer than this many seconds
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// Re-run a process that fails up to this many times before counting it
+    /// as failed
+    #[arg(long, default_value = "0")]
+    retries: usize,
+
+    /// Seconds to wait before the first retry, doubling for each retry after
+    /// that
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds, default_value = "0")]
+    retry_delay: Duration,
+
+    /// Seconds to wait between starting one job and the next (ignored in
+    /// interactive mode)
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds, default_value = "0")]
+    delay: Duration,
+
+    /// The most jobs to start per second, or per minute or hour with `N/m`
+    /// or `N/h`, e.g. for commands that call a rate-limited API (ignored in
+    /// interactive mode)
+    #[arg(long, value_name = "N[/PERIOD]")]
+    rate: Option<Rate>,
+
+    /// Only start a job while the 1-minute load average is below this
+    /// (ignored in interactive mode)
+    #[arg(long, value_name = "LOAD")]
+    load_max: Option<f64>,
+
+    /// Only start a job while at least this much memory is available, e.g.
+    /// `2G` (ignored in interactive mode)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memfree: Option<usize>,
+
+    /// Run each job at this niceness, from -20 (most favourable scheduling)
+    /// to 19 (least), e.g. `10` to keep a shared machine responsive
+    #[arg(
+        long,
+        value_name = "N",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19)
+    )]
+    nice: Option<i32>,
+
+    /// The most memory (address space) each job may use, e.g. `2G`. Past
+    /// this, the job's allocations fail.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    memory_limit: Option<usize>,
+
+    /// The most CPU time each job may use, in seconds. Past this, the job is
+    /// sent `SIGXCPU`, then killed a second later.
+    #[arg(long, value_name = "SECS", value_parser = parse_seconds)]
+    cpu_limit: Option<Duration>,
+
+    /// Run jobs on another machine over SSH, as `[N/]LOGIN` to run up to `N`
+    /// at once there (default `--jobs`, or 1), e.g. `4/user@host`. `:` is
+    /// this machine. Can be given more than once, to share jobs between
+    /// machines.
+    #[arg(long, value_name = "[N/]LOGIN")]
+    sshlogin: Vec<SshLogin>,
+
+    /// The command to run for `--sshlogin`, followed by the login and the
+    /// remote command
+    #[arg(long, value_name = "CMD", default_value = "ssh", requires = "sshlogin")]
+    ssh: String,
+
+    /// With `--sshlogin`, copy each input that is a file to the same path
+    /// (relative to the home directory) on the remote machine before running
+    /// the command there
+    #[arg(long, requires = "sshlogin", conflicts_with = "pipe")]
+    transfer: bool,
+
+    /// With `--sshlogin`, copy this file back from the remote machine after
+    /// running the command there. Placeholders are replaced as in the
+    /// program arguments, e.g. `--return {.}.out`. Can be given more than
+    /// once.
+    #[arg(long = "return", value_name = "PATH", requires = "sshlogin")]
+    return_files: Vec<String>,
+
+    /// With `--sshlogin`, remove the transferred and returned files from the
+    /// remote machine afterwards
+    #[arg(long, requires = "sshlogin")]
+    cleanup: bool,
+
+    /// Print the commands that would be run, without running them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print each command to stderr before running it (ignored in interactive
+    /// mode)
+    #[arg(short = 't', long)]
+    verbose: bool,
+
+    /// Ask on the terminal before running each command: `y` to run it, `a`
+    /// to run it and the rest without asking, anything else to skip it
+    /// (ignored in interactive mode)
+    #[arg(short = 'p', long = "interactive")]
+    prompt: bool,
+
+    /// Capture each process's output and print it all at once when it exits,
+    /// rather than letting the output of parallel processes interleave
+    /// (ignored in interactive mode)
+    #[arg(long)]
+    group: bool,
+
+    /// With `--group`, print each process's output in the order its inputs
+    /// were read, rather than the order the processes finished
+    #[arg(short = 'k', long, requires = "group")]
+    keep_order: bool,
+
+    /// Prefix each line of output with the inputs of the process that wrote
+    /// it, followed by a tab (ignored in interactive mode)
+    #[arg(long)]
+    tag: bool,
+
+    /// Instead of each process's output, print the inputs of the ones that
+    /// succeeded, one per line, so that arrgs can filter a list for the next
+    /// command in a pipeline. With `--pipe`, blocks are printed as they were
+    /// read. (Ignored in interactive mode.)
+    #[arg(long, conflicts_with = "output_capture")]
+    filter: bool,
+
+    /// End each input printed by `--filter` with a NUL rather than a newline,
+    /// for `--null` in the next command. Without `--filter`, print each
+    /// process's output followed by a NUL instead, less one trailing newline.
+    /// (Ignored in interactive mode.)
+    #[arg(long, conflicts_with = "output_capture")]
+    print0: bool,
+
+    /// When to stop because of failed jobs: `never`, `soon` (stop starting
+    /// new jobs), or `now` (also terminate running jobs), optionally with
+    /// `fail=N` to tolerate up to N failures, e.g. `now,fail=3`
+    #[arg(long, default_value = "never")]
+    halt: HaltPolicy,
+
+    /// Set this environment variable for every process, along with the
+    /// `ARRGS_JOB`, `ARRGS_SLOT`, `ARRGS_INPUT` and `ARRGS_INPUT_1`..`N`
+    /// variables that describe its job. Can be given more than once.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// Show a live count of finished, failed and running jobs on stderr, with
+    /// an estimate of the time left once all of the inputs have been read
+    /// (ignored in interactive mode, with `-p` and with `--dry-run`)
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ShowProgress::Auto)]
+    progress: ShowProgress,
+
+    /// Display mode
+    #[arg(short = 'm', long, value_enum, default_value_t = Mode::Simple)]
+    mode: Mode,
+
+    /// Read default options, and key bindings and colors for interactive
+    /// mode, from this file instead of `~/.config/arrgs/config.toml`
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// The key bindings and colors for interactive mode, from the config file
+    #[arg(skip)]
+    tui: config::Tui,
+
+    /// Commands that `--confirm-destructive` always or never asks about, from
+    /// the config file
+    #[arg(skip)]
+    safety: safety::Rules,
+
+    /// The program to invoke for each set of inputs
+    #[arg(
+        required_unless_present = "replay",
+        default_value = "",
+        hide_default_value = true
+    )]
+    program: String,
+
+    /// Additional arguments to the program. Placeholders in these are
+    /// replaced with the inputs: `{}` (all of them), `{1}`..`{n}` (one of
+    /// them), `{.}` (without extensions), `{/}` (file names), `{//}`
+    /// (directories) and `{#}` (the job number). Without any placeholders,
+    /// the inputs are added after these arguments.
+    program_args: Vec<String>,
+
+    /// Simulate a program feeding the UI some inputs. (`-s` is `--max-chars`,
+    /// as in xargs.)
+    #[arg(short = 'S', long)]
+    simulate: bool,
+
+    /// Record the interactive session to this file: every input, line of
+    /// output and exit, with when it happened, for `--replay`
+    #[arg(long, value_name = "PATH")]
+    record: Option<PathBuf>,
+
+    /// Play back a session recorded with `--record` in the interactive TUI,
+    /// instead of running anything
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["record", "simulate", "dry_run"])]
+    replay: Option<PathBuf>,
+
+    /// Maximum bytes of output to keep in memory per job, in the interactive
+    /// TUI or with `--group`, `--output-capture` or `--print0`. Beyond this,
+    /// output is only written to a log file on disk, which is kept after the
+    /// run.
+    #[arg(long, value_name = "N")]
+    max_capture_bytes: Option<usize>,
+
+    /// Ask for confirmation before running commands that look destructive,
+    /// e.g. `rm -rf` or `dd of=...`. The config file's `[safety]` section
+    /// can allow or deny commands of its own.
+    #[arg(long)]
+    confirm_destructive: bool,
+
+    /// Append a hash-chained record of every executed command to this file
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+
+    /// Append a record of every run of the program (sequence number, start
+    /// time, runtime, exit code, signal and command) to this file
+    #[arg(long, value_name = "PATH")]
+    joblog: Option<PathBuf>,
+
+    /// Format of the `--joblog`
+    #[arg(long, value_enum, default_value_t = JobLogFormat::Tsv)]
+    joblog_format: JobLogFormat,
+
+    /// Print the number of runs that succeeded and failed, and their total,
+    /// mean and longest runtimes, to stderr at the end
+    #[arg(long)]
+    summary: bool,
+
+    /// Print statistics about how long the runs took to stderr at the end:
+    /// the wall-clock time, and the user and system CPU time where that's
+    /// available, with a histogram and the slowest runs
+    #[arg(long)]
+    time: bool,
+
+    /// Write the results of every job at the end (its command, inputs, exit
+    /// status, start time, duration and number of attempts), followed by the
+    /// totals, to stdout or `--output-file`
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output: Option<OutputFormat>,
+
+    /// Write the `--output` results to this file instead of stdout
+    #[arg(long, value_name = "PATH", requires = "output")]
+    output_file: Option<PathBuf>,
+
+    /// Include each job's stdout and stderr in the `--output` results,
+    /// instead of printing them. Without `--output-file`, this keeps stdout
+    /// for the results alone. (Ignored in interactive mode.)
+    #[arg(long, requires = "output")]
+    output_capture: bool,
+
+    /// Skip jobs whose inputs are already recorded in the `--joblog`, whether
+    /// they succeeded or not (ignored in interactive mode)
+    #[arg(long, requires = "joblog", conflicts_with = "resume_failed")]
+    resume: bool,
+
+    /// Skip jobs whose inputs are recorded as having succeeded in the
+    /// `--joblog`, re-running the ones that failed (ignored in interactive
+    /// mode)
+    #[arg(long, requires = "joblog")]
+    resume_failed: bool,
+
+    /// Replace the interactive TUI with a plain-text log of process events,
+    /// suited to screen readers. Used automatically when `TERM` is `dumb` or
+    /// empty.
+    #[arg(long)]
+    accessible: bool,
+}
+
+/// Runs the program for the inputs read from stdin (or blocks of stdin with
+/// `--pipe`), as the command line does,
+/// returning the exit code for arrgs
+///
+/// `SIGINT` and `SIGTERM` are passed on to the running children, which are
+/// killed if they don't exit within the grace period. arrgs then exits as a
+/// shell reports a process killed by that signal.
+///
+/// # Errors
+/// Will return an error if the program could not be run, or the interactive
+/// TUI failed
+pub fn run(mut options: Options) -> anyhow::Result<ExitCode> {
+    if options.pipe && options.mode == Mode::Interactive {
+        anyhow::bail!("--pipe isn't supported in interactive mode");
+    }
+    if !options.sshlogin.is_empty() && options.mode == Mode::Interactive {
+        anyhow::bail!("--sshlogin isn't supported in interactive mode");
+    }
+    if options.dry_run {
+        let inputs = read_inputs(&mut options)?;
+        DryRun.execute(&options, inputs)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if options.mode == Mode::Interactive || options.replay.is_some() {
+        // The TUI reads inputs itself, so there are no samples to show
+        confirm_destructive(&options, &mut std::iter::empty())?;
+        signals::catch_termination()?;
+        let result = interactive::run(options);
+        naming::remove_log_directory();
+        result?;
+        return Ok(signals::received().map_or(ExitCode::SUCCESS, signal_exit_code));
+    }
+    let mut inputs = read_inputs(&mut options)?;
+    let samples = confirm_destructive(&options, &mut inputs)?;
+    // Only once confirmed, so Ctrl-C still interrupts the confirmation prompt
+    signals::catch_termination()?;
+    let inputs = samples.into_iter().chain(inputs);
+    let mut progress = show_progress(&options).then(|| Progress::new(io::stderr()));
+    let mut on_event = |event| {
+        if let Some(progress) = progress.as_mut() {
+            progress.update(event);
+        }
+    };
+    let results = match options.mode {
+        _ if !options.sshlogin.is_empty() => Remote.execute_with(&options, inputs, &mut on_event),
+        Mode::Simple => Sequential.execute_with(&options, inputs, &mut on_event),
+        Mode::Parallel => Parallel.execute_with(&options, inputs, &mut on_event),
+        Mode::Interactive => unreachable!(),
+    };
+    naming::remove_log_directory();
+    match results {
+        Ok(results) => Ok(signals::received().map_or_else(
+            || ExitCode::from(exec::exit_code(&results)),
+            signal_exit_code,
+        )),
+        Err(e) => match e.downcast_ref::<exec::SpawnError>() {
+            Some(exec::SpawnError(spawn_error)) => {
+                eprintln!("arrgs: {}: {spawn_error}", options.program);
+                Ok(ExitCode::from(exec::spawn_failure_code(spawn_error.kind())))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Whether to show the progress line, which would get in the way of `-p`
+/// asking on the terminal
+fn show_progress(options: &Options) -> bool {
+    match options.progress {
+        _ if options.prompt => false,
+        ShowProgress::Auto => io::stderr().is_terminal() && !io::stdout().is_terminal(),
+        ShowProgress::Always => true,
+        ShowProgress::Never => false,
+    }
+}
+
+/// The exit code for having been stopped by `signal`, following shells
+fn signal_exit_code(signal: i32) -> ExitCode {
+    ExitCode::from(128u8.saturating_add(u8::try_from(signal).unwrap_or(u8::MAX)))
+}
+
+impl Options {
+    /// Parses the command line like [`Parser::parse`], with defaults from the
+    /// config file: `--config`, or else `~/.config/arrgs/config.toml` if it
+    /// exists
+    ///
+    /// # Errors
+    /// Will return an error if the config file can't be read, or is invalid
+    pub fn parse_with_config<I, T>(args: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        config::parse_options(args)
+    }
+
+    /// Starts building options for running `program`, with the same defaults
+    /// as the command line
+    pub fn builder(program: impl Into<String>) -> OptionsBuilder {
+        OptionsBuilder {
+            options: Self {
+                program: program.into(),
+                ..Self::default()
+            },
+        }
+    }
+}
+
+/// Builds [`Options`] without parsing a command line. Each method corresponds
+/// to the command-line option of the same name.
+#[derive(Debug, Clone)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// Arguments passed to the program before the inputs
+    pub fn args<I: IntoIterator<Item = S>, S: Into<String>>(mut self, args: I) -> Self {
+        self.options.program_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn nargs(mut self, nargs: usize) -> Self {
+        self.options.nargs = Some(nargs);
+        self
+    }
+
+    /// Implies splitting the inputs with [`Splitter::lines`]
+    pub fn max_lines(mut self, lines: usize) -> Self {
+        self.options.max_lines = Some(lines);
+        self
+    }
+
+    pub fn max_chars(mut self, max_chars: usize) -> Self {
+        self.options.max_chars = Some(max_chars);
+        self
+    }
+
+    pub fn replace(mut self, token: impl Into<String>) -> Self {
+        self.options.replace = Some(token.into());
+        self
+    }
+
+    pub fn shell(mut self, shell: bool) -> Self {
+        self.options.shell = shell;
+        self
+    }
+
+    pub fn quote(mut self, quote: bool) -> Self {
+        self.options.quote = quote;
+        self
+    }
+
+    /// Writes blocks of up to `block` bytes of input to each process's stdin
+    pub fn pipe(mut self, block: usize) -> Self {
+        self.options.pipe = true;
+        self.options.block = block;
+        self
+    }
+
+    /// Implies [`pipe`](Self::pipe)
+    pub fn block_records(mut self, records: usize) -> Self {
+        self.options.pipe = true;
+        self.options.block_records = Some(records);
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.options.jobs = jobs;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retries(mut self, retries: usize, delay: Duration) -> Self {
+        self.options.retries = retries;
+        self.options.retry_delay = delay;
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.options.delay = delay;
+        self
+    }
+
+    /// `--rate`, as the time to leave between starting jobs
+    pub fn rate(mut self, interval: Duration) -> Self {
+        self.options.rate = Some(Rate { interval });
+        self
+    }
+
+    pub fn load_max(mut self, load: f64) -> Self {
+        self.options.load_max = Some(load);
+        self
+    }
+
+    /// `--memfree`, in bytes
+    pub fn memfree(mut self, bytes: usize) -> Self {
+        self.options.memfree = Some(bytes);
+        self
+    }
+
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.options.nice = Some(nice);
+        self
+    }
+
+    /// `--memory-limit`, in bytes
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.options.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn cpu_limit(mut self, limit: Duration) -> Self {
+        self.options.cpu_limit = Some(limit);
+        self
+    }
+
+    /// Adds a `--sshlogin`
+    pub fn sshlogin(mut self, login: SshLogin) -> Self {
+        self.options.sshlogin.push(login);
+        self
+    }
+
+    pub fn ssh(mut self, command: impl Into<String>) -> Self {
+        self.options.ssh = command.into();
+        self
+    }
+
+    pub fn transfer(mut self, transfer: bool) -> Self {
+        self.options.transfer = transfer;
+        self
+    }
+
+    /// Adds a `--return` file
+    pub fn return_file(mut self, path: impl Into<String>) -> Self {
+        self.options.return_files.push(path.into());
+        self
+    }
+
+    pub fn cleanup(mut self, cleanup: bool) -> Self {
+        self.options.cleanup = cleanup;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = verbose;
+        self
+    }
+
+    /// `-p`/`--interactive`
+    pub fn prompt(mut self, prompt: bool) -> Self {
+        self.options.prompt = prompt;
+        self
+    }
+
+    pub fn group(mut self, group: bool) -> Self {
+        self.options.group = group;
+        self
+    }
+
+    /// Implies [`group`](Self::group)
+    pub fn keep_order(mut self, keep_order: bool) -> Self {
+        self.options.group |= keep_order;
+        self.options.keep_order = keep_order;
+        self
+    }
+
+    pub fn tag(mut self, tag: bool) -> Self {
+        self.options.tag = tag;
+        self
+    }
+
+    pub fn filter(mut self, filter: bool) -> Self {
+        self.options.filter = filter;
+        self
+    }
+
+    pub fn print0(mut self, print0: bool) -> Self {
+        self.options.print0 = print0;
+        self
+    }
+
+    pub fn halt(mut self, halt: HaltPolicy) -> Self {
+        self.options.halt = halt;
+        self
+    }
+
+    pub fn audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.audit_log = Some(path.into());
+        self
+    }
+
+    pub fn joblog(mut self, path: impl Into<PathBuf>, format: JobLogFormat) -> Self {
+        self.options.joblog = Some(path.into());
+        self.options.joblog_format = format;
+        self
+    }
+
+    pub fn summary(mut self, summary: bool) -> Self {
+        self.options.summary = summary;
+        self
+    }
+
+    pub fn time(mut self, time: bool) -> Self {
+        self.options.time = time;
+        self
+    }
+
+    /// Writes the results of every job at the end, to stdout or `path`
+    pub fn output(mut self, format: OutputFormat, path: Option<PathBuf>) -> Self {
+        self.options.output = Some(format);
+        self.options.output_file = path;
+        self
+    }
+
+    pub fn output_capture(mut self, capture: bool) -> Self {
+        self.options.output_capture = capture;
+        self
+    }
+
+    /// Adds a `--env` variable
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn build(self) -> Options {
+        self.options
+    }
+}
+
+/// Inputs as they're read, from wherever they come from
+type Inputs = Box<dyn Iterator<Item = OsString> + Send>;
+
+/// Reads inputs from each `--arg-file` in turn, or from stdin. Each file is
+/// split separately, so the last input in one can't run into the first in the
+/// next. With `--pipe`, each input is a block of stdin.
+///
+/// Structured input is read up to its first record, to find the names of its
+/// fields for the placeholders, which `options` is updated with. Each record
+/// is then one job.
+///
+/// # Errors
+/// Will return an error if an `--arg-file` cannot be opened, or structured
+/// input doesn't start with a valid record
+fn read_inputs(options: &mut Options) -> anyhow::Result<Inputs> {
+    if options.pipe {
+        let delimiter = if options.nul {
+            "\0"
+        } else {
+            options.delimiter.as_deref().unwrap_or("\n")
+        };
+        let blocks = Blocks::new(UntilSignalled(stdin()), delimiter.as_bytes(), options.block);
+        return Ok(match options.block_records {
+            Some(records) => Box::new(blocks.records(records)),
+            None => Box::new(blocks),
+        });
+    }
+    match options.input_format {
+        InputFormat::Text => {}
+        InputFormat::Json => {
+            let inputs = JsonInputs::new(input_reader(options)?).context("reading JSON input")?;
+            let fields = inputs.fields().to_vec();
+            if !fields.is_empty() {
+                one_job_per_record(options, fields.len(), fields);
+            }
+            return Ok(Box::new(inputs));
+        }
+        InputFormat::Csv | InputFormat::Tsv => {
+            let delimiter = if options.input_format == InputFormat::Csv {
+                b','
+            } else {
+                b'\t'
+            };
+            let inputs = CsvInputs::new(input_reader(options)?, delimiter, !options.no_header);
+            one_job_per_record(options, inputs.columns(), inputs.fields().to_vec());
+            return Ok(Box::new(inputs));
+        }
+    }
+    if options.arg_file.is_empty() {
+        let inputs = split_inputs(options, UntilSignalled(stdin()));
+        return Ok(adapt_inputs(options, inputs));
+    }
+    let files = open_arg_files(options)?;
+    let split_options = options.clone();
+    let inputs = files
+        .into_iter()
+        .flat_map(move |file| split_inputs(&split_options, file));
+    Ok(adapt_inputs(options, inputs))
+}
+
+/// Applies `--unique` and `--shuffle` to text inputs
+fn adapt_inputs(
+    options: &Options,
+    inputs: impl Iterator<Item = OsString> + Send + 'static,
+) -> Inputs {
+    let inputs: Inputs = if options.unique {
+        Box::new(unique(inputs))
+    } else {
+        Box::new(inputs)
+    };
+    if options.shuffle {
+        Box::new(shuffled(inputs, fastrand::u64(..)))
+    } else {
+        inputs
+    }
+}
+
+/// Makes each record of `columns` inputs one job, with `fields` naming them
+fn one_job_per_record(options: &mut Options, columns: usize, fields: Vec<String>) {
+    options.nargs = Some(columns.max(1));
+    options.fields = fields;
+}
+
+fn open_arg_files(options: &Options) -> anyhow::Result<Vec<File>> {
+    options
+        .arg_file
+        .iter()
+        .map(|path| File::open(path).with_context(|| format!("opening {}", path.display())))
+        .collect()
+}
+
+/// Stdin, or else each `-a` file in turn as one stream
+fn input_reader(options: &Options) -> anyhow::Result<Box<dyn Read + Send>> {
+    if options.arg_file.is_empty() {
+        return Ok(Box::new(UntilSignalled(stdin())));
+    }
+    let empty: Box<dyn Read + Send> = Box::new(io::empty());
+    Ok(open_arg_files(options)?
+        .into_iter()
+        .fold(empty, |reader, file| Box::new(reader.chain(file))))
+}
+
+fn split_inputs<R: Read>(options: &Options, reader: R) -> Splitter<R> {
+    if options.max_lines.is_some() {
+        Splitter::lines(reader)
+    } else if options.nul {
+        Splitter::null(reader)
+    } else if let Some(delimiter) = &options.delimiter {
+        Splitter::delimiter(reader, delimiter.as_bytes())
+    } else {
+        Splitter::whitespace(reader)
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("`{value}` is not a valid number of seconds"))
+}
+
+/// Parses a number of bytes, optionally with a `k`, `M` or `G` suffix for
+/// multiples of 1024
+fn parse_size(value: &str) -> Result<usize, String> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("`{value}` is not a valid size"))
+}
+
+fn parse_env(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("`{value}` is not of the form KEY=VALUE")),
+    }
+}
+
+/// Parses the escape sequences allowed in `--delimiter`
+fn parse_delimiter(value: &str) -> Result<String, String> {
+    let mut delimiter = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            delimiter.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => delimiter.push('\n'),
+            Some('t') => delimiter.push('\t'),
+            Some('0') => delimiter.push('\0'),
+            Some('\\') => delimiter.push('\\'),
+            Some(other) => return Err(format!("unknown escape sequence `\\{other}`")),
+            None => return Err("trailing `\\` in delimiter".to_string()),
+        }
+    }
+    if delimiter.is_empty() {
+        return Err("delimiter must not be empty".to_string());
+    }
+    Ok(delimiter)
+}
+
+/// Asks for confirmation if the command looks destructive, returning the
+/// inputs that were read to show as samples. These need to be run along with
+/// the rest of the inputs.
+///
+/// # Errors
+/// Will return an error if the command looks destructive and the user did not
+/// confirm running it
+fn confirm_destructive(
+    options: &Options,
+    inputs: &mut impl Iterator<Item = OsString>,
+) -> anyhow::Result<Vec<OsString>> {
+    if !options.confirm_destructive {
+        return Ok(vec![]);
+    }
+    let reason = if options.shell {
+        safety::destructive_script_reason(&exec::shell_template(options), &options.safety)
+    } else {
+        safety::destructive_reason(&options.program, &options.program_args, &options.safety)
+    };
+    let Some(reason) = reason else {
+        return Ok(vec![]);
+    };
+    let samples: Vec<OsString> = inputs
+        .take(safety::SAMPLE_COMMANDS * options.nargs.or(options.max_lines).unwrap_or(1))
+        .collect();
+    if !safety::confirm(options, reason, &samples)? {
+        anyhow::bail!("Not running destructive command without confirmation");
+    }
+    Ok(samples)
+}
@@ -0,0 +1,76 @@
+//! System load and memory, for holding off starting jobs while the system is
+//! busy (`--load-max` and `--memfree`)
+
+/// The 1-minute load average, if it can be read
+#[cfg(unix)]
+pub fn load_average() -> Option<f64> {
+    let mut load = [0.0f64; 1];
+    // SAFETY: `load` is valid for writes of one `f64`
+    let samples = unsafe { libc::getloadavg(load.as_mut_ptr(), 1) };
+    (samples == 1).then_some(load[0])
+}
+
+#[cfg(not(unix))]
+pub fn load_average() -> Option<f64> {
+    None
+}
+
+/// Bytes of memory available for new processes without swapping, if it can
+/// be read: `MemAvailable` from `/proc/meminfo`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    kib.checked_mul(1024)
+}
+
+/// Bytes of memory available for new processes, if it can be read: the free
+/// page count from `sysctl`, which doesn't count reclaimable caches
+#[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+pub fn available_memory() -> Option<u64> {
+    #[cfg(target_vendor = "apple")]
+    const FREE_PAGES: &std::ffi::CStr = c"vm.page_free_count";
+    #[cfg(target_os = "freebsd")]
+    const FREE_PAGES: &std::ffi::CStr = c"vm.stats.vm.v_free_count";
+
+    let mut pages: u32 = 0;
+    let mut len = std::mem::size_of::<u32>();
+    // SAFETY: `pages` is valid for writes of `len` bytes, and no new value is
+    // being set
+    let result = unsafe {
+        libc::sysctlbyname(
+            FREE_PAGES.as_ptr(),
+            (&raw mut pages).cast(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    // SAFETY: `sysconf` has no memory safety requirements
+    let page_size = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).ok()?;
+    (result == 0).then(|| u64::from(pages) * page_size)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd"
+)))]
+pub fn available_memory() -> Option<u64> {
+    None
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_metrics() {
+        assert!(load_average().is_some_and(|load| load >= 0.0));
+        assert!(available_memory().is_some_and(|bytes| bytes > 0));
+    }
+}
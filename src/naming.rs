@@ -0,0 +1,97 @@
+//! Names for the files that jobs' output is saved to
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// The most characters of a job's inputs to put in its file name
+const MAX_INPUTS_LEN: usize = 48;
+
+/// A file name for job number `seq`, e.g. `0007-photos_a.jpg.log`: the job
+/// number first, since different inputs can look the same once they're made
+/// safe for a file name, then as much of the inputs as fits, with anything
+/// but letters, digits, `.`, `-` and `_` replaced
+pub fn job_file_name(seq: usize, inputs: &[OsString]) -> String {
+    let inputs = inputs.join(OsStr::new(" "));
+    let mut cleaned = String::new();
+    for c in inputs.to_string_lossy().chars() {
+        if c.is_alphanumeric() || matches!(c, '.' | '-') {
+            cleaned.push(c);
+        } else if !cleaned.is_empty() && !cleaned.ends_with('_') {
+            cleaned.push('_');
+        }
+    }
+    let cleaned: String = cleaned.chars().take(MAX_INPUTS_LEN).collect();
+    let cleaned = cleaned.trim_end_matches('_');
+    if cleaned.is_empty() {
+        format!("{seq:04}.log")
+    } else {
+        format!("{seq:04}-{cleaned}.log")
+    }
+}
+
+/// `path` if nothing exists there yet, or else the first of `path-2`,
+/// `path-3`, ... (before any extension) that's free
+pub fn unused_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_owned();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{stem}-{n}{extension}")))
+        .find(|path| !path.exists())
+        .expect("some path is unused")
+}
+
+/// Directory where the full output of jobs is written when output capture is
+/// limited by `--max-capture-bytes`
+pub fn log_directory() -> PathBuf {
+    std::env::temp_dir().join(format!("arrgs-{}", std::process::id()))
+}
+
+/// Removes the [`log_directory`] once the run is over, unless it still holds
+/// the logs of output that was truncated
+pub fn remove_log_directory() {
+    let _ = std::fs::remove_dir(log_directory());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let name = |seq, inputs: &[&str]| {
+            let inputs: Vec<OsString> = inputs.iter().map(OsString::from).collect();
+            job_file_name(seq, &inputs)
+        };
+        assert_eq!(name(7, &["photos/a.jpg"]), "0007-photos_a.jpg.log");
+        assert_eq!(name(8, &["photos a.jpg"]), "0008-photos_a.jpg.log");
+        assert_eq!(name(12345, &["/../", "$x", "ü"]), "12345-.._x_ü.log");
+        assert_eq!(name(1, &[]), "0001.log");
+        assert_eq!(name(2, &["***"]), "0002.log");
+        assert_eq!(
+            name(3, &["x".repeat(100).as_str()]),
+            format!("0003-{}.log", "x".repeat(48))
+        );
+    }
+
+    #[test]
+    fn unused_paths() {
+        let dir = std::env::temp_dir().join(format!("arrgs-naming-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.log");
+        assert_eq!(unused_path(&path), path);
+        std::fs::write(&path, "").unwrap();
+        std::fs::write(dir.join("out-2.log"), "").unwrap();
+        assert_eq!(unused_path(&path), dir.join("out-3.log"));
+        assert_eq!(
+            unused_path(&dir),
+            dir.with_file_name(format!("arrgs-naming-{}-2", std::process::id()))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,650 @@
+//! What running child processes depends on the platform for: stopping them,
+//! what their exit statuses mean, reading their output without blocking, and
+//! the limits and names the system has
+//!
+//! On Unix, each child runs in a process group of its own, and is stopped by
+//! signalling the group, so that anything it started stops too. A child that
+//! was killed by a signal has no exit code. Windows has no signals: children
+//! are stopped with `taskkill`, which ends the whole process tree, and every
+//! exit status has a code. Pipes there can't be read without blocking or
+//! waited on, so each is read by a thread of its own.
+
+use std::io::{self, PipeWriter, Read};
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+use crate::signals::ChildExits;
+use crate::Options;
+
+/// A status for a child that exited with `code`, e.g. as recorded for one
+/// that couldn't be started
+#[cfg(unix)]
+pub fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+pub fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+
+    ExitStatus::from_raw(code as u32)
+}
+
+/// A status for a child that was killed by `signal`, e.g. as replayed from a
+/// recording
+#[cfg(unix)]
+pub fn signal_status(signal: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    ExitStatus::from_raw(signal)
+}
+
+/// The signal that killed the child, if it was. Always `None` on Windows.
+#[cfg(unix)]
+pub fn exit_signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+#[cfg(not(unix))]
+pub fn exit_signal(_: ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The name of `signal` without its `SIG` prefix, e.g. `TERM`, for the
+/// signals a child is commonly killed by
+#[cfg(unix)]
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGHUP => "HUP",
+        libc::SIGINT => "INT",
+        libc::SIGQUIT => "QUIT",
+        libc::SIGILL => "ILL",
+        libc::SIGTRAP => "TRAP",
+        libc::SIGABRT => "ABRT",
+        libc::SIGBUS => "BUS",
+        libc::SIGFPE => "FPE",
+        libc::SIGKILL => "KILL",
+        libc::SIGUSR1 => "USR1",
+        libc::SIGSEGV => "SEGV",
+        libc::SIGUSR2 => "USR2",
+        libc::SIGPIPE => "PIPE",
+        libc::SIGALRM => "ALRM",
+        libc::SIGTERM => "TERM",
+        libc::SIGXCPU => "XCPU",
+        libc::SIGXFSZ => "XFSZ",
+        _ => return None,
+    })
+}
+
+/// The CPU time a child used, in user mode and in the kernel on its behalf,
+/// including that of any children it waited for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuTime {
+    pub user: Duration,
+    pub system: Duration,
+}
+
+/// Checks whether the child has exited, without blocking, like
+/// [`Child::try_wait`], also returning the CPU time it used where that's
+/// available. Once this has returned a status, the child mustn't be waited
+/// for again.
+///
+/// # Errors
+/// Will return an error if the child can't be waited for
+#[cfg(unix)]
+pub fn try_wait(child: &mut Child) -> io::Result<Option<(ExitStatus, Option<CpuTime>)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: `rusage` is plain old data, for which all zeroes is valid
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: `status` and `usage` are valid for writes for the duration
+        // of the call
+        let pid = unsafe { libc::wait4(child.id() as _, &mut status, libc::WNOHANG, &mut usage) };
+        match pid {
+            0 => return Ok(None),
+            -1 => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            _ => break,
+        }
+    }
+    let time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    let cpu_time = CpuTime {
+        user: time(usage.ru_utime),
+        system: time(usage.ru_stime),
+    };
+    Ok(Some((ExitStatus::from_raw(status), Some(cpu_time))))
+}
+
+#[cfg(not(unix))]
+pub fn try_wait(child: &mut Child) -> io::Result<Option<(ExitStatus, Option<CpuTime>)>> {
+    Ok(child.try_wait()?.map(|status| (status, None)))
+}
+
+/// Starts the child in a process group of its own, so that it can be stopped
+/// along with anything it starts (e.g. the commands a `sh -c` script runs).
+/// Ctrl-C at the terminal then only interrupts arrgs, which passes it on.
+///
+/// Not for a child that reads from the terminal: only the foreground process
+/// group can, so it has to stay in ours.
+#[cfg(unix)]
+pub fn own_process_group(command: &mut Command) -> &mut Command {
+    use std::os::unix::process::CommandExt;
+
+    command.process_group(0)
+}
+
+#[cfg(windows)]
+pub fn own_process_group(command: &mut Command) -> &mut Command {
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP)
+}
+
+/// Sends `signal` to a child process and everything it started, e.g. to pass
+/// on one we were sent. If the child was started in its own process group
+/// (see [`own_process_group`]), this signals the whole group, otherwise just
+/// the child.
+#[cfg(unix)]
+pub fn send_signal(pid: u32, signal: i32) {
+    let pid = pid as libc::pid_t;
+    // SAFETY: `getpgid` and `kill` have no memory safety requirements. The
+    // child hasn't been reaped, so its pid hasn't been reused. At worst the
+    // process group has already exited and this fails with `ESRCH`.
+    unsafe {
+        let target = if libc::getpgid(pid) == pid { -pid } else { pid };
+        libc::kill(target, signal);
+    }
+}
+
+/// Signals are never caught on Windows, so there's nothing to pass on, but
+/// the child is stopped all the same
+#[cfg(windows)]
+pub fn send_signal(pid: u32, _signal: i32) {
+    kill(pid);
+}
+
+/// The signal that asks a process to exit, `SIGTERM`, for passing to
+/// [`send_signal`]
+#[cfg(unix)]
+pub const TERMINATION_SIGNAL: i32 = libc::SIGTERM;
+
+/// [`send_signal`] ignores the signal on Windows, but this keeps the number
+/// `SIGTERM` has elsewhere
+#[cfg(windows)]
+pub const TERMINATION_SIGNAL: i32 = 15;
+
+/// Asks the child to exit, by sending it `SIGTERM`
+#[cfg(unix)]
+pub fn terminate(pid: u32) {
+    send_signal(pid, libc::SIGTERM);
+}
+
+/// Asks the child to exit, by closing its windows. Console programs don't
+/// have any, so they keep running until [`kill`]ed.
+#[cfg(windows)]
+pub fn terminate(pid: u32) {
+    taskkill(pid, false);
+}
+
+/// Forces the child to exit, by sending it `SIGKILL`
+#[cfg(unix)]
+pub fn kill(pid: u32) {
+    send_signal(pid, libc::SIGKILL);
+}
+
+/// Forces the child to exit
+#[cfg(windows)]
+pub fn kill(pid: u32) {
+    taskkill(pid, true);
+}
+
+/// Ends the child and every process it started. At worst it has already
+/// exited, and `taskkill` fails.
+#[cfg(windows)]
+fn taskkill(pid: u32, force: bool) {
+    let mut command = Command::new("taskkill");
+    command.args(["/T", "/PID", &pid.to_string()]);
+    if force {
+        command.arg("/F");
+    }
+    let _ = command
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+}
+
+/// Each child's niceness and resource limits, from `--nice`,
+/// `--memory-limit` and `--cpu-limit`
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Limits {
+    nice: Option<i32>,
+    memory: Option<u64>,
+    cpu: Option<Duration>,
+}
+
+#[cfg(unix)]
+impl Limits {
+    fn new(options: &Options) -> Self {
+        Self {
+            nice: options.nice,
+            memory: options.memory_limit.map(|bytes| bytes as u64),
+            cpu: options.cpu_limit,
+        }
+    }
+
+    /// Applies the limits to this process, which is about to run the child's
+    /// program. Only calls that are safe between `fork` and `exec`.
+    fn apply(&self) -> io::Result<()> {
+        let check = |result: libc::c_int| match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        };
+        if let Some(nice) = self.nice {
+            // SAFETY: `setpriority` has no memory safety requirements
+            check(unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) })?;
+        }
+        let limit = |resource, soft: u64, hard: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: soft as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            // SAFETY: `limit` is a valid `rlimit` for the duration of the call
+            check(unsafe { libc::setrlimit(resource, &limit) })
+        };
+        if let Some(bytes) = self.memory {
+            limit(libc::RLIMIT_AS, bytes, bytes)?;
+        }
+        if let Some(cpu) = self.cpu {
+            // Whole seconds, rounded up. The hard limit is a second later, so
+            // the child can catch `SIGXCPU` and clean up before it's killed.
+            let seconds = cpu.as_secs() + u64::from(cpu.subsec_nanos() > 0);
+            limit(libc::RLIMIT_CPU, seconds, seconds + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies `--nice`, `--memory-limit` and `--cpu-limit` to the child when
+/// it starts. If they can't be applied (e.g. a negative niceness without
+/// the privilege for it), the child fails to start.
+#[cfg(unix)]
+pub fn limit_resources<'a>(command: &'a mut Command, options: &Options) -> &'a mut Command {
+    use std::os::unix::process::CommandExt;
+
+    let limits = Limits::new(options);
+    if limits == Limits::default() {
+        return command;
+    }
+    // SAFETY: `apply` only makes system calls, without allocating or taking
+    // locks, which is all that's allowed between `fork` and `exec`
+    unsafe { command.pre_exec(move || limits.apply()) }
+}
+
+/// Resource limits aren't supported, so `--nice`, `--memory-limit` and
+/// `--cpu-limit` are ignored
+#[cfg(not(unix))]
+pub fn limit_resources<'a>(command: &'a mut Command, _options: &Options) -> &'a mut Command {
+    command
+}
+
+/// A pipe that's read without blocking, e.g. one of a child's output streams.
+/// Reading it fails with `WouldBlock` when nothing has been written, and
+/// returns 0 once the other end has been closed.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct PipeReader {
+    file: std::fs::File,
+}
+
+#[cfg(unix)]
+impl PipeReader {
+    pub fn new(pipe: impl Into<std::os::fd::OwnedFd>) -> Self {
+        let file = std::fs::File::from(pipe.into());
+        set_nonblocking(&file);
+        Self { file }
+    }
+}
+
+#[cfg(unix)]
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+/// Sets `O_NONBLOCK` on a pipe, so that reading or writing it never blocks
+#[cfg(unix)]
+fn set_nonblocking(fd: &impl std::os::fd::AsRawFd) {
+    let fd = fd.as_raw_fd();
+    // SAFETY: `fd` is open, and this only changes its flags
+    unsafe {
+        libc::fcntl(
+            fd,
+            libc::F_SETFL,
+            libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+        )
+    };
+}
+
+/// A thread reads the pipe as data arrives, and passes it on to be read here
+#[cfg(windows)]
+#[derive(Debug)]
+pub struct PipeReader {
+    /// What the thread has read. It hangs up once the pipe is closed.
+    chunks: std::sync::mpsc::Receiver<Vec<u8>>,
+    /// The rest of a chunk that didn't fit in the last read
+    pending: Vec<u8>,
+}
+
+#[cfg(windows)]
+impl PipeReader {
+    pub fn new(mut pipe: impl Read + Send + 'static) -> Self {
+        let (tx, chunks) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buffer = vec![0; 16 * 1024];
+            loop {
+                match pipe.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if tx.send(buffer[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        Self {
+            chunks,
+            pending: vec![],
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::sync::mpsc::TryRecvError;
+
+        if self.pending.is_empty() {
+            match self.chunks.try_recv() {
+                Ok(chunk) => self.pending = chunk,
+                Err(TryRecvError::Empty) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+/// A pipe for waking a thread that waits with [`wait_readable`]. Writing to
+/// it never blocks: on Unix a full pipe already has a wakeup pending, and
+/// elsewhere the reader's thread keeps it drained.
+///
+/// # Errors
+/// Will return an error if the pipe can't be created
+pub fn wake_pipe() -> io::Result<(PipeReader, PipeWriter)> {
+    let (reader, writer) = io::pipe()?;
+    #[cfg(unix)]
+    set_nonblocking(&writer);
+    Ok((PipeReader::new(reader), writer))
+}
+
+/// Blocks until a child process exits, one of `pipes` can be read from (or
+/// has been closed), or the deadline passes. Returns whether each of `pipes`
+/// is ready.
+#[cfg(unix)]
+pub fn wait_readable(
+    exits: &ChildExits,
+    pipes: &[&PipeReader],
+    deadline: Option<Instant>,
+) -> Vec<bool> {
+    use std::os::fd::AsRawFd;
+
+    let fds: Vec<_> = pipes.iter().map(|pipe| pipe.file.as_raw_fd()).collect();
+    exits.wait_readable(&fds, deadline)
+}
+
+/// Pipes can't be waited on, so this waits as [`ChildExits::wait`] does,
+/// then reports every pipe as ready
+#[cfg(windows)]
+pub fn wait_readable(
+    exits: &ChildExits,
+    pipes: &[&PipeReader],
+    deadline: Option<Instant>,
+) -> Vec<bool> {
+    exits.wait(deadline);
+    vec![true; pipes.len()]
+}
+
+/// The most bytes a command line can take up, if it's known: `ARG_MAX`,
+/// which also has to fit the environment
+#[cfg(unix)]
+pub fn arg_max() -> Option<usize> {
+    // SAFETY: `sysconf` has no memory safety requirements
+    usize::try_from(unsafe { libc::sysconf(libc::_SC_ARG_MAX) }).ok()
+}
+
+/// `CreateProcess` takes command lines of up to 32767 characters
+#[cfg(windows)]
+pub fn arg_max() -> Option<usize> {
+    Some(32767)
+}
+
+/// The name of the user running arrgs, e.g. for the audit log
+#[cfg(unix)]
+pub fn user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| {
+            // SAFETY: `getuid` is always successful and has no side effects
+            format!("uid={}", unsafe { libc::getuid() })
+        })
+}
+
+#[cfg(windows)]
+pub fn user_name() -> String {
+    std::env::var("USERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// The name of the machine arrgs is running on, e.g. for the audit log
+#[cfg(unix)]
+pub fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for `buffer.len()` bytes, and `gethostname`
+    // never writes past that length
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return "unknown".to_string();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+#[cfg(windows)]
+pub fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Whether the terminal is too limited to display the TUI: `TERM` is set to
+/// `dumb`, or to nothing. An unset `TERM` says nothing about the terminal.
+#[cfg(unix)]
+pub fn term_is_limited() -> bool {
+    std::env::var_os("TERM").is_some_and(|term| term.is_empty() || term == "dumb")
+}
+
+/// Windows consoles don't set `TERM`, so there's nothing to go on
+#[cfg(windows)]
+pub fn term_is_limited() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_statuses() {
+        for code in [0, 1, 127, 255] {
+            assert_eq!(exit_status(code).code(), Some(code));
+            assert_eq!(exit_signal(exit_status(code)), None);
+        }
+        assert!(exit_status(0).success());
+        #[cfg(unix)]
+        assert_eq!(
+            exit_signal(signal_status(libc::SIGKILL)),
+            Some(libc::SIGKILL)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signal_names() {
+        assert_eq!(signal_name(libc::SIGTERM), Some("TERM"));
+        assert_eq!(signal_name(libc::SIGKILL), Some("KILL"));
+        assert_eq!(signal_name(0), None);
+    }
+
+    /// Something that runs until it's stopped
+    fn long_running() -> Command {
+        if cfg!(windows) {
+            let mut command = Command::new("ping");
+            command.args(["-n", "30", "127.0.0.1"]);
+            command
+        } else {
+            let mut command = Command::new("sleep");
+            command.arg("30");
+            command
+        }
+    }
+
+    #[test]
+    fn kills_children() {
+        let mut child = own_process_group(&mut long_running())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        kill(child.id());
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        #[cfg(unix)]
+        assert_eq!(exit_signal(status), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn signals_children_in_our_group() {
+        let mut child = long_running().spawn().unwrap();
+        terminate(child.id());
+        assert_eq!(exit_signal(child.wait().unwrap()), Some(libc::SIGTERM));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn signals_reach_grandchildren() {
+        use std::io::{BufRead, BufReader};
+
+        let mut child =
+            own_process_group(Command::new("sh").args(["-c", "sleep 30 & echo $!; wait"]))
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+        let mut grandchild = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut grandchild)
+            .unwrap();
+        let grandchild: libc::pid_t = grandchild.trim().parse().unwrap();
+        terminate(child.id());
+        assert_eq!(exit_signal(child.wait().unwrap()), Some(libc::SIGTERM));
+        // Once it's exited, it's a zombie until init gets round to reaping it
+        let running = || {
+            std::fs::read_to_string(format!("/proc/{grandchild}/stat"))
+                .is_ok_and(|stat| !stat.contains(") Z "))
+        };
+        let start = Instant::now();
+        while running() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "grandchild still running"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn limits_resources() {
+        let options = Options {
+            nice: Some(7),
+            memory_limit: Some(1 << 30),
+            cpu_limit: Some(Duration::from_millis(2500)),
+            ..Default::default()
+        };
+        let output = limit_resources(
+            Command::new("sh").args(["-c", "nice; ulimit -v; ulimit -t"]),
+            &options,
+        )
+        .output()
+        .unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(output, "7\n1048576\n3\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn fails_to_start_without_privilege() {
+        // SAFETY: `geteuid` has no memory safety requirements
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let options = Options {
+            nice: Some(-20),
+            ..Default::default()
+        };
+        assert!(limit_resources(&mut Command::new("true"), &options)
+            .status()
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn measures_cpu_time() {
+        let mut child = Command::new("sh")
+            .args([
+                "-c",
+                "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done; exit 3",
+            ])
+            .spawn()
+            .unwrap();
+        let (status, cpu_time) = loop {
+            if let Some(exited) = try_wait(&mut child).unwrap() {
+                break exited;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(status.code(), Some(3));
+        let cpu_time = cpu_time.unwrap();
+        assert!(
+            cpu_time.user + cpu_time.system > Duration::ZERO,
+            "{cpu_time:?}"
+        );
+    }
+}
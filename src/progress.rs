@@ -0,0 +1,136 @@
+//! The live progress line shown on stderr while jobs run outside the TUI
+
+use std::io::{Stderr, Write};
+use std::time::{Duration, Instant};
+
+use crate::exec::Event;
+
+/// How often the line is redrawn, at most
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts jobs as the executor reports them, redrawing a line like
+/// `12/40 done, 1 failed, 4 running, ETA 0:23`. Until all of the inputs have
+/// been read, the total is only the jobs started so far, shown as `40+`.
+#[derive(Debug)]
+pub struct Progress<W: Write = Stderr> {
+    out: W,
+    started: usize,
+    finished: usize,
+    failed: usize,
+    no_more_jobs: bool,
+    start: Instant,
+    last_drawn: Option<Instant>,
+    done: bool,
+}
+
+impl<W: Write> Progress<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            started: 0,
+            finished: 0,
+            failed: 0,
+            no_more_jobs: false,
+            start: Instant::now(),
+            last_drawn: None,
+            done: false,
+        }
+    }
+
+    pub fn update(&mut self, event: Event) {
+        match event {
+            Event::Started { .. } => self.started += 1,
+            Event::Finished { result, .. } => {
+                self.finished += 1;
+                self.failed += usize::from(!result.success());
+            }
+            Event::NoMoreJobs => self.no_more_jobs = true,
+        }
+        if self.done {
+            return;
+        }
+        let now = Instant::now();
+        if self.no_more_jobs && self.finished >= self.started {
+            // Left on screen, so whatever's printed next goes on a new line
+            self.done = true;
+            self.draw(now, "\n");
+        } else if self
+            .last_drawn
+            .is_none_or(|drawn| now.duration_since(drawn) >= REDRAW_INTERVAL)
+        {
+            self.draw(now, "");
+        }
+    }
+
+    fn draw(&mut self, now: Instant, end: &str) {
+        self.last_drawn = Some(now);
+        let line = self.line(now.duration_since(self.start));
+        let _ = write!(self.out, "\r\x1b[K{line}{end}").and_then(|()| self.out.flush());
+    }
+
+    fn line(&self, elapsed: Duration) -> String {
+        let running = self.started.saturating_sub(self.finished);
+        let more = if self.no_more_jobs { "" } else { "+" };
+        let mut line = format!(
+            "{}/{}{more} done, {} failed, {running} running",
+            self.finished, self.started, self.failed
+        );
+        if self.done {
+            line.push_str(&format!(" in {}", format_duration(elapsed)));
+        } else if self.no_more_jobs && self.finished > 0 {
+            let per_job = elapsed.div_f64(self.finished as f64);
+            let eta = per_job.saturating_mul(u32::try_from(running).unwrap_or(u32::MAX));
+            line.push_str(&format!(", ETA {}", format_duration(eta)));
+        }
+        line
+    }
+}
+
+/// Formats whole seconds as `m:ss`, or `h:mm:ss` from an hour
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, minutes, secs) => format!("{minutes}:{secs:02}"),
+        (hours, minutes, secs) => format!("{hours}:{minutes:02}:{secs:02}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::JobResult;
+    use crate::platform::exit_status;
+
+    #[test]
+    fn progress_line() {
+        let mut progress = Progress::new(vec![]);
+        for seq in 1..=4 {
+            progress.update(Event::Started { seq });
+        }
+        progress.update(Event::Finished {
+            seq: 1,
+            result: JobResult::exited(exit_status(1), false),
+        });
+        let elapsed = Duration::from_secs(10);
+        assert_eq!(progress.line(elapsed), "1/4+ done, 1 failed, 3 running");
+        progress.update(Event::NoMoreJobs);
+        assert_eq!(
+            progress.line(elapsed),
+            "1/4 done, 1 failed, 3 running, ETA 0:30"
+        );
+        for seq in 2..=4 {
+            progress.update(Event::Finished {
+                seq,
+                result: JobResult::exited(exit_status(0), false),
+            });
+        }
+        assert!(progress.done);
+        let drawn = String::from_utf8(progress.out.clone()).unwrap();
+        assert!(drawn.starts_with("\r\x1b[K0/1+ done"), "{drawn:?}");
+        assert!(drawn.ends_with(" running in 0:00\n"), "{drawn:?}");
+        assert_eq!(
+            progress.line(Duration::from_secs(3725)),
+            "4/4 done, 1 failed, 0 running in 1:02:05"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Recordings of interactive sessions, written with `--record` and played
+//! back in the TUI with `--replay`, e.g. for demos or to reproduce a
+//! rendering bug without running anything. A recording has one JSON object
+//! per line: an [`Event`], with the seconds since the session started.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Something that happened in a session. Processes are numbered from 0 in
+/// the order their inputs were read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    /// The inputs for the next process were read. Invalid UTF-8 is replaced.
+    Input {
+        inputs: Vec<String>,
+    },
+    /// All of the inputs have been read
+    InputDone,
+    /// A process started, or was restarted
+    Started {
+        pid: usize,
+    },
+    Output {
+        pid: usize,
+        stream: Stream,
+        lines: Vec<String>,
+    },
+    /// No more of a process's output was kept in memory
+    Truncated {
+        pid: usize,
+    },
+    Retry {
+        pid: usize,
+        attempt: usize,
+    },
+    Exit {
+        pid: usize,
+        status: Status,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// How a process finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Success,
+    Failure(i32),
+    Signal(i32),
+    TimedOut,
+    Killed,
+    /// The process couldn't be started; with the exit code that counts for
+    NotStarted(i32),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Seconds since the session started
+    at: f64,
+    #[serde(flatten)]
+    event: Event,
+}
+
+/// Writes the events of a session to a `--record` file as they happen
+#[derive(Debug)]
+pub struct Recorder {
+    file: LineWriter<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// # Errors
+    /// Will return an error if the file cannot be created
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("creating recording {}", path.display()))?;
+        Ok(Self {
+            file: LineWriter::new(file),
+            started: Instant::now(),
+        })
+    }
+
+    /// # Errors
+    /// Will return an error if the event cannot be written
+    pub fn record(&mut self, event: &Event) -> anyhow::Result<()> {
+        let entry = Entry {
+            at: self.started.elapsed().as_secs_f64(),
+            event: event.clone(),
+        };
+        let line = serde_json::to_string(&entry)?;
+        writeln!(self.file, "{line}").context("writing recording")?;
+        Ok(())
+    }
+}
+
+/// Reads every event from a recording, with how long after the start of the
+/// session it happened
+///
+/// # Errors
+/// Will return an error if the recording cannot be read, or contains
+/// malformed events
+pub fn read_events(path: &Path) -> anyhow::Result<Vec<(Duration, Event)>> {
+    let file = File::open(path).with_context(|| format!("opening recording {}", path.display()))?;
+    let mut events = vec![];
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line)
+            .with_context(|| format!("malformed event on line {} of the recording", number + 1))?;
+        let at = Duration::try_from_secs_f64(entry.at)
+            .with_context(|| format!("malformed time on line {} of the recording", number + 1))?;
+        events.push((at, entry.event));
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip() {
+        let path = std::env::temp_dir().join(format!("arrgs-recording-{}", std::process::id()));
+        let events = [
+            Event::Input {
+                inputs: vec!["a b".to_string()],
+            },
+            Event::Started { pid: 0 },
+            Event::Output {
+                pid: 0,
+                stream: Stream::Stderr,
+                lines: vec!["oops\n".to_string()],
+            },
+            Event::Retry { pid: 0, attempt: 2 },
+            Event::Exit {
+                pid: 0,
+                status: Status::Failure(3),
+            },
+            Event::InputDone,
+        ];
+        let mut recorder = Recorder::create(&path).unwrap();
+        for event in &events {
+            recorder.record(event).unwrap();
+        }
+        drop(recorder);
+        let recorded = read_events(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            recorded.iter().map(|(_, event)| event).collect::<Vec<_>>(),
+            events.iter().collect::<Vec<_>>()
+        );
+        assert!(recorded.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    }
+
+    #[test]
+    fn event_format() {
+        let entry: Entry =
+            serde_json::from_str(r#"{"at":1.5,"event":"exit","pid":2,"status":{"signal":9}}"#)
+                .unwrap();
+        assert_eq!(entry.at, 1.5);
+        assert_eq!(
+            entry.event,
+            Event::Exit {
+                pid: 2,
+                status: Status::Signal(9)
+            }
+        );
+        let entry = Entry {
+            at: 0.25,
+            event: Event::Exit {
+                pid: 0,
+                status: Status::TimedOut,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"at":0.25,"event":"exit","pid":0,"status":"timed-out"}"#
+        );
+    }
+
+    #[test]
+    fn malformed_events() {
+        let path =
+            std::env::temp_dir().join(format!("arrgs-recording-malformed-{}", std::process::id()));
+        std::fs::write(&path, "{\"at\":0,\"event\":\"input-done\"}\n\n{\"at\":1}\n").unwrap();
+        let error = read_events(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            error.to_string(),
+            "malformed event on line 3 of the recording"
+        );
+    }
+}
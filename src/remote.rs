@@ -0,0 +1,317 @@
+//! Running jobs on other machines over SSH, with `--sshlogin`
+//!
+//! Each login has a number of slots, numbered on from the previous login's,
+//! so scheduling a job on a free slot (as the parallel executor does for
+//! `ARRGS_SLOT`) also picks the machine it runs on.
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::exec::job_vars;
+use crate::template::Values;
+use crate::{shell, Options};
+
+/// A machine to run jobs on, parsed from `--sshlogin` as `[N/]LOGIN`: `LOGIN`
+/// is anything `ssh` accepts as a destination (e.g. `user@host`), or `:` for
+/// this machine, and `N` is how many jobs to run on it at once
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SshLogin {
+    pub slots: Option<usize>,
+    /// `None` for this machine
+    pub login: Option<String>,
+}
+
+impl FromStr for SshLogin {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (slots, login) = match value.split_once('/') {
+            Some((slots, login)) if slots.bytes().all(|b| b.is_ascii_digit()) => {
+                let slots = slots
+                    .parse()
+                    .ok()
+                    .filter(|&slots| slots > 0)
+                    .ok_or_else(|| format!("`{slots}` is not a valid number of jobs"))?;
+                (Some(slots), login)
+            }
+            _ => (None, value),
+        };
+        match login {
+            "" => Err("missing login".to_string()),
+            ":" => Ok(Self { slots, login: None }),
+            login => Ok(Self {
+                slots,
+                login: Some(login.to_string()),
+            }),
+        }
+    }
+}
+
+/// How many jobs to run at once on `login`: as given, or else `--jobs`, or 1
+fn slots(options: &Options, login: &SshLogin) -> usize {
+    login
+        .slots
+        .unwrap_or(if options.jobs == 0 { 1 } else { options.jobs })
+}
+
+/// The number of jobs to run at once across every `--sshlogin`
+pub fn total_slots(options: &Options) -> usize {
+    options
+        .sshlogin
+        .iter()
+        .map(|login| slots(options, login))
+        .sum()
+}
+
+/// The remote machine that runs jobs in `slot` (counting from 1), or `None`
+/// for this machine
+pub fn login_for_slot(options: &Options, slot: usize) -> Option<&str> {
+    let mut first = 1;
+    for login in &options.sshlogin {
+        let next = first + slots(options, login);
+        if (first..next).contains(&slot) {
+            return login.login.as_deref();
+        }
+        first = next;
+    }
+    None
+}
+
+/// The command that runs `command` for a job in `slot`: unchanged on this
+/// machine, or else run with `ssh` in the login's home directory. The job's
+/// environment variables are set for the remote command, since `ssh` doesn't
+/// pass them on.
+///
+/// With `--transfer`, `--return` or `--cleanup`, this is a script for `sh`
+/// that copies the inputs that are files to the same (relative) paths on the
+/// remote machine first, copies the `--return` files back afterwards, then
+/// removes them all remotely. Its exit status is the command's, unless a
+/// transfer fails first.
+pub fn remote_command(
+    options: &Options,
+    seq: usize,
+    slot: usize,
+    inputs: &[OsString],
+    command: Vec<OsString>,
+) -> Vec<OsString> {
+    let Some(login) = login_for_slot(options, slot) else {
+        return command;
+    };
+    let mut script = OsString::new();
+    for (key, value) in job_vars(options, seq, slot, inputs) {
+        script.push(key);
+        script.push("=");
+        script.push(shell::quote_os(&value));
+        script.push(" ");
+    }
+    script.push(shell::join_os(&command));
+    let ssh = |script: &OsStr| -> Vec<OsString> {
+        ssh_command(options)
+            .into_iter()
+            .chain([login.into(), script.to_owned()])
+            .collect()
+    };
+    if !options.transfer && options.return_files.is_empty() && !options.cleanup {
+        return ssh(&script);
+    }
+
+    let transfers: Vec<&Path> = if options.transfer {
+        inputs
+            .iter()
+            .map(Path::new)
+            .filter(|path| path.is_file())
+            .collect()
+    } else {
+        vec![]
+    };
+    let values = Values {
+        token: options.replace.as_deref().unwrap_or("{}"),
+        inputs,
+        fields: &options.fields,
+        seq,
+        quote: false,
+    };
+    let returns: Vec<OsString> = options
+        .return_files
+        .iter()
+        .map(|path| values.expand(path).unwrap_or_else(|| path.into()))
+        .collect();
+    let mut local = OsString::new();
+    for path in &transfers {
+        // `mkdir -p` first, so that inputs in directories land in the same
+        // place relative to the remote home directory
+        let mut copy = OsString::from("mkdir -p -- ");
+        copy.push(shell::quote_os(parent(path)));
+        copy.push(" && cat > ");
+        copy.push(shell::quote_os(path.as_os_str()));
+        local.push(script_line(&ssh(&copy), Some(("<", path.as_os_str()))));
+        local.push("status=$?; [ $status -eq 0 ] || exit $status\n");
+    }
+    local.push(script_line(&ssh(&script), None));
+    local.push("status=$?\n");
+    for path in &returns {
+        let mut fetch = OsString::from("cat -- ");
+        fetch.push(shell::quote_os(path));
+        let mkdir = [
+            "mkdir".into(),
+            "-p".into(),
+            "--".into(),
+            parent(Path::new(path)).into(),
+        ];
+        local.push(script_line(&mkdir, None));
+        local.push(script_line(&ssh(&fetch), Some((">", path))));
+    }
+    if options.cleanup {
+        let mut remove = OsString::from("rm -f --");
+        let transferred = transfers.iter().map(|path| path.as_os_str());
+        for path in transferred.chain(returns.iter().map(OsString::as_os_str)) {
+            remove.push(" ");
+            remove.push(shell::quote_os(path));
+        }
+        local.push(script_line(&ssh(&remove), None));
+    }
+    local.push("exit $status");
+    vec!["sh".into(), "-c".into(), local]
+}
+
+/// One line of a script: the quoted command, with its input or output
+/// redirected to `path` by `redirect`
+fn script_line(command: &[OsString], redirect: Option<(&str, &OsStr)>) -> OsString {
+    let mut line = shell::join_os(command);
+    if let Some((redirect, path)) = redirect {
+        line.push(format!(" {redirect} "));
+        line.push(shell::quote_os(path));
+    }
+    line.push("\n");
+    line
+}
+
+/// `--ssh`, split into the program and its arguments
+fn ssh_command(options: &Options) -> Vec<OsString> {
+    let ssh = Some(options.ssh.as_str()).filter(|ssh| !ssh.trim().is_empty());
+    ssh.unwrap_or("ssh")
+        .split_whitespace()
+        .map(OsString::from)
+        .collect()
+}
+
+/// The directory containing `path`, or `.`
+fn parent(path: &Path) -> &OsStr {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.as_os_str(),
+        _ => OsStr::new("."),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::exec::{Executor, Remote};
+
+    fn options(logins: &[&str]) -> Options {
+        Options {
+            program: "echo".to_string(),
+            sshlogin: logins.iter().map(|login| login.parse().unwrap()).collect(),
+            ssh: "ssh".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_login() {
+        let login = |value: &str| value.parse::<SshLogin>();
+        assert_eq!(
+            login("4/user@host"),
+            Ok(SshLogin {
+                slots: Some(4),
+                login: Some("user@host".to_string())
+            })
+        );
+        assert_eq!(
+            login(":"),
+            Ok(SshLogin {
+                slots: None,
+                login: None
+            })
+        );
+        assert!(login("0/host").is_err());
+        assert!(login("2/").is_err());
+    }
+
+    #[test]
+    fn slots_across_logins() {
+        let options = options(&["2/a", ":", "b"]);
+        assert_eq!(total_slots(&options), 4);
+        let logins: Vec<_> = (1..=5).map(|slot| login_for_slot(&options, slot)).collect();
+        assert_eq!(logins, [Some("a"), Some("a"), None, Some("b"), None]);
+    }
+
+    #[test]
+    fn command_over_ssh() {
+        let options = options(&["host"]);
+        let command = vec!["echo".into(), "a b".into()];
+        let remote = remote_command(&options, 3, 1, &["a b".into()], command.clone());
+        assert_eq!(
+            remote,
+            [
+                "ssh",
+                "host",
+                "ARRGS_JOB=3 ARRGS_SLOT=1 ARRGS_INPUT='a b' ARRGS_INPUT_1='a b' echo 'a b'"
+            ]
+        );
+        let local = Options {
+            sshlogin: vec![":".parse().unwrap()],
+            ..options
+        };
+        assert_eq!(remote_command(&local, 3, 1, &[], command.clone()), command);
+    }
+
+    #[test]
+    fn returns_and_cleans_up() {
+        let options = Options {
+            return_files: vec!["out/{/}.txt".to_string()],
+            cleanup: true,
+            ..options(&["host"])
+        };
+        let remote = remote_command(&options, 1, 1, &["in/x.c".into()], vec!["true".into()]);
+        assert_eq!(&remote[..2], ["sh", "-c"]);
+        let script = remote[2].to_str().unwrap();
+        assert!(
+            script.contains("mkdir -p -- out\nssh host 'cat -- out/x.c.txt' > out/x.c.txt\n"),
+            "{script}"
+        );
+        assert!(
+            script.ends_with("ssh host 'rm -f -- out/x.c.txt'\nexit $status"),
+            "{script}"
+        );
+    }
+
+    #[test]
+    fn runs_on_every_login() {
+        // Stands in for `ssh`, running the command here and failing some jobs
+        let ssh = std::env::temp_dir().join(format!("arrgs-ssh-{}", std::process::id()));
+        std::fs::write(
+            &ssh,
+            "#!/bin/sh\nexec sh -c \"$2; exit \\$(( \\$ARRGS_JOB % 3 ))\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let options = Options {
+            program: "true".to_string(),
+            ssh: ssh.display().to_string(),
+            ..options(&["2/a", "b"])
+        };
+        let inputs = (1..=6).map(|n| OsString::from(n.to_string()));
+        let results = Remote.execute(&options, inputs).unwrap();
+        std::fs::remove_file(&ssh).unwrap();
+        let mut codes: Vec<_> = results
+            .iter()
+            .map(|result| result.status().code())
+            .collect();
+        codes.sort();
+        assert_eq!(codes, [0, 0, 1, 1, 2, 2].map(Some));
+    }
+}
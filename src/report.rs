@@ -0,0 +1,289 @@
+//! `arrgs report`: summarizes the jobs recorded by previous runs, in job logs
+//! (`--joblog`), results files (`--output json`) or audit logs
+//! (`--audit-log`), and suggests how to re-run the failures
+//!
+//! The retry suggestion is a command line rather than an `arrgs retry`
+//! subcommand: none of these files record the options the run used (e.g.
+//! `--jobs` or `--timeout`), so re-running the failures well needs them added
+//! by hand anyway.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+
+use crate::audit::{self, AuditRecord};
+use crate::joblog::{self, JobLogFormat, JobRecord};
+use crate::timing::percentile;
+use crate::{results, shell};
+
+/// Number of jobs listed under "Slowest jobs"
+const SLOWEST_JOBS: usize = 5;
+
+/// Summarize the jobs recorded by previous runs
+#[derive(Parser, Debug)]
+#[command(name = "arrgs report", bin_name = "arrgs report")]
+pub struct ReportOptions {
+    /// Job logs (`--joblog`), results files (`--output json`) or audit logs
+    /// (`--audit-log`), in any mix. The format of each is detected.
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+}
+
+/// # Errors
+/// Will return an error if any of the files cannot be read or parsed
+pub fn run(options: ReportOptions) -> anyhow::Result<()> {
+    let mut jobs = vec![];
+    for file in &options.files {
+        jobs.extend(read_jobs(file).with_context(|| format!("reading {}", file.display()))?);
+    }
+    print!("{}", render(&jobs));
+    Ok(())
+}
+
+/// One run of a job, from whichever kind of file recorded it
+#[derive(Debug, Clone, PartialEq)]
+struct Job {
+    command: Vec<String>,
+    /// Seconds the job took
+    duration: f64,
+    /// How the job failed, e.g. `exit status: 1`, or `None` if it succeeded
+    failure: Option<String>,
+}
+
+impl From<AuditRecord> for Job {
+    fn from(record: AuditRecord) -> Self {
+        Self {
+            duration: record.duration(),
+            failure: (!record.succeeded()).then_some(record.status),
+            command: record.command,
+        }
+    }
+}
+
+impl From<JobRecord> for Job {
+    fn from(record: JobRecord) -> Self {
+        let failure = match (record.error, record.signal) {
+            (Some(error), _) => Some(error),
+            (None, 0) if record.exit_code == 0 => None,
+            (None, 0) => Some(format!("exit status: {}", record.exit_code)),
+            (None, signal) => Some(format!("signal: {signal}")),
+        };
+        Self {
+            command: record.command,
+            duration: record.runtime,
+            failure,
+        }
+    }
+}
+
+/// Reads the jobs from a job log, results file or audit log, telling which it
+/// is from the first line. Only audit logs have their hash chain checked.
+fn read_jobs(path: &Path) -> anyhow::Result<Vec<Job>> {
+    let mut first = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut first)?;
+    let first = first.trim_end_matches('\n');
+    let jobs: Vec<Job> = if first.is_empty() {
+        vec![]
+    } else if results::is_results_line(first) {
+        results::read_records(path)?
+            .into_iter()
+            .map(Job::from)
+            .collect()
+    } else if let Some(format) = JobLogFormat::detect(first) {
+        joblog::read_records(path, format)?
+            .into_iter()
+            .map(Job::from)
+            .collect()
+    } else {
+        audit::read_records(path)?
+            .into_iter()
+            .map(Job::from)
+            .collect()
+    };
+    Ok(jobs)
+}
+
+fn render(jobs: &[Job]) -> String {
+    let failed: Vec<&Job> = jobs.iter().filter(|job| job.failure.is_some()).collect();
+    let mut output = format!(
+        "Jobs: {} ({} succeeded, {} failed)\n",
+        jobs.len(),
+        jobs.len() - failed.len(),
+        failed.len()
+    );
+    if jobs.is_empty() {
+        return output;
+    }
+
+    let mut by_duration: Vec<&Job> = jobs.iter().collect();
+    by_duration.sort_by(|a, b| b.duration.total_cmp(&a.duration));
+    output.push_str("\nSlowest jobs:\n");
+    for job in by_duration.iter().take(SLOWEST_JOBS) {
+        output.push_str(&format!(
+            "    {:>9.3}s  {}\n",
+            job.duration,
+            shell::join(job.command.iter().map(String::as_str))
+        ));
+    }
+
+    let durations: Vec<f64> = by_duration.iter().rev().map(|job| job.duration).collect();
+    output.push_str("\nDurations:\n   ");
+    for (label, p) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("max", 1.0)] {
+        output.push_str(&format!(" {label} {:.3}s", percentile(&durations, p)));
+    }
+    output.push('\n');
+
+    if failed.is_empty() {
+        return output;
+    }
+    let mut by_status: BTreeMap<&str, usize> = BTreeMap::new();
+    for job in &failed {
+        *by_status
+            .entry(job.failure.as_deref().unwrap_or_default())
+            .or_default() += 1;
+    }
+    output.push_str("\nFailures:\n");
+    for (status, count) in by_status {
+        output.push_str(&format!("    {count:>5}  {status}\n"));
+    }
+    output.push_str("\nRetry failed jobs with:\n");
+    for line in retry_commands(&failed) {
+        output.push_str(&format!("    {line}\n"));
+    }
+    output
+}
+
+/// Builds command lines that re-run the failed jobs. When the failures share
+/// the same program and fixed arguments, and each had the same number of
+/// inputs, that's a single `arrgs` invocation fed by `printf`; otherwise it's
+/// each failed command on its own.
+fn retry_commands(failed: &[&Job]) -> Vec<String> {
+    let shortest = failed.iter().map(|r| r.command.len()).min().unwrap_or(0);
+    let prefix_len = (0..shortest.saturating_sub(1))
+        .take_while(|&i| failed.iter().all(|r| r.command[i] == failed[0].command[i]))
+        .count();
+    let nargs = failed[0].command.len() - prefix_len;
+    let same_nargs = failed.iter().all(|r| r.command.len() - prefix_len == nargs);
+    if prefix_len == 0 || !same_nargs {
+        return failed
+            .iter()
+            .map(|r| shell::join(r.command.iter().map(String::as_str)))
+            .collect();
+    }
+    let inputs = shell::join(
+        failed
+            .iter()
+            .flat_map(|r| r.command[prefix_len..].iter().map(String::as_str)),
+    );
+    let command = shell::join(failed[0].command[..prefix_len].iter().map(String::as_str));
+    vec![format!(
+        "printf '%s\\0' {inputs} | arrgs -0 -n {nargs} -- {command}"
+    )]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(command: &[&str], duration: f64, status: &str) -> Job {
+        Job::from(AuditRecord {
+            start: 100.0,
+            end: 100.0 + duration,
+            user: "user".to_string(),
+            host: "host".to_string(),
+            status: status.to_string(),
+            command: command.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn retry_shared_prefix() {
+        let a = record(&["cp", "-v", "a b", "/backup"], 1.0, "exit status: 1");
+        let b = record(&["cp", "-v", "c", "/backup"], 1.0, "exit status: 1");
+        assert_eq!(
+            retry_commands(&[&a, &b]),
+            vec![r"printf '%s\0' 'a b' /backup c /backup | arrgs -0 -n 2 -- cp -v"]
+        );
+        let a = record(&["sh", "-c", "exit $0", "1"], 1.0, "exit status: 1");
+        assert_eq!(
+            retry_commands(&[&a]),
+            vec![r"printf '%s\0' 1 | arrgs -0 -n 1 -- sh -c 'exit $0'"]
+        );
+    }
+
+    #[test]
+    fn retry_single_failure() {
+        let a = record(&["rm", "foo"], 1.0, "exit status: 1");
+        assert_eq!(
+            retry_commands(&[&a]),
+            vec![r"printf '%s\0' foo | arrgs -0 -n 1 -- rm"]
+        );
+    }
+
+    #[test]
+    fn retry_different_programs() {
+        let a = record(&["rm", "foo"], 1.0, "exit status: 1");
+        let b = record(&["ls", "bar"], 1.0, "exit status: 2");
+        assert_eq!(retry_commands(&[&a, &b]), vec!["rm foo", "ls bar"]);
+    }
+
+    #[test]
+    fn render_summary() {
+        let records = [
+            record(&["sleep", "1"], 1.0, "exit status: 0"),
+            record(&["sleep", "2"], 2.0, "signal: 9 (SIGKILL)"),
+        ];
+        let output = render(&records);
+        assert!(output.starts_with("Jobs: 2 (1 succeeded, 1 failed)\n"));
+        assert!(output.contains("1  signal: 9 (SIGKILL)"), "{output}");
+        assert!(output.contains("p50 1.000s"), "{output}");
+    }
+
+    #[test]
+    fn reads_every_format() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        use crate::exec::JobResult;
+        use crate::joblog::JobLog;
+        use crate::platform::exit_status;
+
+        let start = UNIX_EPOCH + Duration::from_secs(100);
+        let record = |code| {
+            JobRecord::new(
+                1,
+                &["a b".into()],
+                &["sh".into(), "-c".into(), "a b".into()],
+                start,
+                start + Duration::from_secs(2),
+                JobResult::exited(exit_status(code), false),
+            )
+        };
+        let expected = [
+            Job {
+                command: vec!["sh".into(), "-c".into(), "a b".into()],
+                duration: 2.0,
+                failure: None,
+            },
+            Job {
+                command: vec!["sh".into(), "-c".into(), "a b".into()],
+                duration: 2.0,
+                failure: Some("exit status: 3".into()),
+            },
+        ];
+        for format in [JobLogFormat::Tsv, JobLogFormat::Json] {
+            let path = std::env::temp_dir()
+                .join(format!("arrgs-report-{}-{format:?}", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let mut log = JobLog::open(&path, format).unwrap();
+            log.record(&record(0)).unwrap();
+            log.record(&record(3)).unwrap();
+            let jobs = read_jobs(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(jobs, expected, "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! The machine-readable results of a run, written at the end with `--output`
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::exec::{exit_code, JobResult};
+use crate::job::Output;
+use crate::joblog::{input_hash, JobRecord};
+use crate::platform::exit_signal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// A JSON object per line: one for each job, in job order, then one with
+    /// the totals
+    Json,
+}
+
+/// The result of a job, after any retries
+#[derive(Debug, Serialize)]
+struct JobEntry {
+    /// Which job this was, counting from 1 in input order
+    seq: usize,
+    /// The command line of the last attempt
+    command: Vec<String>,
+    inputs: Vec<String>,
+    /// The exit code, or `null` if the process was killed by a signal
+    exit_code: Option<i32>,
+    /// The signal that killed the process, if it was
+    signal: Option<i32>,
+    /// That the job timed out, or why it couldn't be started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Seconds since the Unix epoch that the first attempt started
+    start: f64,
+    /// Seconds from the start of the first attempt to the end of the last
+    duration: f64,
+    attempts: usize,
+    /// What the job wrote, with `--output-capture`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<String>,
+    #[serde(skip)]
+    started: SystemTime,
+    #[serde(skip)]
+    result: JobResult,
+}
+
+/// The totals, written after the jobs
+#[derive(Debug, Serialize)]
+struct Totals {
+    jobs: usize,
+    succeeded: usize,
+    failed: usize,
+    /// Seconds the whole run took
+    duration: f64,
+    /// The exit code for the jobs' statuses, see [`exit_code`]
+    exit_code: u8,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line<'a> {
+    Job(&'a JobEntry),
+    Summary(Totals),
+}
+
+/// Collects the result of each job as it finishes, to write them all at the
+/// end, to stdout or `--output-file`
+#[derive(Debug)]
+pub struct Results {
+    /// `None` for stdout
+    file: Option<File>,
+    start: Instant,
+    jobs: BTreeMap<usize, JobEntry>,
+}
+
+impl Results {
+    /// # Errors
+    /// Will return an error if the output file cannot be created
+    pub fn open(path: Option<&Path>) -> anyhow::Result<Self> {
+        let file = path
+            .map(|path| File::create(path).with_context(|| format!("creating {}", path.display())))
+            .transpose()?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+            jobs: BTreeMap::new(),
+        })
+    }
+
+    /// Records one run of job number `seq`, which takes the place of any
+    /// earlier attempt's result
+    pub fn record(
+        &mut self,
+        seq: usize,
+        inputs: &[OsString],
+        command: &[OsString],
+        start: SystemTime,
+        end: SystemTime,
+        result: JobResult,
+    ) {
+        let job = self.jobs.entry(seq).or_insert_with(|| JobEntry {
+            seq,
+            command: vec![],
+            inputs: lossy(inputs),
+            exit_code: None,
+            signal: None,
+            error: None,
+            start: start
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            duration: 0.0,
+            attempts: 0,
+            stdout: None,
+            stderr: None,
+            started: start,
+            result,
+        });
+        let status = result.status();
+        job.command = lossy(command);
+        job.exit_code = status.code();
+        job.signal = exit_signal(status);
+        job.error = result.error();
+        job.duration = end
+            .duration_since(job.started)
+            .unwrap_or_default()
+            .as_secs_f64();
+        job.attempts += 1;
+        job.result = result;
+    }
+
+    /// Adds output captured from job number `seq`
+    pub fn capture(&mut self, seq: usize, output: &Output) {
+        if let Some(job) = self.jobs.get_mut(&seq) {
+            let append = |captured: &mut Option<String>, output: &[u8]| {
+                captured
+                    .get_or_insert_default()
+                    .push_str(&String::from_utf8_lossy(output));
+            };
+            append(&mut job.stdout, &output.stdout);
+            append(&mut job.stderr, &output.stderr);
+        }
+    }
+
+    /// Writes every job's result, then the totals
+    ///
+    /// # Errors
+    /// Will return an error if the results cannot be written
+    pub fn write(&mut self) -> io::Result<()> {
+        let lines = self.lines();
+        match &mut self.file {
+            Some(file) => file.write_all(lines.as_bytes()),
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(lines.as_bytes())?;
+                stdout.flush()
+            }
+        }
+    }
+
+    fn lines(&self) -> String {
+        let results: Vec<JobResult> = self.jobs.values().map(|job| job.result).collect();
+        let succeeded = results.iter().filter(|result| result.success()).count();
+        let totals = Totals {
+            jobs: results.len(),
+            succeeded,
+            failed: results.len() - succeeded,
+            duration: self.start.elapsed().as_secs_f64(),
+            exit_code: exit_code(&results),
+        };
+        self.jobs
+            .values()
+            .map(Line::Job)
+            .chain([Line::Summary(totals)])
+            .map(|line| serde_json::to_string(&line).expect("results serialize") + "\n")
+            .collect()
+    }
+}
+
+/// A line of a results file, as read back: only what a [`JobRecord`] needs
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ReadLine {
+    Job {
+        seq: usize,
+        command: Vec<String>,
+        inputs: Vec<String>,
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+        #[serde(default)]
+        error: Option<String>,
+        start: f64,
+        duration: f64,
+    },
+    Summary,
+}
+
+/// Whether `line` is from a results file, e.g. the first line of a file
+/// that might be one
+pub fn is_results_line(line: &str) -> bool {
+    serde_json::from_str::<ReadLine>(line).is_ok()
+}
+
+/// Reads the jobs from a results file written with `--output json`, as job
+/// log records spanning all of each job's attempts
+///
+/// # Errors
+/// Will return an error if the file cannot be read, or contains malformed
+/// lines
+pub fn read_records(path: &Path) -> anyhow::Result<Vec<JobRecord>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut records = vec![];
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let read = serde_json::from_str(&line)
+            .with_context(|| format!("malformed results on line {}", number + 1))?;
+        if let ReadLine::Job {
+            seq,
+            command,
+            inputs,
+            exit_code,
+            signal,
+            error,
+            start,
+            duration,
+        } = read
+        {
+            let inputs: Vec<OsString> = inputs.into_iter().map(OsString::from).collect();
+            records.push(JobRecord {
+                seq,
+                start,
+                runtime: duration,
+                exit_code: exit_code.unwrap_or(-1),
+                signal: signal.unwrap_or(0),
+                input_hash: input_hash(&inputs),
+                command,
+                error,
+            });
+        }
+    }
+    Ok(records)
+}
+
+fn lossy(args: &[OsString]) -> Vec<String> {
+    args.iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::ErrorKind;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn results_lines() {
+        let mut results = Results::open(None).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(100);
+        let inputs = ["a b".into()];
+        let command = ["false".into(), "a b".into()];
+        for (attempt, status) in [(0, 1 << 8), (2, 0)] {
+            let start = start + Duration::from_secs(attempt);
+            let end = start + Duration::from_millis(500);
+            results.record(
+                2,
+                &inputs,
+                &command,
+                start,
+                end,
+                JobResult::exited(ExitStatus::from_raw(status), false),
+            );
+        }
+        results.capture(
+            2,
+            &Output {
+                stdout: b"out\n".to_vec(),
+                stderr: vec![],
+            },
+        );
+        let killed = JobResult::exited(ExitStatus::from_raw(libc::SIGKILL), false);
+        results.record(1, &[], &["true".into()], start, start, killed);
+        let not_found = JobResult::SpawnError(ErrorKind::NotFound);
+        results.record(3, &[], &["nope".into()], start, start, not_found);
+
+        let lines: Vec<Value> = results
+            .lines()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["seq"], 1);
+        assert_eq!(lines[0]["exit_code"], Value::Null);
+        assert_eq!(lines[0]["signal"], libc::SIGKILL);
+        assert!(lines[0].get("stdout").is_none());
+        assert!(lines[0].get("error").is_none());
+        assert_eq!(lines[1]["type"], "job");
+        assert_eq!(lines[1]["command"], serde_json::json!(["false", "a b"]));
+        assert_eq!(lines[1]["inputs"], serde_json::json!(["a b"]));
+        assert_eq!(lines[1]["exit_code"], 0);
+        assert_eq!(lines[1]["start"], 100.0);
+        assert_eq!(lines[1]["duration"], 2.5);
+        assert_eq!(lines[1]["attempts"], 2);
+        assert_eq!(lines[1]["stdout"], "out\n");
+        assert_eq!(lines[1]["stderr"], "");
+        assert_eq!(lines[2]["exit_code"], 127);
+        assert_eq!(lines[2]["error"], "could not start: entity not found");
+        assert_eq!(lines[3]["type"], "summary");
+        assert_eq!(lines[3]["jobs"], 3);
+        assert_eq!(lines[3]["succeeded"], 1);
+        assert_eq!(lines[3]["failed"], 2);
+        assert_eq!(lines[3]["exit_code"], 127);
+    }
+
+    #[test]
+    fn results_read_back() {
+        let mut results = Results::open(None).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(100);
+        let not_found = JobResult::SpawnError(ErrorKind::NotFound);
+        results.record(
+            1,
+            &["a".into()],
+            &["nope".into(), "a".into()],
+            start,
+            start,
+            not_found,
+        );
+        let path = std::env::temp_dir().join(format!("arrgs-results-{}", std::process::id()));
+        std::fs::write(&path, results.lines()).unwrap();
+        assert!(is_results_line(results.lines().lines().last().unwrap()));
+        let records = read_records(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].command, ["nope", "a"]);
+        assert_eq!(records[0].exit_code, 127);
+        assert_eq!(records[0].input_hash, input_hash(&["a".into()]));
+        assert_eq!(
+            records[0].error.as_deref(),
+            Some("could not start: entity not found")
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::exec::{chunk_inputs, command_line};
+use crate::{shell, Options};
+
+/// Number of expanded commands to show when asking for confirmation
+pub const SAMPLE_COMMANDS: usize = 3;
+
+/// Shells whose `-c` scripts are checked for output redirection
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish"];
+
+/// Why a command denied by the `[safety]` config needs confirmation
+const DENIED: &str = "denied by the [safety] config";
+
+/// Commands from the `[safety]` section of the config file that always, or
+/// never, need confirmation, whatever the built-in patterns say. Each is the
+/// words a command starts with. Programs are compared by file name, so
+/// `rm -rf build` also covers `/bin/rm -rf build {}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    pub allow: Vec<Vec<String>>,
+    /// Takes precedence over `allow`
+    pub deny: Vec<Vec<String>>,
+}
+
+impl Rules {
+    /// Whether the command is denied (`Some(true)`) or allowed
+    /// (`Some(false)`) by a rule, or `None` if no rule covers it
+    fn denies(&self, program: &str, args: &[String]) -> Option<bool> {
+        let covers = |rule: &Vec<String>| {
+            rule.split_first().is_some_and(|(rule_program, rule_args)| {
+                file_name(rule_program) == file_name(program) && args.starts_with(rule_args)
+            })
+        };
+        if self.deny.iter().any(covers) {
+            Some(true)
+        } else if self.allow.iter().any(covers) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+fn file_name(program: &str) -> &str {
+    Path::new(program)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(program)
+}
+
+/// Returns a short description of why the given command looks destructive,
+/// or `None` if nothing obviously dangerous was found. The `[safety]` rules
+/// are checked first. A shell's `-c` script is checked like a `--shell`
+/// script.
+pub fn destructive_reason(
+    program: &str,
+    program_args: &[String],
+    rules: &Rules,
+) -> Option<&'static str> {
+    match rules.denies(program, program_args) {
+        Some(true) => Some(DENIED),
+        Some(false) => None,
+        None if SHELLS.contains(&file_name(program)) => {
+            let script = program_args.iter().skip_while(|arg| *arg != "-c").nth(1)?;
+            destructive_script_reason(script, rules)
+        }
+        None => builtin_reason(program, program_args),
+    }
+}
+
+/// Checks the command against the built-in patterns only
+fn builtin_reason(program: &str, program_args: &[String]) -> Option<&'static str> {
+    match file_name(program) {
+        "rm" if program_args.iter().any(|arg| is_recursive_or_forced(arg)) => {
+            Some("recursive or forced removal")
+        }
+        "dd" if program_args.iter().any(|arg| arg.starts_with("of=")) => {
+            Some("dd writing to an output file")
+        }
+        "shred" | "wipefs" => Some("overwrites file or device contents"),
+        name if name.starts_with("mkfs") => Some("creates a filesystem"),
+        _ => None,
+    }
+}
+
+/// Like [`destructive_reason`], for a `--shell` script: checks each of the
+/// script's commands against the `[safety]` rules and the built-in patterns,
+/// then checks the whole script for output redirection. A rule allowing one
+/// command doesn't cover the commands around it, or any redirection.
+pub fn destructive_script_reason(script: &str, rules: &Rules) -> Option<&'static str> {
+    let commands: Vec<Vec<String>> = script_commands(script)
+        .map(|command| command.split_whitespace().map(String::from).collect())
+        .filter(|words: &Vec<String>| !words.is_empty())
+        .collect();
+    let denied = |words: &[String]| rules.denies(&words[0], &words[1..]);
+    if commands.iter().any(|words| denied(words) == Some(true)) {
+        return Some(DENIED);
+    }
+    commands
+        .iter()
+        .filter(|words| denied(words).is_none())
+        .find_map(|words| builtin_reason(&words[0], &words[1..]))
+        .or_else(|| {
+            redirects_to_file(script).then_some("shell output redirection may overwrite files")
+        })
+}
+
+/// Splits a shell script into its commands, at `;`, `&&`, `||`, `|`, `&`
+/// and newlines, but not at redirections like `2>&1` or `>|`. Quoting isn't
+/// understood, so a quoted separator splits too, which errs on the side of
+/// checking more.
+fn script_commands(script: &str) -> impl Iterator<Item = &str> {
+    let bytes = script.as_bytes();
+    let mut commands = vec![];
+    let mut start = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let redirection = (i > 0 && matches!(bytes[i - 1], b'>' | b'<'))
+            || (byte == b'&' && bytes.get(i + 1) == Some(&b'>'));
+        if matches!(byte, b';' | b'&' | b'|' | b'\n') && !redirection {
+            commands.push(&script[start..i]);
+            start = i + 1;
+        }
+    }
+    commands.push(&script[start..]);
+    commands
+        .into_iter()
+        .map(|command| command.trim_start_matches(|c: char| c.is_whitespace() || c == '('))
+}
+
+/// Whether a shell script redirects output to a file. Duplicating a file
+/// descriptor (e.g. `2>&1`) and writing to `/dev/null` don't count.
+fn redirects_to_file(script: &str) -> bool {
+    script.match_indices('>').any(|(i, _)| {
+        let target = script[i + 1..].trim_start_matches(['>', '|']);
+        if target.starts_with('&') {
+            return false;
+        }
+        let target = target.trim_start();
+        let word = target
+            .split(|c: char| c.is_whitespace() || ";&|)".contains(c))
+            .next()
+            .unwrap_or_default();
+        word != "/dev/null"
+    })
+}
+
+fn is_recursive_or_forced(arg: &str) -> bool {
+    match arg.strip_prefix("--") {
+        Some(long) => matches!(long, "recursive" | "force"),
+        None => arg.starts_with('-') && arg[1..].chars().any(|c| matches!(c, 'r' | 'R' | 'f')),
+    }
+}
+
+/// Prints why the command looks destructive along with a few sample expanded
+/// commands, and asks for confirmation on the controlling terminal (stdin is
+/// used for inputs).
+///
+/// # Errors
+/// Will return an error if the controlling terminal cannot be opened
+pub fn confirm(options: &Options, reason: &str, samples: &[OsString]) -> anyhow::Result<bool> {
+    let mut tty = File::options().read(true).write(true).open("/dev/tty")?;
+    writeln!(tty, "This command looks destructive ({reason}):")?;
+    let chunks = chunk_inputs(options, samples.iter().cloned()).take(SAMPLE_COMMANDS);
+    for (index, chunk) in chunks.enumerate() {
+        let command = command_line(options, index + 1, &chunk).join(OsStr::new(" "));
+        writeln!(tty, "    {}", command.to_string_lossy())?;
+    }
+    write!(tty, "Run it for all inputs? [y/N] ")?;
+    tty.flush()?;
+    let mut answer = String::new();
+    BufReader::new(tty).read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Asks before running each command with `-p`, on the controlling terminal
+/// (stdin is used for inputs)
+pub struct Prompt {
+    tty: BufReader<File>,
+    /// Whether `a` was answered, so the rest run without asking
+    all: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    All,
+}
+
+impl Prompt {
+    /// # Errors
+    /// Will return an error if the controlling terminal cannot be opened
+    pub fn open() -> anyhow::Result<Self> {
+        let tty = File::options().read(true).write(true).open("/dev/tty")?;
+        Ok(Self {
+            tty: BufReader::new(tty),
+            all: false,
+        })
+    }
+
+    /// Prints the command and asks whether to run it. Anything but `y` or `a`
+    /// (including failing to read an answer) means no.
+    pub fn ask(&mut self, command: &[OsString]) -> bool {
+        if self.all {
+            return true;
+        }
+        let tty = self.tty.get_mut();
+        let asked = write!(tty, "{} ?...[y/n/a] ", shell::join(command)).and_then(|()| tty.flush());
+        let mut answer = String::new();
+        if asked.is_err() || self.tty.read_line(&mut answer).is_err() {
+            return false;
+        }
+        match parse_answer(&answer) {
+            Answer::Yes => true,
+            Answer::No => false,
+            Answer::All => {
+                self.all = true;
+                true
+            }
+        }
+    }
+}
+
+fn parse_answer(answer: &str) -> Answer {
+    match answer.trim() {
+        "y" | "Y" | "yes" => Answer::Yes,
+        "a" | "A" | "all" => Answer::All,
+        _ => Answer::No,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn destructive_reason(program: &str, program_args: &[String]) -> Option<&'static str> {
+        super::destructive_reason(program, program_args, &Rules::default())
+    }
+
+    fn destructive_script_reason(script: &str) -> Option<&'static str> {
+        super::destructive_script_reason(script, &Rules::default())
+    }
+
+    #[test]
+    fn rm_recursive() {
+        assert!(destructive_reason("rm", &args(&["-rf"])).is_some());
+        assert!(destructive_reason("/bin/rm", &args(&["-R"])).is_some());
+        assert!(destructive_reason("rm", &args(&["--force"])).is_some());
+        assert!(destructive_reason("rm", &args(&["-i"])).is_none());
+        assert!(destructive_reason("rm", &args(&[])).is_none());
+    }
+
+    #[test]
+    fn dd_output_file() {
+        assert!(destructive_reason("dd", &args(&["if=/dev/zero", "of=disk.img"])).is_some());
+        assert!(destructive_reason("dd", &args(&["if=/dev/zero"])).is_none());
+    }
+
+    #[test]
+    fn shell_redirection() {
+        assert!(destructive_reason("sh", &args(&["-c", "echo > $1"])).is_some());
+        assert!(destructive_reason("sh", &args(&["-c", "echo >>$1.log"])).is_some());
+        assert!(destructive_reason("bash", &args(&["-c", "echo $1"])).is_none());
+        assert!(destructive_reason("sh", &args(&["script.sh"])).is_none());
+    }
+
+    #[test]
+    fn harmless_redirection() {
+        assert!(destructive_script_reason("make {} 2>&1 >build.log").is_some());
+        assert!(destructive_script_reason("make {} 2>&1").is_none());
+        assert!(destructive_script_reason("grep x {} >/dev/null").is_none());
+        assert!(destructive_script_reason("grep x {} > /dev/null 2>&1").is_none());
+        assert!(destructive_script_reason("echo {} >&2").is_none());
+        assert!(destructive_script_reason("echo {} >/dev/null; echo > out").is_some());
+    }
+
+    #[test]
+    fn configured_rules() {
+        let rules = Rules {
+            allow: vec![args(&["rm", "-rf", "build"]), args(&["git", "push"])],
+            deny: vec![args(&["git", "push", "--force"])],
+        };
+        let reason = |program, program_args: &[&str]| {
+            super::destructive_reason(program, &args(program_args), &rules)
+        };
+        assert_eq!(reason("/bin/rm", &["-rf", "build", "{}"]), None);
+        assert!(reason("rm", &["-rf", "{}"]).is_some());
+        assert_eq!(reason("git", &["push", "origin"]), None);
+        assert_eq!(reason("git", &["push", "--force", "{}"]), Some(DENIED));
+        assert_eq!(reason("git", &["status"]), None);
+        assert_eq!(
+            super::destructive_script_reason("git push --force origin {}", &rules),
+            Some(DENIED)
+        );
+        let script_reason = |script| super::destructive_script_reason(script, &rules);
+        assert_eq!(script_reason("rm -rf build {}"), None);
+        assert_eq!(script_reason("rm -rf build {} 2>&1"), None);
+        // Allowing a command doesn't cover its redirection, or the commands
+        // after it
+        assert_eq!(
+            script_reason("rm -rf build {} > log"),
+            Some("shell output redirection may overwrite files")
+        );
+        assert_eq!(
+            script_reason("rm -rf build; rm -rf {}"),
+            Some("recursive or forced removal")
+        );
+        assert_eq!(
+            script_reason("git status && git push --force"),
+            Some(DENIED)
+        );
+        assert_eq!(
+            super::destructive_reason("sh", &args(&["-c", "rm -rf build {}"]), &rules),
+            None
+        );
+    }
+
+    #[test]
+    fn chained_commands() {
+        assert!(destructive_script_reason("cd {} && rm -rf .").is_some());
+        assert!(destructive_script_reason("true; rm -rf {}").is_some());
+        assert!(destructive_script_reason("make {} || rm -rf {}").is_some());
+        assert!(destructive_script_reason("sleep 1 & rm -rf {}").is_some());
+        assert!(destructive_script_reason("echo {}\n(rm -rf {})").is_some());
+        assert!(destructive_script_reason("cat {} | shred").is_some());
+        assert!(destructive_script_reason("cat {} 2>&1 | sort && echo done").is_none());
+        assert!(destructive_reason("sh", &args(&["-c", "cd $1 && rm -rf ."])).is_some());
+    }
+
+    #[test]
+    fn shell_scripts() {
+        assert!(destructive_script_reason("rm -rf {}").is_some());
+        assert!(destructive_script_reason("sort {} > {}.sorted").is_some());
+        assert!(destructive_script_reason("convert {} {.}.png").is_none());
+    }
+
+    #[test]
+    fn harmless() {
+        assert!(destructive_reason("echo", &args(&["-rf"])).is_none());
+        assert!(destructive_reason("ls", &args(&["-la"])).is_none());
+    }
+
+    #[test]
+    fn prompt_answers() {
+        assert_eq!(parse_answer("y\n"), Answer::Yes);
+        assert_eq!(parse_answer(" yes "), Answer::Yes);
+        assert_eq!(parse_answer("a\n"), Answer::All);
+        assert_eq!(parse_answer("\n"), Answer::No);
+        assert_eq!(parse_answer("yep"), Answer::No);
+    }
+}
@@ -0,0 +1,154 @@
+//! Quoting for POSIX shells, e.g. for inputs that end up in a command line
+//! that a shell reads again:
+//!
+//! ```
+//! use std::ffi::OsStr;
+//!
+//! let input = OsStr::new("it's $5.txt");
+//! assert_eq!(arrgs::shell::quote_os(input), r"'it'\''s $5.txt'");
+//! ```
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+
+/// Quotes `arg` so that a POSIX shell reads it back as a single word. Words
+/// made only of safe characters are returned unchanged.
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+    }
+}
+
+/// Like [`quote`], but keeps bytes that aren't valid UTF-8 intact, for
+/// building scripts that are run by a shell
+pub fn quote_os(arg: &OsStr) -> OsString {
+    if let Some(arg) = arg.to_str() {
+        return quote(arg).into_owned().into();
+    }
+    let mut quoted = vec![b'\''];
+    for &byte in arg.as_encoded_bytes() {
+        match byte {
+            b'\'' => quoted.extend_from_slice(br"'\''"),
+            _ => quoted.push(byte),
+        }
+    }
+    quoted.push(b'\'');
+    // SAFETY: `arg` was only split around ASCII quotes, which are valid UTF-8
+    unsafe { OsString::from_encoded_bytes_unchecked(quoted) }
+}
+
+/// Quotes every word of a command line and joins them with spaces. Invalid
+/// UTF-8 is replaced with `U+FFFD`.
+pub fn join<S: AsRef<OsStr>>(command: impl IntoIterator<Item = S>) -> String {
+    command
+        .into_iter()
+        .map(|word| quote(&word.as_ref().to_string_lossy()).into_owned())
+        .intersperse(String::from(" "))
+        .collect()
+}
+
+/// Like [`join`], but keeps bytes that aren't valid UTF-8 intact, for
+/// building scripts that are run by a shell
+pub fn join_os<S: AsRef<OsStr>>(command: impl IntoIterator<Item = S>) -> OsString {
+    let mut joined = OsString::new();
+    for (i, word) in command.into_iter().enumerate() {
+        if i > 0 {
+            joined.push(" ");
+        }
+        joined.push(quote_os(word.as_ref()));
+    }
+    joined
+}
+
+/// Splits a command line back into its words, as a POSIX shell would without
+/// expanding anything: the reverse of [`join`]. Single and double quotes and
+/// backslashes are understood. Returns `None` if a quote isn't closed.
+pub fn split(line: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_default();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => {
+                            let c = chars.next()?;
+                            if !"$`\"\\\n".contains(c) {
+                                word.push('\\');
+                            }
+                            word.push(c);
+                        }
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_default().extend(chars.next()),
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_words_are_unchanged() {
+        assert_eq!(quote("foo/bar-baz.txt"), "foo/bar-baz.txt");
+        assert_eq!(quote("a=b"), "a=b");
+    }
+
+    #[test]
+    fn unsafe_words_are_quoted() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("foo bar"), "'foo bar'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn invalid_utf8_is_quoted() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let arg = OsStr::from_bytes(b"it's\xFF");
+        assert_eq!(quote_os(arg).as_bytes(), b"'it'\\''s\xFF'");
+        assert_eq!(quote_os(OsStr::new("a b")), "'a b'");
+    }
+
+    #[test]
+    fn join_command() {
+        assert_eq!(join(["echo", "hello world"]), "echo 'hello world'");
+        assert_eq!(join_os(["echo", "it's"]), r"echo 'it'\''s'");
+    }
+
+    #[test]
+    fn split_command() {
+        let words = ["echo", "", "a b", "it's", "$HOME", "tab\there"];
+        assert_eq!(split(&join(words)).unwrap(), words);
+        assert_eq!(
+            split(r#"a\ b "c \"d\" \e"  f"#).unwrap(),
+            ["a b", r#"c "d" \e"#, "f"]
+        );
+        assert_eq!(split("  ").unwrap(), Vec::<String>::new());
+        assert_eq!(split("echo 'oops"), None);
+    }
+}
@@ -0,0 +1,372 @@
+//! Waking up as soon as a child process exits, rather than polling on a timer,
+//! and catching termination signals so children can be cleaned up
+//!
+//! On Unix, a `SIGCHLD` handler writes to pipes that [`ChildExits::wait`]
+//! blocks on, and exited children are found with `waitid(P_ALL, WNOWAIT)`
+//! without reaping them, so that the `std::process::Child` they belong to can
+//! still be waited on as usual. Elsewhere, waiting falls back to sleeping for
+//! a short interval, and callers have to check every child.
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+/// Whether [`ChildExits::next_exited`] reports which children have exited.
+/// Without it, every child has to be checked after each wait.
+pub const REPORTS_EXITS: bool = cfg!(unix);
+
+/// The longest a wait lasts without a child exiting, in case other code has
+/// replaced the `SIGCHLD` handler
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// The termination signal that was caught, or 0
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(unix)]
+pub use unix::{catch_termination, ChildExits};
+
+/// The termination signal that was caught by [`catch_termination`], if any.
+/// Once one has been, children should be sent the same signal, and no more
+/// should be started or retried.
+pub fn received() -> Option<i32> {
+    Some(RECEIVED.load(Ordering::Relaxed)).filter(|&signal| signal != 0)
+}
+
+/// Reads from `R` until a termination signal is caught, which then ends the
+/// input. Without this, waiting for more input (e.g. typed at the terminal)
+/// would hold up exiting.
+pub struct UntilSignalled<R>(pub R);
+
+impl<R: Read> Read for UntilSignalled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && received().is_some() => Ok(0),
+            result => result,
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::os::fd::RawFd;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::OnceLock;
+
+    use super::*;
+
+    /// How many `ChildExits` can exist at once
+    const SLOTS: usize = 64;
+
+    /// The pipes the signal handler writes to, one per `ChildExits`. Pipes are
+    /// never closed, only reused, so the handler can't write to a file
+    /// descriptor that has since been reused for something else.
+    static WRITE_FDS: [AtomicI32; SLOTS] = [const { AtomicI32::new(-1) }; SLOTS];
+    static READ_FDS: [AtomicI32; SLOTS] = [const { AtomicI32::new(-1) }; SLOTS];
+    static IN_USE: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+    /// The error from installing the signal handler, if it failed
+    static INSTALLED: OnceLock<Result<(), i32>> = OnceLock::new();
+
+    /// Wakes the caller as soon as a child process exits
+    #[derive(Debug)]
+    pub struct ChildExits {
+        slot: usize,
+        read: RawFd,
+    }
+
+    impl ChildExits {
+        /// Installs the `SIGCHLD` handler, the first time it's called. This
+        /// must happen before starting the children to wait for, or their
+        /// exits may be missed until the next timeout.
+        ///
+        /// # Errors
+        /// Will return an error if the signal handler or a pipe can't be set
+        /// up, or too many `ChildExits` exist at once
+        pub fn new() -> io::Result<Self> {
+            if let Err(errno) = *INSTALLED
+                .get_or_init(|| install_handler().map_err(|e| e.raw_os_error().unwrap_or(0)))
+            {
+                return Err(io::Error::from_raw_os_error(errno));
+            }
+            let slot = (0..SLOTS)
+                .find(|&slot| {
+                    IN_USE[slot]
+                        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                        .is_ok()
+                })
+                .ok_or_else(|| io::Error::other("too many waits for child processes"))?;
+            let mut read = READ_FDS[slot].load(Ordering::Acquire);
+            if read < 0 {
+                match open_pipe() {
+                    Ok([pipe_read, pipe_write]) => {
+                        read = pipe_read;
+                        READ_FDS[slot].store(pipe_read, Ordering::Release);
+                        WRITE_FDS[slot].store(pipe_write, Ordering::Release);
+                    }
+                    Err(e) => {
+                        IN_USE[slot].store(false, Ordering::Release);
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(Self { slot, read })
+        }
+
+        /// Blocks until a child process exits, or the deadline passes
+        pub fn wait(&self, deadline: Option<Instant>) {
+            self.wait_readable(&[], deadline);
+        }
+
+        /// Blocks until a child process exits, one of `fds` can be read from
+        /// (or has been closed), or the deadline passes. Returns whether each
+        /// of `fds` is ready.
+        pub fn wait_readable(&self, fds: &[RawFd], deadline: Option<Instant>) -> Vec<bool> {
+            let timeout = deadline
+                .map_or(MAX_WAIT, |deadline| {
+                    deadline.saturating_duration_since(Instant::now())
+                })
+                .min(MAX_WAIT);
+            let mut pollfds: Vec<libc::pollfd> = std::iter::once(self.read)
+                .chain(fds.iter().copied())
+                .map(|fd| libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            // Rounded up, so as not to wake just before the deadline
+            let millis = timeout.as_nanos().div_ceil(1_000_000) as libc::c_int;
+            // SAFETY: `pollfds` is valid for reads and writes of its length
+            unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, millis) };
+            // Empty the pipe, so that the next wait blocks until another child
+            // exits. Children that exited in the meantime are still found by
+            // `next_exited`.
+            let mut buffer = [0u8; 64];
+            // SAFETY: `buffer` is valid for writes of its length
+            while unsafe { libc::read(self.read, buffer.as_mut_ptr().cast(), buffer.len()) } > 0 {}
+            pollfds[1..]
+                .iter()
+                .map(|pollfd| pollfd.revents != 0)
+                .collect()
+        }
+
+        /// Returns the pid of a child that has exited, without reaping it. The
+        /// same child is returned until it has been waited on.
+        pub fn next_exited(&self) -> Option<u32> {
+            // SAFETY: `siginfo_t` is plain old data, valid when zeroed
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            // SAFETY: `info` is a valid pointer to a `siginfo_t`
+            let result = unsafe {
+                libc::waitid(
+                    libc::P_ALL,
+                    0,
+                    &mut info,
+                    libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+                )
+            };
+            if result != 0 {
+                return None;
+            }
+            // SAFETY: `waitid` succeeded, so `info` describes a child (or has a
+            // pid of 0 if no child has exited)
+            let pid = unsafe { info.si_pid() };
+            u32::try_from(pid).ok().filter(|&pid| pid != 0)
+        }
+    }
+
+    impl Drop for ChildExits {
+        fn drop(&mut self) {
+            IN_USE[self.slot].store(false, Ordering::Release);
+        }
+    }
+
+    fn open_pipe() -> io::Result<[RawFd; 2]> {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` is valid for writes of two file descriptors
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for fd in fds {
+            // SAFETY: `fd` was just opened, and these only change its flags
+            unsafe {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
+            }
+        }
+        Ok(fds)
+    }
+
+    /// Catches `SIGINT` and `SIGTERM`, so that child processes can be cleaned
+    /// up before exiting: [`received`] returns the signal, and every
+    /// [`ChildExits::wait`] wakes up. A second signal exits straight away, as
+    /// if it hadn't been caught.
+    ///
+    /// Blocking calls on the thread that handles the signal are interrupted
+    /// rather than restarted, so that e.g. reading from [`UntilSignalled`]
+    /// stdin can stop.
+    ///
+    /// # Errors
+    /// Will return an error if the signal handlers can't be installed
+    pub fn catch_termination() -> io::Result<()> {
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            set_handler(signal, on_termination, 0)?;
+        }
+        Ok(())
+    }
+
+    fn install_handler() -> io::Result<()> {
+        set_handler(
+            libc::SIGCHLD,
+            on_sigchld,
+            libc::SA_RESTART | libc::SA_NOCLDSTOP,
+        )
+    }
+
+    fn set_handler(
+        signal: libc::c_int,
+        handler: extern "C" fn(libc::c_int),
+        flags: libc::c_int,
+    ) -> io::Result<()> {
+        // SAFETY: `sigaction` is plain old data, valid when zeroed
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = flags;
+        // SAFETY: `action` is a valid `sigaction`, and the handlers only make
+        // async-signal-safe calls
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    extern "C" fn on_sigchld(_: libc::c_int) {
+        preserving_errno(wake_all);
+    }
+
+    extern "C" fn on_termination(signal: libc::c_int) {
+        preserving_errno(|| {
+            if RECEIVED.swap(signal, Ordering::Relaxed) != 0 {
+                // SAFETY: `signal` and `raise` are async-signal-safe. The
+                // signal is blocked until this handler returns, and then
+                // terminates the process.
+                unsafe {
+                    libc::signal(signal, libc::SIG_DFL);
+                    libc::raise(signal);
+                }
+            }
+            wake_all();
+        });
+    }
+
+    /// Wakes every [`ChildExits::wait`]
+    fn wake_all() {
+        for fd in &WRITE_FDS {
+            let fd = fd.load(Ordering::Acquire);
+            if fd >= 0 {
+                // SAFETY: `write` is async-signal-safe. If the pipe is full,
+                // there's already a wakeup pending.
+                unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+            }
+        }
+    }
+
+    /// Runs part of a signal handler, restoring `errno` afterwards since the
+    /// interrupted code may be about to read it
+    fn preserving_errno(f: impl FnOnce()) {
+        let errno = io::Error::last_os_error().raw_os_error();
+        f();
+        if let Some(errno) = errno {
+            set_errno(errno);
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn set_errno(errno: i32) {
+        // SAFETY: `__errno_location` returns a valid pointer to this thread's
+        // `errno`
+        unsafe { *libc::__errno_location() = errno };
+    }
+
+    #[cfg(target_vendor = "apple")]
+    fn set_errno(errno: i32) {
+        // SAFETY: `__error` returns a valid pointer to this thread's `errno`
+        unsafe { *libc::__error() = errno };
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+    fn set_errno(_: i32) {}
+}
+
+/// Termination signals aren't caught, so [`received`] is always `None`
+///
+/// # Errors
+/// Never returns an error
+#[cfg(not(unix))]
+pub fn catch_termination() -> io::Result<()> {
+    Ok(())
+}
+
+/// Wakes the caller after a short interval, since child exits can't be waited
+/// for directly
+#[cfg(not(unix))]
+#[derive(Debug)]
+pub struct ChildExits;
+
+#[cfg(not(unix))]
+impl ChildExits {
+    /// # Errors
+    /// Never returns an error
+    pub fn new() -> io::Result<Self> {
+        Ok(Self)
+    }
+
+    pub fn wait(&self, deadline: Option<Instant>) {
+        let interval = Duration::from_millis(10);
+        let timeout = deadline.map_or(interval, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        });
+        std::thread::sleep(timeout.min(interval).min(MAX_WAIT));
+    }
+
+    pub fn next_exited(&self) -> Option<u32> {
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    #[test]
+    fn wakes_when_a_child_exits() {
+        let exits = ChildExits::new().unwrap();
+        let mut child = Command::new("sleep").arg("0.05").spawn().unwrap();
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(10);
+        // Other tests' children may wake us first
+        while child.try_wait().unwrap().is_none() {
+            exits.wait(Some(deadline));
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn wakes_when_a_pipe_is_readable() {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+
+        let exits = ChildExits::new().unwrap();
+        let (reader, mut writer) = io::pipe().unwrap();
+        let (idle, idle_writer) = io::pipe().unwrap();
+        let fds = [idle.as_raw_fd(), reader.as_raw_fd()];
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(exits.wait_readable(&fds, Some(deadline)), [false, false]);
+        writer.write_all(b"x").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert_eq!(exits.wait_readable(&fds, Some(deadline)), [false, true]);
+        drop(writer);
+        drop(idle_writer);
+        assert_eq!(exits.wait_readable(&fds, Some(deadline)), [true, true]);
+    }
+}
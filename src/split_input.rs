@@ -1,77 +1,596 @@
-use std::str::SplitWhitespace;
+use std::collections::{HashSet, VecDeque};
+use std::ffi::OsString;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{BufRead, BufReader, Read};
+use std::iter::Peekable;
 
-pub struct NullSplitter<'a> {
-    buffer: &'a [u8],
+use clap::ValueEnum;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::de::IoRead;
+use serde_json::{StreamDeserializer, Value};
+
+use crate::signals;
+
+/// How inputs are read, with `--input-format`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Inputs separated by whitespace, or by `-0`/`-d`
+    #[default]
+    Text,
+    /// A JSON array, or JSON values one after another (e.g. JSON lines)
+    Json,
+    /// Comma-separated values, with a header line naming the columns unless
+    /// `--no-header`
+    Csv,
+    /// Tab-separated values, quoted like `csv`
+    Tsv,
 }
 
-impl<'a> Iterator for NullSplitter<'a> {
-    type Item = &'a str;
+enum Separator {
+    Delimiter(Vec<u8>),
+    Whitespace,
+    /// Each non-blank line, without its line ending
+    Lines,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let null_index = self.buffer.iter().position(|&b| b == 0);
-        let output = match null_index {
-            None => {
-                if self.buffer.is_empty() {
-                    return None;
+/// Splits inputs read from `R` as they arrive, rather than waiting for all of
+/// the input to be read first.
+///
+/// Inputs are passed through as raw bytes, so they don't need to be valid
+/// UTF-8 (e.g. arbitrary filenames from `find -print0`). Whitespace splitting
+/// only considers ASCII whitespace.
+pub struct Splitter<R> {
+    reader: BufReader<R>,
+    separator: Separator,
+    pending: VecDeque<OsString>,
+    done: bool,
+}
+
+impl<R: Read> Splitter<R> {
+    pub fn null(reader: R) -> Self {
+        Self::delimiter(reader, b"\0")
+    }
+
+    /// Splits on an arbitrary (non-empty) delimiter string
+    pub fn delimiter(reader: R, delimiter: &[u8]) -> Self {
+        assert!(!delimiter.is_empty(), "delimiter must not be empty");
+        Self::new(reader, Separator::Delimiter(delimiter.to_vec()))
+    }
+
+    pub fn whitespace(reader: R) -> Self {
+        Self::new(reader, Separator::Whitespace)
+    }
+
+    /// Makes each line one input, spaces and all, like `xargs -L`. Blank
+    /// lines are skipped, and `\r\n` line endings are removed too.
+    pub fn lines(reader: R) -> Self {
+        Self::new(reader, Separator::Lines)
+    }
+
+    fn new(reader: R, separator: Separator) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            separator,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Reads up to the next delimiter (or newline, for whitespace separated
+    /// inputs and lines), queueing any inputs found
+    fn fill(&mut self) {
+        let delimiter: &[u8] = match &self.separator {
+            Separator::Delimiter(delimiter) => delimiter,
+            Separator::Whitespace | Separator::Lines => b"\n",
+        };
+        let last_byte = delimiter[delimiter.len() - 1];
+        let mut buffer = vec![];
+        // Multi-byte delimiters may need several reads to find the whole thing
+        while !buffer.ends_with(delimiter) {
+            match self.reader.read_until(last_byte, &mut buffer) {
+                Ok(0) | Err(_) => {
+                    self.done = true;
+                    break;
                 }
-                let output = self.buffer;
-                self.buffer = &[];
-                output
+                Ok(_) => {}
             }
-            Some(null_index) => {
-                let (output, rest) = self.buffer.split_at(null_index);
-                self.buffer = &rest[1.min(self.buffer.len())..];
-                output
+        }
+        if buffer.ends_with(delimiter) {
+            buffer.truncate(buffer.len() - delimiter.len());
+        } else if buffer.is_empty() {
+            return;
+        }
+        match self.separator {
+            Separator::Delimiter(_) => self.pending.push_back(os_string(buffer)),
+            Separator::Whitespace => self.pending.extend(
+                buffer
+                    .split(u8::is_ascii_whitespace)
+                    .filter(|input| !input.is_empty())
+                    .map(|input| os_string(input.to_vec())),
+            ),
+            Separator::Lines => {
+                if buffer.ends_with(b"\r") {
+                    buffer.pop();
+                }
+                if !buffer.iter().all(u8::is_ascii_whitespace) {
+                    self.pending.push_back(os_string(buffer));
+                }
             }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    std::os::unix::ffi::OsStringExt::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+impl<R: Read> Iterator for Splitter<R> {
+    type Item = OsString;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(input) = self.pending.pop_front() {
+                return Some(input);
+            }
+            if self.done {
+                return None;
+            }
+            self.fill();
+        }
+    }
+}
+
+/// Reads inputs from JSON: the elements of each top-level array, or each
+/// top-level value that isn't an array, e.g. JSON lines. Strings are used as
+/// they are, `null` as an empty input and anything else as its JSON.
+///
+/// If the first element is an object, its keys are the inputs' fields, for
+/// `{name}` placeholders. Every element must then be an object, and gives an
+/// input for each field (empty if the object doesn't have it), in the order
+/// of the first object's keys.
+///
+/// Top-level arrays are read whole, but other values are read as they arrive.
+pub struct JsonInputs<R: Read> {
+    values: StreamDeserializer<'static, IoRead<BufReader<R>>, Json>,
+    /// The rest of the top-level array being read
+    elements: VecDeque<Json>,
+    fields: Vec<String>,
+    pending: VecDeque<OsString>,
+    done: bool,
+}
+
+impl<R: Read> JsonInputs<R> {
+    /// Reads up to the first element, to find the fields
+    ///
+    /// # Errors
+    /// Will return an error if the input doesn't start with valid JSON
+    pub fn new(reader: R) -> serde_json::Result<Self> {
+        let mut inputs = Self {
+            values: serde_json::Deserializer::from_reader(BufReader::new(reader)).into_iter(),
+            elements: VecDeque::new(),
+            fields: vec![],
+            pending: VecDeque::new(),
+            done: false,
         };
-        output.utf8_chunks().next().map(|c| c.valid())
+        if let Some(first) = inputs.next_element()? {
+            if let Json::Object(entries) = &first {
+                inputs.fields = entries.iter().map(|(key, _)| key.clone()).collect();
+            }
+            inputs.elements.push_front(first);
+        }
+        Ok(inputs)
+    }
+
+    /// The names of the inputs' fields, if the elements are objects
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    fn next_element(&mut self) -> serde_json::Result<Option<Json>> {
+        loop {
+            if let Some(element) = self.elements.pop_front() {
+                return Ok(Some(element));
+            }
+            match self.values.next().transpose()? {
+                Some(Json::Array(elements)) => self.elements.extend(elements),
+                element => return Ok(element),
+            }
+        }
+    }
+
+    /// Queues the inputs for the next element, or returns why there aren't
+    /// any more
+    fn fill(&mut self) -> Result<(), String> {
+        let element = match self.next_element() {
+            Ok(Some(element)) => element,
+            Ok(None) => return Err(String::new()),
+            Err(e) => return Err(format!("invalid JSON input: {e}")),
+        };
+        if self.fields.is_empty() {
+            self.pending.push_back(element.into_input());
+            return Ok(());
+        }
+        let Json::Object(mut entries) = element else {
+            return Err(format!(
+                "expected a JSON object like the first input, found {}",
+                element.into_value()
+            ));
+        };
+        for field in &self.fields {
+            let input = match entries.iter().position(|(key, _)| key == field) {
+                Some(i) => Json::Value(entries.swap_remove(i).1).into_input(),
+                None => OsString::new(),
+            };
+            self.pending.push_back(input);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for JsonInputs<R> {
+    type Item = OsString;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(input) = self.pending.pop_front() {
+                return Some(input);
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.fill() {
+                self.done = true;
+                // Reading stops early when interrupted, which isn't worth
+                // complaining about
+                if !e.is_empty() && signals::received().is_none() {
+                    eprintln!("arrgs: {e}");
+                }
+            }
+        }
     }
 }
 
-pub enum Splitter<'a> {
-    Null(NullSplitter<'a>),
-    Whitespace(SplitWhitespace<'a>),
+/// Reads inputs from CSV (or TSV, with a tab delimiter): an input for each
+/// column of each record. Fields may be quoted with `"`, to include the
+/// delimiter, newlines or (doubled) quotes. Blank lines are skipped.
+///
+/// The first record is the header, naming the columns for `{name}`
+/// placeholders, unless there isn't one. Either way, it sets the number of
+/// columns: shorter records are padded with empty inputs, and longer ones cut
+/// short, so that each record is the same number of inputs.
+pub struct CsvInputs<R> {
+    reader: BufReader<R>,
+    delimiter: u8,
+    fields: Vec<String>,
+    columns: usize,
+    pending: VecDeque<OsString>,
+    done: bool,
 }
 
-impl<'a> Splitter<'a> {
-    pub fn null(buffer: &'a [u8]) -> Self {
-        Self::Null(NullSplitter { buffer })
+impl<R: Read> CsvInputs<R> {
+    /// Reads the first record, for the header or the number of columns
+    pub fn new(reader: R, delimiter: u8, header: bool) -> Self {
+        let mut inputs = Self {
+            reader: BufReader::new(reader),
+            delimiter,
+            fields: vec![],
+            columns: 0,
+            pending: VecDeque::new(),
+            done: false,
+        };
+        let first = inputs.read_record().unwrap_or_default();
+        inputs.columns = first.len();
+        if header {
+            inputs.fields = first
+                .iter()
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect();
+        } else {
+            inputs.pending.extend(first);
+        }
+        inputs
     }
 
-    pub fn whitespace(buffer: &'a [u8]) -> Self {
-        let contents = buffer.utf8_chunks().next().map_or("", |c| c.valid());
-        Self::Whitespace(contents.split_whitespace())
+    /// The column names from the header, if there is one
+    pub fn fields(&self) -> &[String] {
+        &self.fields
     }
 
-    pub fn chunks(self, chunk_size: usize) -> SplitterChunks<'a> {
-        SplitterChunks {
-            iter: self,
-            chunk_size,
+    /// How many inputs each record is
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Reads the next record, which may span several lines, or `None` at the
+    /// end of the input
+    fn read_record(&mut self) -> Option<Vec<OsString>> {
+        let mut record = vec![];
+        let mut field = vec![];
+        let mut quoted = false;
+        let mut line = vec![];
+        loop {
+            line.clear();
+            match self.reader.read_until(b'\n', &mut line) {
+                // An unterminated quote ends with the input
+                Ok(0) | Err(_) if quoted => break,
+                Ok(0) | Err(_) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(_) => {}
+            }
+            if !quoted && record.is_empty() && field.is_empty() {
+                if let b"\n" | b"\r\n" = line.as_slice() {
+                    continue;
+                }
+            }
+            let mut bytes = line.iter().copied().peekable();
+            while let Some(byte) = bytes.next() {
+                match byte {
+                    b'"' if quoted => {
+                        if bytes.next_if_eq(&b'"').is_some() {
+                            field.push(b'"');
+                        } else {
+                            quoted = false;
+                        }
+                    }
+                    _ if quoted => field.push(byte),
+                    b'"' if field.is_empty() => quoted = true,
+                    b'\n' => {}
+                    _ if byte == self.delimiter => {
+                        record.push(os_string(std::mem::take(&mut field)))
+                    }
+                    _ => field.push(byte),
+                }
+            }
+            if !quoted {
+                break;
+            }
         }
+        if field.last() == Some(&b'\r') {
+            field.pop();
+        }
+        record.push(os_string(field));
+        Some(record)
     }
 }
 
-impl<'a> Iterator for Splitter<'a> {
-    type Item = &'a str;
+impl<R: Read> Iterator for CsvInputs<R> {
+    type Item = OsString;
 
     fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(input) = self.pending.pop_front() {
+                return Some(input);
+            }
+            if self.done {
+                return None;
+            }
+            if let Some(mut record) = self.read_record() {
+                record.resize(self.columns, OsString::new());
+                self.pending.extend(record);
+            }
+        }
+    }
+}
+
+/// A JSON value as read by [`JsonInputs`], keeping the order of objects' keys
+enum Json {
+    Array(Vec<Json>),
+    Object(Vec<(String, Value)>),
+    Value(Value),
+}
+
+impl Json {
+    fn into_value(self) -> Value {
         match self {
-            Splitter::Null(null_splitter) => null_splitter.next(),
-            Splitter::Whitespace(split_whitespace) => split_whitespace.next(),
+            Self::Array(elements) => elements.into_iter().map(Self::into_value).collect(),
+            Self::Object(entries) => Value::Object(entries.into_iter().collect()),
+            Self::Value(value) => value,
+        }
+    }
+
+    fn into_input(self) -> OsString {
+        match self.into_value() {
+            Value::Null => OsString::new(),
+            Value::String(s) => s.into(),
+            value => value.to_string().into(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonVisitor)
+    }
+}
+
+struct JsonVisitor;
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Json, E> {
+        Ok(Json::Value(v.into()))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Json, E> {
+        Ok(Json::Value(v.into()))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Json, E> {
+        Ok(Json::Value(v.into()))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Json, E> {
+        Ok(Json::Value(v.into()))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Json, E> {
+        Ok(Json::Value(v.into()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Json, E> {
+        Ok(Json::Value(v.into()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Json, E> {
+        Ok(Json::Value(Value::Null))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json, A::Error> {
+        let mut elements = vec![];
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Json::Array(elements))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+        let mut entries = vec![];
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(Json::Object(entries))
+    }
+}
+
+/// Reads `R` in blocks of whole records for `--pipe`, so that no record is
+/// split between two child processes. Records end with a delimiter (a newline
+/// by default), which is kept.
+///
+/// Each block is as many records as fit in `size` bytes, or a fixed number of
+/// records. A record longer than `size` gets a block to itself.
+pub struct Blocks<R> {
+    reader: BufReader<R>,
+    delimiter: Vec<u8>,
+    size: usize,
+    records: Option<usize>,
+    /// A record that didn't fit in the last block
+    pending: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> Blocks<R> {
+    pub fn new(reader: R, delimiter: &[u8], size: usize) -> Self {
+        assert!(!delimiter.is_empty(), "delimiter must not be empty");
+        Self {
+            reader: BufReader::new(reader),
+            delimiter: delimiter.to_vec(),
+            size,
+            records: None,
+            pending: vec![],
+            done: false,
+        }
+    }
+
+    /// Puts this many records in each block instead, however long they are
+    pub fn records(mut self, records: usize) -> Self {
+        self.records = Some(records.max(1));
+        self
+    }
+
+    /// Reads the next record, or an empty `Vec` at the end of the input
+    fn read_record(&mut self) -> Vec<u8> {
+        let mut record = std::mem::take(&mut self.pending);
+        if !record.is_empty() || self.done {
+            return record;
+        }
+        let last_byte = self.delimiter[self.delimiter.len() - 1];
+        while !record.ends_with(&self.delimiter) {
+            match self.reader.read_until(last_byte, &mut record) {
+                Ok(0) | Err(_) => {
+                    self.done = true;
+                    break;
+                }
+                Ok(_) => {}
+            }
         }
+        record
     }
 }
 
-pub struct SplitterChunks<'a> {
+impl<R: Read> Iterator for Blocks<R> {
+    type Item = OsString;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut block = vec![];
+        let mut records = 0;
+        while self.records.is_none_or(|limit| records < limit) {
+            let record = self.read_record();
+            if record.is_empty() {
+                break;
+            }
+            if self.records.is_none() && !block.is_empty() && block.len() + record.len() > self.size
+            {
+                self.pending = record;
+                break;
+            }
+            block.extend(record);
+            records += 1;
+        }
+        (!block.is_empty()).then(|| os_string(block))
+    }
+}
+
+/// Groups the items of `iter` into `Vec`s of (at most) `chunk_size` items
+pub fn chunks<I: Iterator>(iter: I, chunk_size: usize) -> Chunks<I> {
+    Chunks {
+        iter: iter.peekable(),
+        chunk_size,
+        budget: usize::MAX,
+        cost: |_| 0,
+    }
+}
+
+pub struct Chunks<I: Iterator, F = fn(&<I as Iterator>::Item) -> usize> {
     chunk_size: usize,
-    iter: Splitter<'a>,
+    budget: usize,
+    cost: F,
+    iter: Peekable<I>,
 }
 
-impl<'a> Iterator for SplitterChunks<'a> {
-    type Item = Vec<&'a str>;
+impl<I: Iterator, F> Chunks<I, F> {
+    /// Also ends each chunk before the total `cost` of its items would exceed
+    /// `budget`. An item that costs more than the whole budget gets a chunk
+    /// to itself.
+    pub fn within<G: FnMut(&I::Item) -> usize>(self, budget: usize, cost: G) -> Chunks<I, G> {
+        Chunks {
+            chunk_size: self.chunk_size,
+            budget,
+            cost,
+            iter: self.iter,
+        }
+    }
+}
+
+impl<I: Iterator, F: FnMut(&I::Item) -> usize> Iterator for Chunks<I, F> {
+    type Item = Vec<I::Item>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let result: Vec<_> = self.iter.by_ref().take(self.chunk_size).collect();
+        let mut result = vec![];
+        let mut spent = 0usize;
+        while result.len() < self.chunk_size {
+            let Some(item) = self.iter.peek() else {
+                break;
+            };
+            spent = spent.saturating_add((self.cost)(item));
+            if spent > self.budget && !result.is_empty() {
+                break;
+            }
+            result.extend(self.iter.next());
+        }
         if result.is_empty() {
             None
         } else {
@@ -80,6 +599,63 @@ impl<'a> Iterator for SplitterChunks<'a> {
     }
 }
 
+/// The items of `iter`, skipping any equal to an earlier one
+pub fn unique<I: Iterator>(iter: I) -> Unique<I>
+where
+    I::Item: Eq + Hash + Clone,
+{
+    Unique {
+        iter,
+        seen: HashSet::new(),
+    }
+}
+
+pub struct Unique<I: Iterator> {
+    iter: I,
+    seen: HashSet<I::Item>,
+}
+
+impl<I: Iterator> Iterator for Unique<I>
+where
+    I::Item: Eq + Hash + Clone,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let seen = &mut self.seen;
+        self.iter.find(|item| seen.insert(item.clone()))
+    }
+}
+
+/// The items of `iter` in a random order, which is the same for the same
+/// `seed`. Nothing comes out until all of `iter` has been read.
+pub fn shuffled<I: Iterator>(iter: I, seed: u64) -> Shuffled<I> {
+    Shuffled {
+        iter: Some(iter),
+        items: vec![],
+        rng: fastrand::Rng::with_seed(seed),
+    }
+}
+
+pub struct Shuffled<I: Iterator> {
+    /// `None` once it's all been read
+    iter: Option<I>,
+    items: Vec<I::Item>,
+    rng: fastrand::Rng,
+}
+
+impl<I: Iterator> Iterator for Shuffled<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(iter) = self.iter.take() {
+            self.items = iter.collect();
+            self.rng.shuffle(&mut self.items);
+        }
+        self.items.pop()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,60 +663,207 @@ mod tests {
     #[test]
     fn null_splitter() {
         let buffer = b"foo\0bar\0baz\0";
-        let result: Vec<_> = Splitter::null(buffer).collect();
+        let result: Vec<_> = Splitter::null(&buffer[..]).collect();
         assert_eq!(result, vec!["foo", "bar", "baz"]);
     }
 
     #[test]
     fn null_splitter_no_null() {
         let buffer = b"foo bar baz";
-        let result: Vec<_> = Splitter::null(buffer).collect();
+        let result: Vec<_> = Splitter::null(&buffer[..]).collect();
         assert_eq!(result, vec!["foo bar baz"]);
     }
 
     #[test]
     fn whitespace_splitter() {
         let buffer = b"foo bar baz";
-        let result: Vec<_> = Splitter::whitespace(buffer).collect();
+        let result: Vec<_> = Splitter::whitespace(&buffer[..]).collect();
         assert_eq!(result, vec!["foo", "bar", "baz"]);
     }
 
     #[test]
     fn whitespace_splitter_no_whitespace() {
         let buffer = b"foo\0bar\0baz\0";
-        let result: Vec<_> = Splitter::whitespace(buffer).collect();
+        let result: Vec<_> = Splitter::whitespace(&buffer[..]).collect();
         assert_eq!(result, vec!["foo\0bar\0baz\0"]);
     }
 
     #[test]
     fn splitter_empty() {
         let buffer = b"";
-        let result = Splitter::null(buffer).collect::<Vec<_>>();
-        assert_eq!(result, Vec::<&str>::new());
-        let result: Vec<_> = Splitter::whitespace(buffer).collect();
-        assert_eq!(result, Vec::<&str>::new());
+        let result = Splitter::null(&buffer[..]).collect::<Vec<_>>();
+        assert_eq!(result, Vec::<OsString>::new());
+        let result: Vec<_> = Splitter::whitespace(&buffer[..]).collect();
+        assert_eq!(result, Vec::<OsString>::new());
     }
 
     #[test]
+    #[cfg(unix)]
     fn bad_utf8() {
-        let buffer = b"foo\xFFbar";
-        let result: Vec<_> = Splitter::null(buffer).collect();
-        assert_eq!(result, vec!["foo"]);
-        let result: Vec<_> = Splitter::whitespace(buffer).collect();
-        assert_eq!(result, vec!["foo"]);
+        use std::os::unix::ffi::OsStrExt;
+
+        let buffer = b"foo\xFFbar\0baz";
+        let result: Vec<_> = Splitter::null(&buffer[..]).collect();
+        assert_eq!(result[0].as_bytes(), b"foo\xFFbar");
+        assert_eq!(result[1], "baz");
+        let result: Vec<_> = Splitter::whitespace(&buffer[..]).collect();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].as_bytes(), b"foo\xFFbar\0baz");
     }
 
     #[test]
     fn chunks_1() {
         let buffer = b"foo\0bar\0baz\0";
-        let result: Vec<_> = Splitter::null(buffer).chunks(1).collect();
+        let result: Vec<_> = chunks(Splitter::null(&buffer[..]), 1).collect();
         assert_eq!(result, vec![vec!["foo"], vec!["bar"], vec!["baz"]]);
     }
 
     #[test]
     fn chunks_incomplete() {
         let buffer = b"foo\0bar\0baz\0";
-        let result: Vec<_> = Splitter::null(buffer).chunks(2).collect();
+        let result: Vec<_> = chunks(Splitter::null(&buffer[..]), 2).collect();
         assert_eq!(result, vec![vec!["foo", "bar"], vec!["baz"]]);
     }
+
+    #[test]
+    fn chunks_within_budget() {
+        let buffer = b"a\0bb\0cccccc\0d\0e\0";
+        let result: Vec<_> = chunks(Splitter::null(&buffer[..]), usize::MAX)
+            .within(5, |input| input.len() + 1)
+            .collect();
+        assert_eq!(
+            result,
+            vec![vec!["a", "bb"], vec!["cccccc"], vec!["d", "e"]]
+        );
+        let result: Vec<_> = chunks(1..=6, 2).within(7, |&n| n).collect();
+        assert_eq!(result, vec![vec![1, 2], vec![3, 4], vec![5], vec![6]]);
+    }
+
+    #[test]
+    fn json_inputs() {
+        let buffer = br#"["a b", 1, null, {"x": [true]}] "c" [] [2.5]"#;
+        let inputs = JsonInputs::new(&buffer[..]).unwrap();
+        assert!(inputs.fields().is_empty());
+        let result: Vec<_> = inputs.collect();
+        assert_eq!(result, vec!["a b", "1", "", r#"{"x":[true]}"#, "c", "2.5"]);
+        assert_eq!(JsonInputs::new(&b" "[..]).unwrap().count(), 0);
+        assert!(JsonInputs::new(&b"[1,"[..]).is_err());
+    }
+
+    #[test]
+    fn json_fields() {
+        let buffer = b"{\"path\": \"a.txt\", \"id\": 7}\n{\"id\": 8, \"extra\": 0}\n[1]\n";
+        let inputs = JsonInputs::new(&buffer[..]).unwrap();
+        assert_eq!(inputs.fields(), ["path", "id"]);
+        let result: Vec<_> = inputs.collect();
+        // The last element isn't an object, so reading stops there
+        assert_eq!(result, vec!["a.txt", "7", "", "8"]);
+    }
+
+    #[test]
+    fn csv_inputs() {
+        let buffer = b"name,note\r\n\r\na.txt,\"x, \"\"y\"\"\"\nb c,\"two\nlines\",extra\n\"d\"\n";
+        let inputs = CsvInputs::new(&buffer[..], b',', true);
+        assert_eq!(inputs.fields(), ["name", "note"]);
+        let result: Vec<_> = inputs.collect();
+        assert_eq!(
+            result,
+            vec!["a.txt", "x, \"y\"", "b c", "two\nlines", "d", ""]
+        );
+    }
+
+    #[test]
+    fn tsv_without_header() {
+        let buffer = b"1\t2\n3\t\n\"4\t5";
+        let inputs = CsvInputs::new(&buffer[..], b'\t', false);
+        assert!(inputs.fields().is_empty());
+        assert_eq!(inputs.columns(), 2);
+        let result: Vec<_> = inputs.collect();
+        assert_eq!(result, vec!["1", "2", "3", "", "4\t5", ""]);
+        let empty = CsvInputs::new(&b""[..], b',', true);
+        assert_eq!(empty.columns(), 0);
+        assert_eq!(empty.count(), 0);
+    }
+
+    #[test]
+    fn blocks_of_whole_records() {
+        let buffer = b"one\ntwo\nthree\nfour";
+        let result: Vec<_> = Blocks::new(&buffer[..], b"\n", 8).collect();
+        assert_eq!(result, vec!["one\ntwo\n", "three\n", "four"]);
+        let result: Vec<_> = Blocks::new(&buffer[..], b"\n", 2).collect();
+        assert_eq!(result, vec!["one\n", "two\n", "three\n", "four"]);
+        let result: Vec<_> = Blocks::new(&buffer[..], b"\n", 1024).collect();
+        assert_eq!(result, vec!["one\ntwo\nthree\nfour"]);
+    }
+
+    #[test]
+    fn blocks_of_records() {
+        let buffer = b"a::b::c:d::e";
+        let result: Vec<_> = Blocks::new(&buffer[..], b"::", 1).records(2).collect();
+        assert_eq!(result, vec!["a::b::", "c:d::e"]);
+        let result: Vec<_> = Blocks::new(&b""[..], b"\n", 1).collect();
+        assert_eq!(result, Vec::<OsString>::new());
+    }
+
+    #[test]
+    fn delimiter_splitter() {
+        let buffer = b"foo,bar baz,,qux";
+        let result: Vec<_> = Splitter::delimiter(&buffer[..], b",").collect();
+        assert_eq!(result, vec!["foo", "bar baz", "", "qux"]);
+    }
+
+    #[test]
+    fn delimiter_splitter_multi_byte() {
+        let buffer = b"foo::bar:baz::";
+        let result: Vec<_> = Splitter::delimiter(&buffer[..], b"::").collect();
+        assert_eq!(result, vec!["foo", "bar:baz"]);
+    }
+
+    #[test]
+    fn delimiter_splitter_newline() {
+        let buffer = b"foo bar\nbaz\n";
+        let result: Vec<_> = Splitter::delimiter(&buffer[..], b"\n").collect();
+        assert_eq!(result, vec!["foo bar", "baz"]);
+    }
+
+    #[test]
+    fn lines_splitter() {
+        let buffer = b"foo  bar \n\n \t\nbaz\r\nqux";
+        let result: Vec<_> = Splitter::lines(&buffer[..]).collect();
+        assert_eq!(result, vec!["foo  bar ", "baz", "qux"]);
+    }
+
+    #[test]
+    fn whitespace_across_lines() {
+        let buffer = b"foo bar\n  baz\n\nqux";
+        let result: Vec<_> = Splitter::whitespace(&buffer[..]).collect();
+        assert_eq!(result, vec!["foo", "bar", "baz", "qux"]);
+    }
+
+    #[test]
+    fn streams_from_reader() {
+        // Inputs are available before the reader reaches the end of its data
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let mut splitter = Splitter::null(reader);
+        std::io::Write::write_all(&mut writer, b"foo\0").unwrap();
+        assert_eq!(splitter.next().unwrap(), "foo");
+        drop(writer);
+        assert_eq!(splitter.next(), None);
+    }
+
+    #[test]
+    fn unique_inputs() {
+        let inputs = Splitter::whitespace(&b"b a b c a"[..]);
+        assert_eq!(unique(inputs).collect::<Vec<_>>(), ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn shuffled_inputs() {
+        let shuffle = |seed| shuffled(1..=20, seed).collect::<Vec<_>>();
+        let mut inputs = shuffle(1);
+        assert_eq!(inputs, shuffle(1));
+        assert_ne!(inputs, shuffle(2));
+        inputs.sort();
+        assert_eq!(inputs, (1..=20).collect::<Vec<_>>());
+    }
 }
@@ -0,0 +1,205 @@
+//! Placeholders in the program arguments (or `--shell` script) that are
+//! replaced with each job's inputs:
+//!
+//! - `{}` (or the `-I` token): all of the inputs, separated by spaces
+//! - `{1}`, `{2}`, ...: one input, counting from 1, or nothing if there are
+//!   fewer inputs
+//! - `{.}`: the inputs without their extensions
+//! - `{/}`: the inputs' file names, without their directories
+//! - `{//}`: the inputs' directories, or `.` for inputs without one
+//! - `{#}`: the job number, counting from 1
+//! - `{name}`: with structured input (`--input-format json`, `csv` or `tsv`),
+//!   the input for the field or column `name`
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use crate::shell;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    All,
+    Field(usize),
+    Stem,
+    Basename,
+    Dirname,
+    Seq,
+}
+
+/// What the placeholders are replaced with for one job
+pub struct Values<'a, S> {
+    /// The token for all of the inputs: `{}` unless `-I` gives another
+    pub token: &'a str,
+    pub inputs: &'a [S],
+    /// The names of the inputs, in order, for `{name}` placeholders
+    pub fields: &'a [String],
+    pub seq: usize,
+    /// Shell-quote each input, so it can't inject commands into a script
+    pub quote: bool,
+}
+
+impl<S: AsRef<OsStr>> Values<'_, S> {
+    /// Replaces every placeholder in `template`, or returns `None` if there
+    /// aren't any
+    pub fn expand(&self, template: &str) -> Option<OsString> {
+        let mut expanded = OsString::new();
+        let mut rest = template;
+        let mut replaced = false;
+        while let Some((start, len, placeholder)) = find(rest, self.token, self.fields) {
+            expanded.push(&rest[..start]);
+            expanded.push(self.value(placeholder));
+            rest = &rest[start + len..];
+            replaced = true;
+        }
+        expanded.push(rest);
+        replaced.then_some(expanded)
+    }
+
+    /// All of the inputs, (quoted and) separated by spaces
+    pub fn all(&self) -> OsString {
+        self.join(self.inputs.iter().map(AsRef::as_ref))
+    }
+
+    fn value(&self, placeholder: Placeholder) -> OsString {
+        let paths = self.inputs.iter().map(|input| Path::new(input.as_ref()));
+        match placeholder {
+            Placeholder::All => self.all(),
+            Placeholder::Field(n) => self.join(
+                n.checked_sub(1)
+                    .and_then(|i| self.inputs.get(i))
+                    .map(AsRef::as_ref),
+            ),
+            Placeholder::Stem => {
+                self.join(paths.map(|path| path.with_extension("").into_os_string()))
+            }
+            Placeholder::Basename => {
+                self.join(paths.map(|path| path.file_name().unwrap_or(path.as_os_str())))
+            }
+            Placeholder::Dirname => self.join(paths.map(|path| match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.as_os_str(),
+                _ => OsStr::new("."),
+            })),
+            Placeholder::Seq => self.seq.to_string().into(),
+        }
+    }
+
+    fn join<T: AsRef<OsStr>>(&self, values: impl IntoIterator<Item = T>) -> OsString {
+        let mut joined = OsString::new();
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                joined.push(" ");
+            }
+            if self.quote {
+                joined.push(shell::quote_os(value.as_ref()));
+            } else {
+                joined.push(value);
+            }
+        }
+        joined
+    }
+}
+
+/// Finds the first placeholder in `s`, returning where it starts, its length
+/// and which it is. `{name}` is the same as `{n}` for the `n`th of `fields`.
+fn find(s: &str, token: &str, fields: &[String]) -> Option<(usize, usize, Placeholder)> {
+    s.char_indices().find_map(|(start, _)| {
+        let rest = &s[start..];
+        if rest.starts_with(token) {
+            return Some((start, token.len(), Placeholder::All));
+        }
+        let fixed = [
+            ("{.}", Placeholder::Stem),
+            ("{/}", Placeholder::Basename),
+            ("{//}", Placeholder::Dirname),
+            ("{#}", Placeholder::Seq),
+        ];
+        if let Some((name, placeholder)) = fixed.iter().find(|(name, _)| rest.starts_with(name)) {
+            return Some((start, name.len(), *placeholder));
+        }
+        let name = rest.strip_prefix('{')?;
+        let len = name.find(|c: char| !c.is_ascii_digit())?;
+        if let Ok(n) = name[..len].parse() {
+            if name[len..].starts_with('}') {
+                return Some((start, len + 2, Placeholder::Field(n)));
+            }
+        }
+        fields.iter().enumerate().find_map(|(i, field)| {
+            let named = name.strip_prefix(field.as_str())?.starts_with('}');
+            named.then_some((start, field.len() + 2, Placeholder::Field(i + 1)))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str, inputs: &[&str], quote: bool) -> Option<OsString> {
+        Values {
+            token: "{}",
+            inputs,
+            fields: &[],
+            seq: 7,
+            quote,
+        }
+        .expand(template)
+    }
+
+    #[test]
+    fn placeholders() {
+        let inputs = ["dir/a.tar.gz", "b c.jpg"];
+        assert_eq!(
+            expand("{}", &inputs, false).unwrap(),
+            "dir/a.tar.gz b c.jpg"
+        );
+        assert_eq!(
+            expand("{2}:{1}", &inputs, false).unwrap(),
+            "b c.jpg:dir/a.tar.gz"
+        );
+        assert_eq!(expand("{.}", &inputs, false).unwrap(), "dir/a.tar b c");
+        assert_eq!(expand("{/}", &inputs, false).unwrap(), "a.tar.gz b c.jpg");
+        assert_eq!(expand("{//}", &inputs, false).unwrap(), "dir .");
+        assert_eq!(expand("out-{#}.log", &inputs, false).unwrap(), "out-7.log");
+        assert_eq!(expand("{3}{0}", &inputs, false).unwrap(), "");
+    }
+
+    #[test]
+    fn quoted() {
+        let inputs = ["dir/$(reboot).jpg", "b c"];
+        assert_eq!(
+            expand("mv {1} {//}/{#}", &inputs, true).unwrap(),
+            "mv 'dir/$(reboot).jpg' dir ./7"
+        );
+    }
+
+    #[test]
+    fn not_placeholders() {
+        assert_eq!(expand("{x} {1 {-1} { } {", &["a"], false), None);
+        assert_eq!(expand("${HOME}", &["a"], false), None);
+        let values = Values {
+            token: "%%",
+            inputs: &["a"],
+            fields: &[],
+            seq: 1,
+            quote: false,
+        };
+        assert_eq!(values.expand("cp %% {}").unwrap(), "cp a {}");
+    }
+
+    #[test]
+    fn named_fields() {
+        let fields = ["path".to_string(), "id".to_string(), "7".to_string()];
+        let values = Values {
+            token: "{}",
+            inputs: &["a.txt", "3", "x"],
+            fields: &fields,
+            seq: 1,
+            quote: false,
+        };
+        assert_eq!(
+            values.expand("{path}.{id} {2} {7} {{id}}").unwrap(),
+            "a.txt.3 3  {3}"
+        );
+        assert_eq!(values.expand("{pat} {path "), None);
+    }
+}
@@ -0,0 +1,159 @@
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+use crate::signals::{self, ChildExits};
+use crate::Options;
+
+/// The most jobs to start in a period of time, parsed from `--rate` as `N`
+/// per second, or `N/s`, `N/m` or `N/h` (also `sec`, `min` and `hour`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    /// How long to leave between starting jobs
+    pub interval: Duration,
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (count, period) = value.split_once('/').unwrap_or((value, "s"));
+        let period: u32 = match period {
+            "s" | "sec" => 1,
+            "m" | "min" => 60,
+            "h" | "hour" => 3600,
+            _ => return Err(format!("unknown period `{period}`, expected s, m or h")),
+        };
+        count
+            .parse::<f64>()
+            .ok()
+            .filter(|count| *count > 0.0)
+            .and_then(|count| Duration::try_from_secs_f64(f64::from(period) / count).ok())
+            .map(|interval| Self { interval })
+            .ok_or_else(|| format!("`{count}` is not a valid number of jobs"))
+    }
+}
+
+/// How long to wait before checking again whether the system is still busy
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Holds off starting jobs: spacing them out for `--delay` and `--rate`, and
+/// while the system is busy for `--load-max` and `--memfree`. Only the first
+/// attempt of each job counts, since retries have their own `--retry-delay`.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    next: Option<Instant>,
+    load_max: Option<f64>,
+    memfree: Option<u64>,
+}
+
+impl Throttle {
+    pub fn new(options: &Options) -> Self {
+        let rate = options.rate.map(|rate| rate.interval).unwrap_or_default();
+        Self {
+            interval: options.delay.max(rate),
+            next: None,
+            load_max: options.load_max,
+            memfree: options.memfree.map(|bytes| bytes as u64),
+        }
+    }
+
+    /// When the next job may start (or whether the system is still busy
+    /// should be checked again), if that isn't yet
+    pub fn ready_at(&self) -> Option<Instant> {
+        let now = Instant::now();
+        self.next
+            .filter(|&next| next > now)
+            .or_else(|| self.system_busy().then(|| now + RECHECK_INTERVAL))
+    }
+
+    /// Whether the load average is at least `--load-max`, or there's less
+    /// than `--memfree` memory available. Metrics that can't be read don't
+    /// hold anything up.
+    fn system_busy(&self) -> bool {
+        let loaded = self
+            .load_max
+            .is_some_and(|max| metrics::load_average().is_some_and(|load| load >= max));
+        let low_memory = self.memfree.is_some_and(|floor| {
+            metrics::available_memory().is_some_and(|available| available < floor)
+        });
+        loaded || low_memory
+    }
+
+    /// Blocks until the next job may start, or a termination signal is caught
+    ///
+    /// # Errors
+    /// Will return an error if waiting can't be set up
+    pub fn wait(&self) -> io::Result<()> {
+        if self.ready_at().is_none() {
+            return Ok(());
+        }
+        let exits = ChildExits::new()?;
+        while let Some(ready) = self.ready_at() {
+            if signals::received().is_some() {
+                break;
+            }
+            exits.wait(Some(ready));
+        }
+        Ok(())
+    }
+
+    /// Records that a job has just started
+    pub fn started(&mut self) {
+        if !self.interval.is_zero() {
+            self.next = Some(Instant::now() + self.interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rate() {
+        let interval = |value: &str| value.parse::<Rate>().map(|rate| rate.interval);
+        assert_eq!(interval("4"), Ok(Duration::from_millis(250)));
+        assert_eq!(interval("2/sec"), Ok(Duration::from_millis(500)));
+        assert_eq!(interval("30/m"), Ok(Duration::from_secs(2)));
+        assert_eq!(interval("0.5/hour"), Ok(Duration::from_secs(7200)));
+        assert!(interval("0").is_err());
+        assert!(interval("-1/s").is_err());
+        assert!(interval("10/day").is_err());
+    }
+
+    #[test]
+    fn spaces_out_starts() {
+        let options = Options {
+            delay: Duration::from_millis(50),
+            rate: "100".parse().ok(),
+            ..Default::default()
+        };
+        let mut throttle = Throttle::new(&options);
+        assert_eq!(throttle.ready_at(), None);
+        let start = Instant::now();
+        throttle.started();
+        assert!(throttle.ready_at().is_some());
+        throttle.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(throttle.ready_at(), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn waits_for_memory() {
+        let options = Options {
+            memfree: Some(usize::MAX),
+            ..Default::default()
+        };
+        assert!(Throttle::new(&options).ready_at().is_some());
+        let options = Options {
+            memfree: Some(1),
+            load_max: Some(f64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(Throttle::new(&options).ready_at(), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Timing statistics for every run of the program, printed at the end with
+//! `--time`, for finding the slow outliers among many jobs
+
+use std::ffi::OsString;
+use std::fmt;
+use std::time::Duration;
+
+use crate::platform::CpuTime;
+use crate::shell;
+
+/// Number of runs listed under "Slowest runs"
+const SLOWEST_RUNS: usize = 5;
+
+/// Number of bars in the histogram of wall-clock times
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// The length of the histogram's longest bar
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Nearest-rank percentile of already sorted values
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug)]
+struct Run {
+    command: Vec<OsString>,
+    wall: Duration,
+    cpu_time: Option<CpuTime>,
+}
+
+/// The wall-clock time of each run, and the CPU time where it's available
+#[derive(Debug, Default)]
+pub struct Timings {
+    runs: Vec<Run>,
+}
+
+impl Timings {
+    pub fn add(&mut self, command: &[OsString], wall: Duration, cpu_time: Option<CpuTime>) {
+        self.runs.push(Run {
+            command: command.to_vec(),
+            wall,
+            cpu_time,
+        });
+    }
+
+    /// One row of statistics: min, p50, p95, max and total seconds
+    fn row(f: &mut fmt::Formatter<'_>, label: &str, mut seconds: Vec<f64>) -> fmt::Result {
+        if seconds.is_empty() {
+            return Ok(());
+        }
+        seconds.sort_by(f64::total_cmp);
+        write!(f, "    {label:<4}")?;
+        for (label, p) in [("min", 0.0), ("p50", 0.5), ("p95", 0.95), ("max", 1.0)] {
+            write!(f, "  {label} {:.3}s", percentile(&seconds, p))?;
+        }
+        writeln!(f, "  total {:.3}s", seconds.iter().sum::<f64>())
+    }
+
+    /// How many runs took how long, in equal ranges from the quickest to the
+    /// slowest
+    fn histogram(&self) -> Vec<(f64, f64, usize)> {
+        let seconds: Vec<f64> = self.runs.iter().map(|run| run.wall.as_secs_f64()).collect();
+        let min = seconds.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = seconds.iter().copied().fold(0.0, f64::max);
+        let buckets = if max > min { HISTOGRAM_BUCKETS } else { 1 };
+        let width = (max - min) / buckets as f64;
+        let mut counts = vec![0; buckets];
+        for seconds in seconds {
+            let bucket = if width > 0.0 {
+                ((seconds - min) / width) as usize
+            } else {
+                0
+            };
+            counts[bucket.min(buckets - 1)] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let start = min + width * i as f64;
+                (start, start + width, count)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Times for {} runs:", self.runs.len())?;
+        if self.runs.is_empty() {
+            return Ok(());
+        }
+        let seconds = |time: fn(&Run) -> Option<Duration>| -> Vec<f64> {
+            self.runs
+                .iter()
+                .filter_map(time)
+                .map(|time| time.as_secs_f64())
+                .collect()
+        };
+        Self::row(f, "wall", seconds(|run| Some(run.wall)))?;
+        Self::row(f, "user", seconds(|run| Some(run.cpu_time?.user)))?;
+        Self::row(f, "sys", seconds(|run| Some(run.cpu_time?.system)))?;
+
+        writeln!(f, "\nWall-clock times:")?;
+        let histogram = self.histogram();
+        let most = histogram
+            .iter()
+            .map(|&(_, _, count)| count)
+            .max()
+            .unwrap_or(1);
+        for (start, end, count) in histogram {
+            let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(most));
+            let line = format!("    {start:>9.3}s - {end:>9.3}s  {count:>6}  {bar}");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        writeln!(f, "\nSlowest runs:")?;
+        let mut slowest: Vec<&Run> = self.runs.iter().collect();
+        slowest.sort_by_key(|run| std::cmp::Reverse(run.wall));
+        for run in slowest.into_iter().take(SLOWEST_RUNS) {
+            writeln!(
+                f,
+                "    {:>9.3}s  {}",
+                run.wall.as_secs_f64(),
+                shell::join(&run.command)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&values, 0.5), 5.0);
+        assert_eq!(percentile(&values, 0.9), 9.0);
+        assert_eq!(percentile(&values, 1.0), 10.0);
+        assert_eq!(percentile(&[3.0], 0.5), 3.0);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut timings = Timings::default();
+        for ms in 1..=100 {
+            let cpu_time = CpuTime {
+                user: Duration::from_millis(ms / 2),
+                system: Duration::from_millis(1),
+            };
+            timings.add(
+                &["sleep".into(), ms.to_string().into()],
+                Duration::from_millis(ms),
+                (ms != 50).then_some(cpu_time),
+            );
+        }
+        let output = timings.to_string();
+        assert!(output.starts_with("Times for 100 runs:\n"), "{output}");
+        assert!(
+            output.contains(
+                "    wall  min 0.001s  p50 0.050s  p95 0.095s  max 0.100s  total 5.050s\n"
+            ),
+            "{output}"
+        );
+        assert!(output.contains("    sys   min 0.001s"), "{output}");
+        assert!(output.contains("total 0.099s\n"), "{output}");
+        assert!(
+            output.contains("        0.001s -     0.011s      10  ####"),
+            "{output}"
+        );
+        assert!(
+            output.ends_with("Slowest runs:\n        0.100s  sleep 100\n        0.099s  sleep 99\n        0.098s  sleep 98\n        0.097s  sleep 97\n        0.096s  sleep 96\n"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn one_bucket_when_all_runs_take_as_long() {
+        let mut timings = Timings::default();
+        timings.add(&["true".into()], Duration::from_secs(1), None);
+        timings.add(&["true".into()], Duration::from_secs(1), None);
+        assert_eq!(timings.histogram(), [(1.0, 1.0, 2)]);
+        assert!(!timings.to_string().contains("user"));
+    }
+}